@@ -0,0 +1,156 @@
+//! offline analysis of a WAV file with the same spectrum pipeline as the live view, one line of
+//! band levels per frame, meant for scripts and regression checks
+use crate::equalizer::dsp::downmix;
+use crate::equalizer::dsp::pipeline::{Pipeline, PipelineSpec};
+use crate::equalizer::dsp::spectrum::{Analyzer, Frame, Settings, MIN_DB};
+use crate::equalizer::wav;
+use crate::errors::Error;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+const BLOCK: usize = 16384; // frames read from the file at a time
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Format {
+    Csv,
+    JsonLines, // one JSON object per line, also known as NDJSON
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "csv" => Ok(Format::Csv),
+            "jsonl" | "ndjson" => Ok(Format::JsonLines),
+            _ => Err(format!(
+                "Unknown format {}, expected one of csv, jsonl, ndjson",
+                s
+            )),
+        }
+    }
+}
+
+/// analyzes the downmix of all channels of the input, writes to the output file or stdout; the
+/// file is read and every frame written as it goes, long files do not need to fit in memory
+pub fn run(
+    input: &Path,
+    output: Option<&Path>,
+    format: Format,
+    settings: Settings,
+    pipeline: Option<&PipelineSpec>,
+) -> Result<(), Error> {
+    settings.validate().map_err(Error::Config)?;
+    let mut blocks = wav::Blocks::open(input)?;
+    let (channels, sample_rate) = (blocks.channels(), blocks.sample_rate());
+    let mut analyzer = match pipeline {
+        Some(spec) => Analyzer::with_pipeline(
+            settings,
+            sample_rate,
+            Pipeline::from_spec(spec, &settings, sample_rate)?,
+        )?,
+        None => Analyzer::new(settings, sample_rate),
+    };
+
+    let stdout = io::stdout();
+    let mut out: Box<dyn Write> = match output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(BufWriter::new(stdout.lock())),
+    };
+
+    if format == Format::Csv {
+        writeln!(out, "{}", csv_header(&analyzer.bands()))?;
+    }
+    let mut block = Vec::with_capacity(BLOCK * channels);
+    let mut sequence = 0;
+    while blocks.read(&mut block, BLOCK * channels)? > 0 {
+        for frame in analyzer.feed(&downmix(&block, channels)).iter() {
+            let line = match format {
+                Format::Csv => csv_line(frame),
+                Format::JsonLines => json_line(sequence, frame),
+            };
+            writeln!(out, "{}", line)?;
+            sequence += 1;
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+// band columns are named by their edges in Hz
+fn csv_header(bands: &[(f32, f32)]) -> String {
+    let mut header = String::from("time");
+    for (lower, upper) in bands {
+        header.push_str(&format!(",{:.1}-{:.1}", lower, upper));
+    }
+    header
+}
+
+fn csv_line(frame: &Frame) -> String {
+    let mut line = format!("{:.6}", frame.time);
+    for level in frame.levels.iter() {
+        line.push_str(&format!(",{:.2}", level));
+    }
+    line
+}
+
+fn json_line(sequence: usize, frame: &Frame) -> String {
+    let levels: Vec<String> = frame.levels.iter().map(|l| json_level(*l)).collect();
+    format!(
+        "{{\"sequence\":{},\"time\":{:.6},\"levels\":[{}]}}",
+        sequence,
+        frame.time,
+        levels.join(",")
+    )
+}
+
+// JSON has no infinities: silence goes to the floor, anything else that is not a number is null
+fn json_level(level: f32) -> String {
+    if level.is_finite() {
+        format!("{:.2}", level)
+    } else if level == f32::NEG_INFINITY {
+        format!("{:.2}", MIN_DB)
+    } else {
+        String::from("null")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame() -> Frame {
+        Frame {
+            time: 0.0464,
+            levels: vec![-3.012, -120.],
+        }
+    }
+
+    #[test]
+    fn formats_csv() {
+        assert_eq!(
+            csv_header(&[(0., 11025.), (11025., 22050.)]),
+            "time,0.0-11025.0,11025.0-22050.0"
+        );
+        assert_eq!(csv_line(&frame()), "0.046400,-3.01,-120.00");
+    }
+
+    #[test]
+    fn formats_json_lines() {
+        assert_eq!(
+            json_line(3, &frame()),
+            "{\"sequence\":3,\"time\":0.046400,\"levels\":[-3.01,-120.00]}"
+        );
+        assert_eq!("ndjson".parse::<Format>(), Ok(Format::JsonLines));
+        let silent = Frame {
+            time: 0.,
+            levels: vec![f32::NEG_INFINITY, f32::NAN, f32::INFINITY],
+        };
+        assert_eq!(
+            json_line(0, &silent),
+            "{\"sequence\":0,\"time\":0.000000,\"levels\":[-120.00,null,null]}"
+        );
+    }
+}
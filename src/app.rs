@@ -1,14 +1,23 @@
 use gio::prelude::*;
 use gtk::prelude::*;
 
+mod chroma;
+mod equalizer;
 mod graph;
+mod replay;
+mod scope;
+mod tuner;
+mod vectorscope;
 
+use crate::equalizer::device::DeviceStatus;
+use crate::equalizer::dsp::spectrum::{band_label, Band};
+use crate::equalizer::session::Player;
 use crate::equalizer::Equalizer;
+use crate::errors::Error;
+use crate::shutdown;
 use gtk::{Application, ApplicationWindow, Box, Frame, Label};
 use std::cell::RefCell;
 use std::rc::Rc;
-use std::thread;
-use std::time::Duration;
 
 // TODO: add config file with configs?
 const UPDATE_TIMEOUT: u64 = 50; // ms
@@ -32,41 +41,56 @@ impl GuiApp {
         }
     }
 
-    fn setup_timeout(equalizer: &Rc<RefCell<Equalizer>>, graph: &Rc<RefCell<graph::Graph>>) {
+    fn setup_timeout(
+        equalizer: &Rc<RefCell<Equalizer>>,
+        graph: &Rc<RefCell<graph::Graph>>,
+        scope: &Rc<RefCell<scope::ScopeView>>,
+        vectorscope: &Rc<RefCell<vectorscope::Vectorscope>>,
+        tuner: &Rc<RefCell<tuner::TunerPanel>>,
+        chroma: &Rc<RefCell<chroma::ChromaView>>,
+        labels: &gtk::Box,
+    ) {
         // TODO: big refactor once it works, make it all generic properly!
-        // new thread for updating feeding graph with data obtained from equalizer
-        let (ready_tx, ready_rx) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
-
-        thread::spawn(clone!(@strong ready_tx => move || loop {
-            let sleep_duration = Duration::from_millis(UPDATE_TIMEOUT);
-            thread::sleep(sleep_duration);
-            ready_tx
-                .send(false)
-                .expect("Failed to send data through graph refresh channel");
-        }));
+        // feeds the views on the main loop, it ends with it
+        let mut shown = equalizer.borrow().bands();
 
-        ready_rx.attach(
-            None,
-            clone!(@strong graph, @weak equalizer => @default-panic, move |_: bool| { // TODO: I am not sure I understand why 'weak' graph failed and 'strong' is ok
+        glib::timeout_add_local(
+            UPDATE_TIMEOUT as u32,
+            clone!(@strong graph, @strong scope, @strong vectorscope, @strong tuner, @strong chroma, @weak labels, @weak equalizer => @default-return glib::Continue(false), move || { // TODO: I am not sure I understand why 'weak' graph failed and 'strong' is ok
                 info!("Receiving data from equalizer for graph");
                 // Test FFT workings and why it hangs here after uncommenting equalizer code
                 // Rudimentary graph drawing and updating
                 // Understand WTF is going on with these references and cloning
                 //
                 if let Some(payload) = equalizer.borrow().get_processed_samples() {
-                    // This is raw FFT, could be formatted better -> 20 frequency bins and unwrap
-                    // from cell
-                    if let Err(err) = graph.borrow_mut().push(payload) {
+                    // the spectrum controls can change the bands
+                    if *payload.bands != shown[..] {
+                        shown = payload.bands.to_vec();
+                        labels.foreach(|label| labels.remove(label));
+                        GuiApp::add_labels(&labels, &shown);
+                        labels.show_all();
+                    }
+                    let mut graph = graph.borrow_mut();
+                    graph.set_equalized(equalizer.borrow().get_equalized_spectrum());
+                    if let Err(err) = graph.push(payload) {
                         error!("{}", err);
                     }
                 }
+                if let Some(waveform) = equalizer.borrow().get_waveform() {
+                    scope.borrow_mut().push(waveform);
+                }
+                if let Some(image) = equalizer.borrow().get_stereo_image() {
+                    vectorscope.borrow_mut().push(image);
+                }
+                tuner.borrow_mut().push(equalizer.borrow().get_pitch());
+                chroma.borrow_mut().push(equalizer.borrow().get_chromagram());
                 glib::Continue(true)
             }),
         );
     }
 
-    // This builds the general UI of the application (for now also the main UI - equalizer graph)
-    pub fn build_ui(&self, equalizer: Rc<RefCell<Equalizer>>, bins: Option<usize>) -> () {
+    /// This builds the general UI of the application (for now also the main UI - equalizer graph)
+    pub fn build_ui(&self, equalizer: Rc<RefCell<Equalizer>>) -> () {
         self.application.connect_activate(move |app| {
             let window = gtk::ApplicationWindow::new(app);
 
@@ -87,29 +111,161 @@ impl GuiApp {
             window.set_title("Rustalizer"); // lifetime issues with closures, TODO: fix this
             window.set_default_size(XSIZE, YSIZE + 50);
 
-            let bins_clone = bins.clone();
-            let equalizer_graph =
-                graph::Graph::new(XSIZE - 2 * XMARGIN, YSIZE - 2 * YMARGIN, bins_clone);
+            let equalizer_graph = graph::Graph::new(XSIZE - 2 * XMARGIN, YSIZE - 2 * YMARGIN);
             // connect refreshing context to gtk
             equalizer_graph.attach_to(&vertical_layout);
             // share out the graph object, now it is Rc
             let equalizer_graph = GuiApp::connect_graph(equalizer_graph);
 
             // add frequency labels
-            let horizontal_layout = gtk::Box::new(
-                gtk::Orientation::Horizontal,
-                XSIZE / bins_clone.unwrap_or(31) as i32,
-            );
-            GuiApp::add_labels(&horizontal_layout, XSIZE, bins_clone);
+            let horizontal_layout = gtk::Box::new(gtk::Orientation::Horizontal, 0);
+            GuiApp::add_labels(&horizontal_layout, &equalizer.borrow().bands());
             vertical_layout.pack_start(&horizontal_layout, true, true, 0);
 
-            GuiApp::setup_timeout(&equalizer, &equalizer_graph);
-            window.add(&vertical_layout);
+            let post_eq = gtk::CheckButton::with_label("Show post-EQ spectrum");
+            post_eq.set_active(equalizer.borrow().post_eq());
+            post_eq.connect_toggled(clone!(@weak equalizer => move |button| {
+                equalizer.borrow().set_post_eq(button.get_active());
+            }));
+            vertical_layout.pack_start(&post_eq, false, false, 0);
+            vertical_layout.pack_start(&GuiApp::spectrum_controls(&equalizer), false, false, 0);
+
+            let record = gtk::ToggleButton::with_label("Record");
+            record.set_active(equalizer.borrow().recording());
+            record.connect_toggled(clone!(@weak equalizer => move |button| {
+                equalizer.borrow().set_recording(button.get_active());
+            }));
+            vertical_layout.pack_start(&record, false, false, 0);
+
+            let session = gtk::ToggleButton::with_label("Record session");
+            session.set_active(equalizer.borrow().session_recording());
+            session.connect_toggled(clone!(@weak equalizer => move |button| {
+                let equalizer = equalizer.borrow();
+                let result = if button.get_active() {
+                    equalizer.start_session().map(|_| ())
+                } else {
+                    equalizer.stop_session()
+                };
+                if let Err(err) = result {
+                    error!("Session recording failed: {}", err);
+                }
+            }));
+            vertical_layout.pack_start(&session, false, false, 0);
+
+            // empty unless the input device is not the one asked for or is gone
+            let device = gtk::Label::new(None);
+            vertical_layout.pack_start(&device, false, false, 0);
+
+            // every view gets its own tab
+            let notebook = gtk::Notebook::new();
+            notebook.append_page(&vertical_layout, Some(&gtk::Label::new(Some("Spectrum"))));
+
+            let scope_view = scope::ScopeView::new(
+                XSIZE - 2 * XMARGIN,
+                YSIZE - 2 * YMARGIN,
+                equalizer.borrow().scope(),
+            );
+            notebook.append_page(scope_view.widget(), Some(&gtk::Label::new(Some("Scope"))));
+            let scope_view = GuiApp::connect_scope(scope_view);
+
+            let vectorscope =
+                vectorscope::Vectorscope::new(XSIZE - 2 * XMARGIN, YSIZE - 2 * YMARGIN);
+            notebook.append_page(
+                &vectorscope.area,
+                Some(&gtk::Label::new(Some("Vectorscope"))),
+            );
+            let vectorscope = GuiApp::connect_vectorscope(vectorscope);
+
+            let tuner = tuner::TunerPanel::new(
+                XSIZE - 2 * XMARGIN,
+                YSIZE - 2 * YMARGIN,
+                equalizer.borrow().tuner(),
+            );
+            notebook.append_page(tuner.widget(), Some(&gtk::Label::new(Some("Tuner"))));
+            let tuner = GuiApp::connect_tuner(tuner);
+
+            let chroma = chroma::ChromaView::new(XSIZE - 2 * XMARGIN, YSIZE - 2 * YMARGIN);
+            notebook.append_page(&chroma.area, Some(&gtk::Label::new(Some("Chroma"))));
+            let chroma = GuiApp::connect_chroma(chroma);
+
+            let equalizer_panel = equalizer::EqualizerPanel::new(
+                equalizer.borrow().filters(),
+                equalizer.borrow().latency(),
+            );
+            notebook.append_page(
+                equalizer_panel.widget(),
+                Some(&gtk::Label::new(Some("Equalizer"))),
+            );
+
+            GuiApp::setup_timeout(
+                &equalizer,
+                &equalizer_graph,
+                &scope_view,
+                &vectorscope,
+                &tuner,
+                &chroma,
+                &horizontal_layout,
+            );
+            GuiApp::watch_shutdown(
+                app,
+                clone!(@weak equalizer, @weak device => @default-return Ok(()), move || {
+                    let mut equalizer = equalizer.borrow_mut();
+                    equalizer.supervise();
+                    let status = match equalizer.get_device_status() {
+                        Some(DeviceStatus::Connected { fallback: false, .. }) | None => String::new(),
+                        Some(status) => status.to_string(),
+                    };
+                    device.set_text(&status);
+                    equalizer.check()
+                }),
+            );
+            window.add(&notebook);
 
             window.show_all();
         });
     }
 
+    /// a window with only the spectrum of a recorded session, nothing is captured
+    pub fn build_replay(&self, player: Player) {
+        let player = RefCell::new(Some(player));
+        self.application.connect_activate(move |app| {
+            let player = match player.borrow_mut().take() {
+                Some(player) => player,
+                None => return,
+            };
+            let window = gtk::ApplicationWindow::new(app);
+            window.set_title("Rustalizer | replay");
+            window.set_default_size(800, 650);
+            let view = replay::ReplayView::new(790, 580, player, UPDATE_TIMEOUT as u32);
+            window.add(view.widget());
+            GuiApp::watch_shutdown(app, || Ok(()));
+            window.show_all();
+        });
+    }
+
+    // runs check on every update; Ctrl-C, SIGTERM and a failed check quit the application like
+    // closing its window does, main then stops the streams and joins the threads
+    fn watch_shutdown<F>(app: &gtk::Application, check: F)
+    where
+        F: Fn() -> Result<(), Error> + 'static,
+    {
+        glib::timeout_add_local(
+            UPDATE_TIMEOUT as u32,
+            clone!(@weak app => @default-return glib::Continue(false), move || {
+                let result = check();
+                if let Err(err) = &result {
+                    error!("{}", err);
+                }
+                if shutdown::requested() || result.is_err() {
+                    app.quit();
+                    glib::Continue(false)
+                } else {
+                    glib::Continue(true)
+                }
+            }),
+        );
+    }
+
     pub fn run(&self) -> () {
         glib::set_application_name("rustalizer");
         self.application.run(&[]);
@@ -131,26 +287,153 @@ impl GuiApp {
         graph
     }
 
-    fn add_labels(layout: &gtk::Box, width: i32, bins: Option<usize>) {
-        match bins {
-            Some(bin_nr) => {
-                for i in 0..bin_nr {
-                    let label = gtk::Label::new(Some(&(i.to_string() + " kHz"))); // TODO: they are not kHz, could probably make one place for such static stuff
-                    layout.pack_start(&label, true, true, 0);
-                }
+    fn connect_scope(scope: scope::ScopeView) -> Rc<RefCell<scope::ScopeView>> {
+        let area = scope.area.clone();
+        let scope = Rc::new(RefCell::new(scope));
+        area.connect_draw(
+            clone!(@weak scope => @default-return gtk::Inhibit(false), move |w, c| {
+                scope.borrow().draw(
+                    c,
+                    f64::from(w.get_allocated_width()),
+                    f64::from(w.get_allocated_height()),
+                );
+                gtk::Inhibit(false)
+            }),
+        );
+        scope
+    }
+
+    fn connect_vectorscope(
+        vectorscope: vectorscope::Vectorscope,
+    ) -> Rc<RefCell<vectorscope::Vectorscope>> {
+        let area = vectorscope.area.clone();
+        let vectorscope = Rc::new(RefCell::new(vectorscope));
+        area.connect_draw(
+            clone!(@weak vectorscope => @default-return gtk::Inhibit(false), move |w, c| {
+                vectorscope.borrow().draw(
+                    c,
+                    f64::from(w.get_allocated_width()),
+                    f64::from(w.get_allocated_height()),
+                );
+                gtk::Inhibit(false)
+            }),
+        );
+        vectorscope
+    }
+
+    fn connect_tuner(tuner: tuner::TunerPanel) -> Rc<RefCell<tuner::TunerPanel>> {
+        let area = tuner.area.clone();
+        let tuner = Rc::new(RefCell::new(tuner));
+        area.connect_draw(
+            clone!(@weak tuner => @default-return gtk::Inhibit(false), move |w, c| {
+                tuner.borrow().draw(
+                    c,
+                    f64::from(w.get_allocated_width()),
+                    f64::from(w.get_allocated_height()),
+                );
+                gtk::Inhibit(false)
+            }),
+        );
+        tuner
+    }
+
+    fn connect_chroma(chroma: chroma::ChromaView) -> Rc<RefCell<chroma::ChromaView>> {
+        let area = chroma.area.clone();
+        let chroma = Rc::new(RefCell::new(chroma));
+        area.connect_draw(
+            clone!(@weak chroma => @default-return gtk::Inhibit(false), move |w, c| {
+                chroma.borrow().draw(
+                    c,
+                    f64::from(w.get_allocated_width()),
+                    f64::from(w.get_allocated_height()),
+                );
+                gtk::Inhibit(false)
+            }),
+        );
+        chroma
+    }
+
+    // window, FFT size, weighting, bins and smoothing, changed while the capture runs
+    fn spectrum_controls(equalizer: &Rc<RefCell<Equalizer>>) -> gtk::Box {
+        let settings = equalizer.borrow().settings();
+        let controls = gtk::Box::new(gtk::Orientation::Horizontal, 5);
+
+        let window = gtk::ComboBoxText::new();
+        for name in &["hann", "hamming", "blackman", "rectangular"] {
+            window.append(Some(name), name);
+        }
+        window.set_active_id(Some(&format!("{:?}", settings.window).to_lowercase()));
+        window.connect_changed(clone!(@weak equalizer => move |combo| {
+            if let Some(window) = combo.get_active_id().and_then(|id| id.parse().ok()) {
+                report(equalizer.borrow().set_window(window));
             }
-            None => {
-                let vals = vec![
-                    "20 Hz", "25 Hz", "31 Hz", "40 Hz", "50 Hz", "63 Hz", "80 Hz", "100 Hz",
-                    "125 Hz", "160 Hz", "200 Hz", "250 Hz", "315 Hz", "400 Hz", "500 Hz", "630 Hz",
-                    "800 Hz", "1 kHz", "1.2 kHz", "1.6 kHz", "2 kHz", "2.5 kHz", "3.1 kHz",
-                    "4 kHz", "5 kHz", "6.3 kHz", "8 kHz", "10 kHz", "12 kHz", "16 kHz", "20 kHz",
-                ];
-                for i in 0..vals.len() {
-                    let label = gtk::Label::new(Some(vals[i]));
-                    layout.pack_start(&label, true, true, 0);
-                }
+        }));
+
+        let fft_size = gtk::ComboBoxText::new();
+        for size in (8..=14).map(|power| 1usize << power) {
+            let size = size.to_string();
+            fft_size.append(Some(&size), &size);
+        }
+        fft_size.set_active_id(Some(&settings.fft_size.to_string()));
+        fft_size.connect_changed(clone!(@weak equalizer => move |combo| {
+            if let Some(size) = combo.get_active_id().and_then(|id| id.parse().ok()) {
+                report(equalizer.borrow().set_fft_size(size));
+            }
+        }));
+
+        let weighting = gtk::ComboBoxText::new();
+        for name in &["z", "a", "c"] {
+            weighting.append(Some(name), &name.to_uppercase());
+        }
+        weighting.set_active_id(Some(&format!("{:?}", settings.weighting).to_lowercase()));
+        weighting.connect_changed(clone!(@weak equalizer => move |combo| {
+            if let Some(weighting) = combo.get_active_id().and_then(|id| id.parse().ok()) {
+                report(equalizer.borrow().set_weighting(weighting));
             }
+        }));
+
+        // 0 for third octaves
+        let bins = gtk::SpinButton::with_range(0., 64., 1.);
+        bins.set_value(settings.bins.unwrap_or(0) as f64);
+        bins.connect_value_changed(clone!(@weak equalizer => move |spin| {
+            let bins = spin.get_value_as_int() as usize;
+            report(equalizer.borrow().set_bins(if bins == 0 { None } else { Some(bins) }));
+        }));
+
+        // 0 for none
+        let smoothing = gtk::SpinButton::with_range(0., 0.95, 0.05);
+        smoothing.set_digits(2);
+        smoothing.set_value(f64::from(settings.smoothing.unwrap_or(0.)));
+        smoothing.connect_value_changed(clone!(@weak equalizer => move |spin| {
+            let factor = spin.get_value() as f32;
+            report(equalizer.borrow().set_smoothing(if factor > 0. { Some(factor) } else { None }));
+        }));
+
+        for (name, widget) in &[
+            ("Window", window.upcast_ref::<gtk::Widget>()),
+            ("FFT size", fft_size.upcast_ref()),
+            ("Weighting", weighting.upcast_ref()),
+            ("Bins", bins.upcast_ref()),
+            ("Smoothing", smoothing.upcast_ref()),
+        ] {
+            controls.pack_start(&gtk::Label::new(Some(name)), false, false, 0);
+            controls.pack_start(*widget, false, false, 0);
         }
+        controls
+    }
+
+    // one label per band under the spectrum, spread like the bars
+    fn add_labels(layout: &gtk::Box, bands: &[Band]) {
+        layout.set_homogeneous(true);
+        for band in bands {
+            let label = gtk::Label::new(Some(&band_label(band.centre)));
+            layout.pack_start(&label, true, true, 0);
+        }
+    }
+}
+
+fn report(result: Result<(), Error>) {
+    if let Err(err) = result {
+        error!("Cannot change the spectrum: {}", err);
     }
 }
@@ -0,0 +1,71 @@
+use crate::equalizer::dsp::chroma::Chromagram;
+use crate::equalizer::dsp::tuner::NOTE_NAMES;
+
+use gtk::prelude::*;
+use gtk::DrawingArea;
+
+const LABEL_HEIGHT: f64 = 20.;
+
+/// 12 pitch class bars with the estimated key written above them
+pub struct ChromaView {
+    pub area: DrawingArea,
+    chromagram: Option<Chromagram>,
+}
+
+impl ChromaView {
+    pub fn new(width: i32, height: i32) -> ChromaView {
+        let view = ChromaView {
+            area: DrawingArea::new(),
+            chromagram: None,
+        };
+        view.area.set_size_request(width, height);
+        view
+    }
+
+    pub fn push(&mut self, chromagram: Option<Chromagram>) {
+        self.chromagram = chromagram;
+        self.area.queue_draw();
+    }
+
+    pub fn draw(&self, ctx: &cairo::Context, width: f64, height: f64) {
+        // paint background with grey, same as the spectrum graph
+        ctx.set_source_rgb(0.5, 0.5, 0.5);
+        ctx.rectangle(0., 0., width, height);
+        ctx.fill();
+
+        let x_incr = width / 12.;
+        let x_sep = 2.;
+        let bar_height = height - 2. * LABEL_HEIGHT;
+
+        ctx.set_font_size(LABEL_HEIGHT * 0.7);
+        ctx.set_source_rgb(0., 0., 0.);
+        for (i, name) in NOTE_NAMES.iter().enumerate() {
+            ctx.move_to(i as f64 * x_incr + x_incr / 3., height - 5.);
+            ctx.show_text(name);
+        }
+
+        let chromagram = match &self.chromagram {
+            Some(chromagram) => chromagram,
+            None => return,
+        };
+
+        let key = match &chromagram.key {
+            Some(key) => format!("Key: {} ({:.2})", key.name(), key.confidence),
+            None => String::from("Key: --"),
+        };
+        ctx.move_to(5., LABEL_HEIGHT - 5.);
+        ctx.show_text(&key);
+
+        ctx.set_source_rgb(0., 0., 1.0);
+        for (i, value) in chromagram.chroma.iter().enumerate() {
+            let h = f64::from(*value) * bar_height;
+            ctx.rectangle(
+                i as f64 * x_incr,
+                LABEL_HEIGHT + bar_height - h,
+                x_incr - x_sep,
+                h,
+            );
+        }
+        ctx.fill();
+    }
+}
@@ -0,0 +1,94 @@
+use crate::equalizer::dsp::biquad::FilterChain;
+use crate::equalizer::dsp::graphic::MAX_GAIN;
+
+use gtk::prelude::*;
+use gtk::Orientation;
+use std::sync::{Arc, Mutex};
+
+/// one vertical slider per graphic EQ band, with a reset button below them and the latency of
+/// the convolution, if there is one
+pub struct EqualizerPanel {
+    layout: gtk::Box,
+}
+
+impl EqualizerPanel {
+    pub fn new(filters: Arc<Mutex<FilterChain>>, latency: f32) -> EqualizerPanel {
+        let layout = gtk::Box::new(Orientation::Vertical, 5);
+        let sliders = gtk::Box::new(Orientation::Horizontal, 0);
+        sliders.set_homogeneous(true);
+
+        let bands: Vec<(f32, f32)> = match filters.lock() {
+            Ok(filters) => match filters.graphic() {
+                Some(graphic) => graphic
+                    .centres()
+                    .iter()
+                    .cloned()
+                    .zip(graphic.gains().iter().cloned())
+                    .collect(),
+                None => Vec::new(),
+            },
+            Err(_) => Vec::new(),
+        };
+
+        let mut scales = Vec::new();
+        for (band, (centre, gain)) in bands.iter().enumerate() {
+            let column = gtk::Box::new(Orientation::Vertical, 2);
+            let scale = gtk::Scale::with_range(
+                Orientation::Vertical,
+                f64::from(-MAX_GAIN),
+                f64::from(MAX_GAIN),
+                0.5,
+            );
+            scale.set_inverted(true); // boost at the top
+            scale.set_value(f64::from(*gain));
+            scale.add_mark(0., gtk::PositionType::Left, None);
+            let chain = filters.clone();
+            scale.connect_value_changed(move |scale| {
+                if let Ok(mut chain) = chain.lock() {
+                    if let Some(graphic) = chain.graphic_mut() {
+                        graphic.set_gain(band, scale.get_value() as f32);
+                    }
+                }
+            });
+            column.pack_start(&scale, true, true, 0);
+            column.pack_start(&gtk::Label::new(Some(&label(*centre))), false, false, 0);
+            sliders.pack_start(&column, true, true, 0);
+            scales.push(scale);
+        }
+
+        if bands.is_empty() {
+            let hint = gtk::Label::new(Some(
+                "No graphic EQ, start with --graphic-eq 10 or --graphic-eq 31",
+            ));
+            layout.pack_start(&hint, true, true, 0);
+        } else {
+            // the sliders write the gains back through their value-changed handlers
+            let reset = gtk::Button::with_label("Flat");
+            reset.connect_clicked(move |_| {
+                for scale in scales.iter() {
+                    scale.set_value(0.);
+                }
+            });
+            layout.pack_start(&sliders, true, true, 0);
+            layout.pack_start(&reset, false, false, 0);
+        }
+
+        if latency > 0. {
+            let text = format!("Convolution latency: {:.1} ms", latency * 1000.);
+            layout.pack_start(&gtk::Label::new(Some(&text)), false, false, 0);
+        }
+        EqualizerPanel { layout }
+    }
+
+    pub fn widget(&self) -> &gtk::Box {
+        &self.layout
+    }
+}
+
+fn label(freq: f32) -> String {
+    if freq >= 1000. {
+        format!("{}k", freq / 1000.)
+    } else {
+        format!("{}", freq)
+    }
+}
@@ -1,3 +1,5 @@
+use crate::equalizer::dsp::display_level;
+use crate::equalizer::dsp::spectrum::SpectrumFrame;
 use crate::errors::Error;
 use crate::ring_buffer::RingBuffer;
 
@@ -8,19 +10,19 @@ use gtk::{BoxExt, ContainerExt, DrawingArea, WidgetExt};
 // implement it on a static memory???
 
 pub struct Graph {
-    pub data: RingBuffer<Vec<usize>>, // a ring buffer of vectors of data
+    pub data: RingBuffer<SpectrumFrame>, // frames waiting to be drawn, the newest 16 of them
+    pub equalized: Option<SpectrumFrame>, // drawn as a line over the bars
     pub area: DrawingArea,
     horizontal_layout: gtk::Box,
-    bins: Option<usize>,
 }
 
 impl Graph {
-    pub fn new(width: i32, height: i32, bins: Option<usize>) -> Graph {
+    pub fn new(width: i32, height: i32) -> Graph {
         let g = Graph {
-            data: RingBuffer::new(16),
+            data: RingBuffer::overwriting(16),
+            equalized: None,
             area: DrawingArea::new(),
             horizontal_layout: gtk::Box::new(gtk::Orientation::Horizontal, 0),
-            bins,
         };
         g.area.set_size_request(width, height);
         g.horizontal_layout.pack_start(&g.area, true, true, 0);
@@ -32,13 +34,18 @@ impl Graph {
         to.add(&self.horizontal_layout);
     }
 
-    pub fn push(&mut self, data: Vec<usize>) -> Result<(), Error> {
-        info!("Received data");
-        self.data.push(data)?;
+    pub fn push(&mut self, frame: SpectrumFrame) -> Result<(), Error> {
+        info!("Received frame {}", frame.sequence);
+        self.data.push(frame)?;
         self.invalidate();
         Ok(())
     }
 
+    /// the post-EQ spectrum over the captured one, None hides it
+    pub fn set_equalized(&mut self, frame: Option<SpectrumFrame>) {
+        self.equalized = frame;
+    }
+
     // TODO: dirty algorithm for that
     pub fn draw(&mut self, ctx: &cairo::Context, width: f64, height: f64) {
         // paint background with grey
@@ -47,9 +54,8 @@ impl Graph {
         ctx.fill();
         ctx.set_line_width(0.5);
 
-        // Draw it 20 on 30 cells //TODO: adjust to bins number
+        // Draw it 20 on 30 cells
         // go column by column altering colours and drawing up with a magnitude
-        let x_incr = width / self.bins.unwrap_or(31) as f64; // TODO: default is 21?
         let y_incr = height / 30.;
         //dbg!(x_incr, y_incr);
         let y_sep = 1.;
@@ -58,8 +64,13 @@ impl Graph {
         let mut y_pos;
         info!("before drawing");
 
-        if let Ok(data) = self.data.pop() {
-            for i in data {
+        if let Ok(frame) = self.data.pop() {
+            let x_incr = width / frame.magnitudes.len().max(1) as f64;
+            let equalized = self
+                .equalized
+                .as_ref()
+                .filter(|equalized| equalized.bands == frame.bands);
+            for i in frame.magnitudes.iter().map(|db| display_level(*db)) {
                 let mut y_ctr; // TODO: adjust scaling
                 if i > 0 && i < 100 {
                     y_ctr = 1;
@@ -85,6 +96,27 @@ impl Graph {
                 }
                 x_pos += x_incr;
             }
+
+            // the tops of the post-EQ bars, on the same scale
+            if let Some(equalized) = equalized {
+                ctx.set_source_rgb(1.0, 0.6, 0.);
+                ctx.set_line_width(2.);
+                for (i, level) in equalized
+                    .magnitudes
+                    .iter()
+                    .map(|db| display_level(*db).min(3000))
+                    .enumerate()
+                {
+                    let x = (i as f64 + 0.5) * x_incr;
+                    let y = height - level as f64 / 100. * (y_incr + y_sep);
+                    if i == 0 {
+                        ctx.move_to(x, y);
+                    } else {
+                        ctx.line_to(x, y);
+                    }
+                }
+                ctx.stroke();
+            }
         }
         info!("after drawing");
         //self.draw_labels()
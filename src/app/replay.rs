@@ -0,0 +1,100 @@
+use super::graph::Graph;
+use super::GuiApp;
+use crate::equalizer::dsp::spectrum::band_label;
+use crate::equalizer::session::Player;
+
+use gtk::prelude::*;
+use gtk::Orientation;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Instant;
+
+const SPEEDS: [&str; 7] = ["0.125", "0.25", "0.5", "1", "2", "4", "8"];
+
+/// the spectrum of a recorded session with play/pause, a position slider and a speed selector
+pub struct ReplayView {
+    layout: gtk::Box,
+}
+
+impl ReplayView {
+    pub fn new(width: i32, height: i32, player: Player, update: u32) -> ReplayView {
+        let layout = gtk::Box::new(Orientation::Vertical, 5);
+        let header = &player.session().header;
+        let graph = Graph::new(width, height);
+        graph.attach_to(&layout);
+        let graph = GuiApp::connect_graph(graph);
+
+        let labels = gtk::Box::new(Orientation::Horizontal, 0);
+        labels.set_homogeneous(true);
+        for centre in header.centres() {
+            labels.pack_start(&gtk::Label::new(Some(&band_label(centre))), true, true, 0);
+        }
+        layout.pack_start(&labels, false, false, 0);
+
+        let duration = f64::from(player.session().duration()).max(0.1);
+        let controls = gtk::Box::new(Orientation::Horizontal, 5);
+        let play = gtk::ToggleButton::with_label("Play");
+        play.set_active(player.playing());
+        let position = gtk::Scale::with_range(Orientation::Horizontal, 0., duration, 0.1);
+        position.set_draw_value(false);
+        let time = gtk::Label::new(None);
+        let speed = gtk::ComboBoxText::new();
+        for s in SPEEDS.iter() {
+            speed.append(Some(s), &format!("x{}", s));
+        }
+        speed.set_active_id(Some("1"));
+        controls.pack_start(&play, false, false, 0);
+        controls.pack_start(&position, true, true, 0);
+        controls.pack_start(&time, false, false, 0);
+        controls.pack_start(&speed, false, false, 0);
+        layout.pack_start(&controls, false, false, 0);
+
+        let player = Rc::new(RefCell::new(player));
+        play.connect_toggled(clone!(@weak player => move |button| {
+            player.borrow_mut().set_playing(button.get_active());
+        }));
+        // only moves by the user, the timer below sets the value without seeking
+        position.connect_change_value(
+            clone!(@weak player => @default-return gtk::Inhibit(false), move |_, _, value| {
+                player.borrow_mut().seek(value as f32);
+                gtk::Inhibit(false)
+            }),
+        );
+        speed.connect_changed(clone!(@weak player => move |combo| {
+            if let Some(Ok(speed)) = combo.get_active_id().map(|id| id.parse::<f64>()) {
+                player.borrow_mut().set_speed(speed);
+            }
+        }));
+
+        let mut last = Instant::now();
+        glib::timeout_add_local(update, move || {
+            let mut player = player.borrow_mut();
+            player.advance(last.elapsed());
+            last = Instant::now();
+            if let Some(frame) = player.spectrum() {
+                if let Err(err) = graph.borrow_mut().push(frame) {
+                    error!("{}", err);
+                }
+            }
+            position.set_value(f64::from(player.position()));
+            time.set_text(&format!(
+                "{:.1} / {:.1} s",
+                player.position(),
+                player.session().duration()
+            ));
+            // stopped at the end
+            if play.get_active() != player.playing() {
+                let playing = player.playing();
+                drop(player);
+                play.set_active(playing);
+            }
+            glib::Continue(true)
+        });
+
+        ReplayView { layout }
+    }
+
+    pub fn widget(&self) -> &gtk::Box {
+        &self.layout
+    }
+}
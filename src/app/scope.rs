@@ -0,0 +1,147 @@
+use crate::equalizer::dsp::scope::{Scope, TriggerEdge};
+
+use gtk::prelude::*;
+use gtk::{DrawingArea, Orientation};
+use std::sync::{Arc, Mutex};
+
+/// Time-domain view with the trigger controls right underneath it
+pub struct ScopeView {
+    pub area: DrawingArea,
+    layout: gtk::Box,
+    timebase_label: gtk::Label,
+    samples: Vec<f32>,
+    scope: Arc<Mutex<Scope>>,
+}
+
+impl ScopeView {
+    pub fn new(width: i32, height: i32, scope: Arc<Mutex<Scope>>) -> ScopeView {
+        let view = ScopeView {
+            area: DrawingArea::new(),
+            layout: gtk::Box::new(Orientation::Vertical, 5),
+            timebase_label: gtk::Label::new(None),
+            samples: Vec::new(),
+            scope,
+        };
+        view.area.set_size_request(width, height);
+        view.layout.pack_start(&view.area, true, true, 0);
+        view.layout
+            .pack_start(&view.build_controls(), false, false, 0);
+        view.update_timebase_label();
+        view
+    }
+
+    fn build_controls(&self) -> gtk::Box {
+        let controls = gtk::Box::new(Orientation::Horizontal, 5);
+
+        let edge = gtk::ComboBoxText::new();
+        edge.append_text("Rising");
+        edge.append_text("Falling");
+        edge.set_active(Some(0));
+        let scope = self.scope.clone();
+        edge.connect_changed(move |combo| {
+            if let Ok(mut scope) = scope.lock() {
+                match combo.get_active() {
+                    Some(1) => scope.set_edge(TriggerEdge::Falling),
+                    _ => scope.set_edge(TriggerEdge::Rising),
+                }
+            }
+        });
+
+        let level = gtk::Scale::with_range(Orientation::Horizontal, -1., 1., 0.05);
+        level.set_value(0.);
+        let scope = self.scope.clone();
+        level.connect_value_changed(move |scale| {
+            if let Ok(mut scope) = scope.lock() {
+                scope.set_level(scale.get_value() as f32);
+            }
+        });
+
+        let zoom_in = gtk::Button::with_label("-");
+        let scope = self.scope.clone();
+        let label = self.timebase_label.clone();
+        zoom_in.connect_clicked(move |_| {
+            if let Ok(mut scope) = scope.lock() {
+                scope.zoom_in();
+                label.set_text(&format!("{} ms", scope.timebase()));
+            }
+        });
+
+        let zoom_out = gtk::Button::with_label("+");
+        let scope = self.scope.clone();
+        let label = self.timebase_label.clone();
+        zoom_out.connect_clicked(move |_| {
+            if let Ok(mut scope) = scope.lock() {
+                scope.zoom_out();
+                label.set_text(&format!("{} ms", scope.timebase()));
+            }
+        });
+
+        let hold = gtk::ToggleButton::with_label("Hold");
+        let scope = self.scope.clone();
+        hold.connect_toggled(move |button| {
+            if let Ok(mut scope) = scope.lock() {
+                scope.set_hold(button.get_active());
+            }
+        });
+
+        controls.pack_start(&gtk::Label::new(Some("Trigger")), false, false, 0);
+        controls.pack_start(&edge, false, false, 0);
+        controls.pack_start(&level, true, true, 0);
+        controls.pack_start(&zoom_in, false, false, 0);
+        controls.pack_start(&self.timebase_label, false, false, 0);
+        controls.pack_start(&zoom_out, false, false, 0);
+        controls.pack_start(&hold, false, false, 0);
+        controls
+    }
+
+    fn update_timebase_label(&self) {
+        if let Ok(scope) = self.scope.lock() {
+            self.timebase_label
+                .set_text(&format!("{} ms", scope.timebase()));
+        }
+    }
+
+    pub fn widget(&self) -> &gtk::Box {
+        &self.layout
+    }
+
+    pub fn push(&mut self, samples: Vec<f32>) {
+        self.samples = samples;
+        self.area.queue_draw();
+    }
+
+    pub fn draw(&self, ctx: &cairo::Context, width: f64, height: f64) {
+        // paint background with grey, same as the spectrum graph
+        ctx.set_source_rgb(0.5, 0.5, 0.5);
+        ctx.rectangle(0., 0., width, height);
+        ctx.fill();
+
+        let mid = height / 2.;
+        let level = self.scope.lock().map(|s| s.level()).unwrap_or(0.);
+
+        // zero line and the dashed trigger level
+        ctx.set_line_width(0.5);
+        ctx.set_source_rgb(0.3, 0.3, 0.3);
+        ctx.move_to(0., mid);
+        ctx.line_to(width, mid);
+        ctx.stroke();
+        ctx.set_dash(&[4., 4.], 0.);
+        ctx.set_source_rgb(1., 0.5, 0.);
+        ctx.move_to(0., mid - f64::from(level) * mid);
+        ctx.line_to(width, mid - f64::from(level) * mid);
+        ctx.stroke();
+        ctx.set_dash(&[], 0.);
+
+        if self.samples.len() < 2 {
+            return;
+        }
+        let x_incr = width / (self.samples.len() - 1) as f64;
+        ctx.set_line_width(1.);
+        ctx.set_source_rgb(0., 0., 1.0);
+        ctx.move_to(0., mid - f64::from(self.samples[0]) * mid);
+        for (i, sample) in self.samples.iter().enumerate().skip(1) {
+            ctx.line_to(i as f64 * x_incr, mid - f64::from(*sample) * mid);
+        }
+        ctx.stroke();
+    }
+}
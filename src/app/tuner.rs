@@ -0,0 +1,133 @@
+use crate::equalizer::dsp::tuner::{Reading, Temperament, Tuner};
+
+use gtk::prelude::*;
+use gtk::{DrawingArea, Orientation};
+use std::sync::{Arc, Mutex};
+
+const TEMPERAMENTS: [Temperament; 4] = [
+    Temperament::Equal,
+    Temperament::Just,
+    Temperament::Pythagorean,
+    Temperament::Meantone,
+];
+
+/// Note name and cents needle, with the reference and temperament selectors below
+pub struct TunerPanel {
+    pub area: DrawingArea,
+    layout: gtk::Box,
+    reading: Option<Reading>,
+    tuner: Arc<Mutex<Tuner>>,
+}
+
+impl TunerPanel {
+    pub fn new(width: i32, height: i32, tuner: Arc<Mutex<Tuner>>) -> TunerPanel {
+        let panel = TunerPanel {
+            area: DrawingArea::new(),
+            layout: gtk::Box::new(Orientation::Vertical, 5),
+            reading: None,
+            tuner,
+        };
+        panel.area.set_size_request(width, height);
+        panel.layout.pack_start(&panel.area, true, true, 0);
+        panel
+            .layout
+            .pack_start(&panel.build_controls(), false, false, 0);
+        panel
+    }
+
+    fn build_controls(&self) -> gtk::Box {
+        let controls = gtk::Box::new(Orientation::Horizontal, 5);
+        let (reference, temperament) = match self.tuner.lock() {
+            Ok(tuner) => (tuner.reference(), tuner.temperament()),
+            Err(_) => (440., Temperament::Equal),
+        };
+
+        let a4 = gtk::SpinButton::with_range(400., 480., 0.5);
+        a4.set_value(f64::from(reference));
+        let tuner = self.tuner.clone();
+        a4.connect_value_changed(move |spin| {
+            if let Ok(mut tuner) = tuner.lock() {
+                tuner.set_reference(spin.get_value() as f32);
+            }
+        });
+
+        let temperaments = gtk::ComboBoxText::new();
+        for t in TEMPERAMENTS.iter() {
+            temperaments.append_text(t.name());
+        }
+        let active = TEMPERAMENTS.iter().position(|t| *t == temperament);
+        temperaments.set_active(active.map(|idx| idx as u32));
+        let tuner = self.tuner.clone();
+        temperaments.connect_changed(move |combo| {
+            if let (Some(idx), Ok(mut tuner)) = (combo.get_active(), tuner.lock()) {
+                tuner.set_temperament(TEMPERAMENTS[idx as usize]);
+            }
+        });
+
+        controls.pack_start(&gtk::Label::new(Some("A4 (Hz)")), false, false, 0);
+        controls.pack_start(&a4, false, false, 0);
+        controls.pack_start(&gtk::Label::new(Some("Temperament")), false, false, 0);
+        controls.pack_start(&temperaments, false, false, 0);
+        controls
+    }
+
+    pub fn widget(&self) -> &gtk::Box {
+        &self.layout
+    }
+
+    pub fn push(&mut self, reading: Option<Reading>) {
+        self.reading = reading;
+        self.area.queue_draw();
+    }
+
+    pub fn draw(&self, ctx: &cairo::Context, width: f64, height: f64) {
+        // paint background with grey, same as the spectrum graph
+        ctx.set_source_rgb(0.5, 0.5, 0.5);
+        ctx.rectangle(0., 0., width, height);
+        ctx.fill();
+
+        let (note, details, cents) = match &self.reading {
+            Some(reading) => (
+                format!("{}{}", reading.note, reading.octave),
+                format!("{:+.1} cents  {:.2} Hz", reading.cents, reading.frequency),
+                Some(f64::from(reading.cents)),
+            ),
+            None => (String::from("--"), String::new(), None),
+        };
+
+        ctx.set_source_rgb(0., 0., 0.);
+        ctx.set_font_size(height / 4.);
+        let extents = ctx.text_extents(&note);
+        ctx.move_to((width - extents.width) / 2., height / 2.);
+        ctx.show_text(&note);
+        ctx.set_font_size(height / 16.);
+        let extents = ctx.text_extents(&details);
+        ctx.move_to((width - extents.width) / 2., height / 2. + height / 10.);
+        ctx.show_text(&details);
+
+        // -50..+50 cents scale with a tick every 10 cents
+        let scale_y = height * 0.8;
+        let cents_to_x = |c: f64| width / 2. + c / 50. * (width / 2. - 10.);
+        ctx.set_line_width(1.);
+        ctx.move_to(cents_to_x(-50.), scale_y);
+        ctx.line_to(cents_to_x(50.), scale_y);
+        for tick in (-50..=50).step_by(10) {
+            let len = if tick == 0 { 20. } else { 10. };
+            ctx.move_to(cents_to_x(f64::from(tick)), scale_y);
+            ctx.line_to(cents_to_x(f64::from(tick)), scale_y - len);
+        }
+        ctx.stroke();
+
+        if let Some(cents) = cents {
+            if cents.abs() < 5. {
+                ctx.set_source_rgb(0., 1., 0.);
+            } else {
+                ctx.set_source_rgb(1., 0., 0.);
+            }
+            ctx.set_line_width(3.);
+            ctx.move_to(cents_to_x(cents), scale_y + 5.);
+            ctx.line_to(cents_to_x(cents), scale_y - 40.);
+            ctx.stroke();
+        }
+    }
+}
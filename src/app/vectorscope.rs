@@ -0,0 +1,81 @@
+use crate::equalizer::dsp::stereo::StereoImage;
+
+use gtk::prelude::*;
+use gtk::DrawingArea;
+
+const METER_HEIGHT: f64 = 20.;
+
+/// Goniometer with the phase correlation meter along the bottom edge
+pub struct Vectorscope {
+    pub area: DrawingArea,
+    image: StereoImage,
+}
+
+impl Vectorscope {
+    pub fn new(width: i32, height: i32) -> Vectorscope {
+        let v = Vectorscope {
+            area: DrawingArea::new(),
+            image: StereoImage {
+                points: Vec::new(),
+                correlation: 0.,
+            },
+        };
+        v.area.set_size_request(width, height);
+        v
+    }
+
+    pub fn push(&mut self, image: StereoImage) {
+        self.image = image;
+        self.area.queue_draw();
+    }
+
+    pub fn draw(&self, ctx: &cairo::Context, width: f64, height: f64) {
+        // paint background with grey, same as the spectrum graph
+        ctx.set_source_rgb(0.5, 0.5, 0.5);
+        ctx.rectangle(0., 0., width, height);
+        ctx.fill();
+
+        // keep the goniometer square and centered above the meter
+        let side = (height - METER_HEIGHT).min(width);
+        let half = side / 2.;
+        let (cx, cy) = (width / 2., half);
+
+        ctx.set_line_width(0.5);
+        ctx.set_source_rgb(0.3, 0.3, 0.3);
+        ctx.move_to(cx - half, cy + half);
+        ctx.line_to(cx + half, cy - half);
+        ctx.move_to(cx - half, cy - half);
+        ctx.line_to(cx + half, cy + half);
+        ctx.move_to(cx, cy - half);
+        ctx.line_to(cx, cy + half);
+        ctx.stroke();
+
+        ctx.set_source_rgb(0., 0., 1.0);
+        for (s, m) in &self.image.points {
+            let x = cx + f64::from(*s) * half;
+            let y = cy - f64::from(*m) * half;
+            ctx.rectangle(x, y, 1., 1.);
+        }
+        ctx.fill();
+
+        // correlation meter, from the middle to the left when negative
+        let correlation = f64::from(self.image.correlation);
+        let meter_y = height - METER_HEIGHT;
+        if correlation < 0. {
+            ctx.set_source_rgb(1., 0., 0.);
+        } else {
+            ctx.set_source_rgb(0., 1., 0.);
+        }
+        ctx.rectangle(
+            width / 2.,
+            meter_y + 2.,
+            correlation * width / 2.,
+            METER_HEIGHT - 4.,
+        );
+        ctx.fill();
+
+        ctx.set_source_rgb(0., 0., 0.);
+        ctx.move_to(5., height - 5.);
+        ctx.show_text(&format!("Correlation {:+.2}", correlation));
+    }
+}
@@ -1,57 +1,757 @@
-mod dsp;
+//! the capture side: picks the source, runs the filters on the input callback and hands the
+//! samples to the analysis thread
 
-use crate::equalizer::dsp::DSP;
+pub mod device;
+pub mod dsp;
+pub mod generator;
+#[cfg(feature = "capture")]
+mod passthrough;
+pub mod pcm;
+pub mod recorder;
+pub mod rtp;
+pub mod session;
+pub mod source;
+pub mod wav;
+
+use crate::equalizer::device::{Backoff, DeviceStatus, Health};
+#[cfg(feature = "capture")]
+use crate::equalizer::device::{Search, Searched};
+use crate::equalizer::dsp::beat::{BeatEvent, BeatTracker, Tempo};
+use crate::equalizer::dsp::biquad::{FilterChain, FilterSpec};
+use crate::equalizer::dsp::chroma::{Chroma, Chromagram};
+use crate::equalizer::dsp::pipeline::{Pipeline, PipelineSpec};
+use crate::equalizer::dsp::queue::{FrameQueue, LagPolicy};
+use crate::equalizer::dsp::scope::Scope;
+use crate::equalizer::dsp::spectrum::{centred, Band, Settings, SpectrumFrame, Weighting};
+use crate::equalizer::dsp::stereo::{Goniometer, StereoImage};
+use crate::equalizer::dsp::tuner::{Reading, Tuner};
+use crate::equalizer::dsp::window::WindowType;
+use crate::equalizer::dsp::{FlowStats, SessionSlot, DSP};
+use crate::equalizer::generator::{Generator, GeneratorStream};
+#[cfg(feature = "capture")]
+use crate::equalizer::passthrough::PassThrough;
+use crate::equalizer::pcm::{PcmReader, PcmStream};
+use crate::equalizer::recorder::{Recorder, RecordingStatus, Rotation};
+use crate::equalizer::rtp::{RtpStats, RtpStream};
+use crate::equalizer::session::{SessionHeader, SessionWriter};
+use crate::equalizer::source::Source;
 use crate::errors::{Error, StreamOp};
+#[cfg(not(feature = "capture"))]
+use crate::ring_buffer::Producer;
+use crate::shutdown;
 use anyhow::{Context, Result};
+#[cfg(feature = "capture")]
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+#[cfg(feature = "capture")]
 use cpal::Stream;
-use std::sync::{Arc, Mutex};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const STALL: Duration = Duration::from_secs(2); // a playing device silent this long is gone
+const EQ_BLOCK: usize = 4096; // samples the filters process at once in the input callback
 
+/// an input with the equalizer and the analysis attached; set it up, `connect`, then `play`
 pub struct Equalizer {
     // handle to audio file,stream etc
     core: Arc<Mutex<DSP>>,
-    device: cpal::Device,
-    config: cpal::StreamConfig,
-    stream: Option<Stream>,
-    status: bool,
+    frames: Arc<FrameQueue<SpectrumFrame>>,
+    equalized: Arc<FrameQueue<SpectrumFrame>>,
+    scope: Arc<Mutex<Scope>>,
+    goniometer: Arc<Mutex<Goniometer>>,
+    tuner: Arc<Mutex<Tuner>>,
+    chroma: Arc<Mutex<Chroma>>,
+    beat: Arc<Mutex<BeatTracker>>,
+    filters: Arc<Mutex<FilterChain>>,
+    post_eq: Arc<AtomicBool>, // whether the equalized signal is analysed too
+    unfiltered: Arc<AtomicU64>, // blocks the callback let through while the filters were locked
+    #[cfg(feature = "capture")]
+    output: Option<PassThrough>,
+    recorder: Option<Recorder>,
+    record_dir: PathBuf, // for audio recordings and spectrum sessions
+    session: SessionSlot,
+    #[cfg(feature = "capture")]
+    host: cpal::Host,
+    source: Source,
+    #[cfg(feature = "capture")]
+    device: Option<cpal::Device>, // only for Source::Device
+    #[cfg(feature = "capture")]
+    device_name: Option<String>, // the one asked for, tried first when reconnecting
+    channels: u16,
+    sample_rate: u32,
+    stream: Option<Input>,
+    health: Arc<Health>, // of the current stream
+    lost: Option<Lost>,
+    #[cfg(feature = "capture")]
+    search: Option<Search>, // a device lookup under way
+    #[cfg(feature = "capture")]
+    fallback: Option<Backoff>, // on the default device, when to look for the requested one again
+}
+
+// a device stream that failed, until it is reconnected
+struct Lost {
+    error: String,
+    backoff: Backoff,
+}
+
+// what connect started for the source
+enum Input {
+    #[cfg(feature = "capture")]
+    Device(Stream),
+    Generator(GeneratorStream),
+    Pipe(PcmStream),
+    Rtp(RtpStream),
 }
 
 impl Equalizer {
+    /// opens the host and, for a device source, the named input device; nothing is captured
+    /// before `connect`
+    #[cfg_attr(not(feature = "capture"), allow(unused_variables))]
     pub fn new(
         device_name: &Option<String>,
         host_name: &Option<String>,
-        bins: Option<usize>,
+        source: &Source,
+        settings: Settings,
+        channels: u16,
+        sample_rate: u32,
     ) -> Result<Equalizer, Error> {
-        let mut host = cpal::default_host(); // default host [ALSA]
-        if let Some(hostname) = host_name.as_ref() {
-            for h in cpal::available_hosts() {
-                if h.name() == hostname {
-                    if let Ok(host_enum) = cpal::host_from_id(h) {
-                        host = host_enum;
-                    } else {
-                        warn!("There is no such host, defaulting to ALSA");
+        #[cfg(feature = "capture")]
+        let (host, device) = open_host(device_name, host_name, source)?;
+        #[cfg(not(feature = "capture"))]
+        if *source == Source::Device {
+            return Err(no_capture());
+        }
+
+        let core = DSP::new(settings, sample_rate, channels as usize); // TODO: extend to different formats?
+        let frames = core.frames();
+        let equalized = core.equalized();
+        let scope = core.scope();
+        let goniometer = core.goniometer();
+        let tuner = core.tuner();
+        let chroma = core.chroma();
+        let beat = core.beat();
+        let session = core.session();
+
+        Ok(Equalizer {
+            core: Arc::new(Mutex::new(core)),
+            frames,
+            equalized,
+            scope,
+            goniometer,
+            tuner,
+            chroma,
+            beat,
+            filters: Arc::new(Mutex::new(FilterChain::new(sample_rate, channels as usize))),
+            post_eq: Arc::new(AtomicBool::new(false)),
+            unfiltered: Arc::new(AtomicU64::new(0)),
+            #[cfg(feature = "capture")]
+            output: None,
+            recorder: None,
+            record_dir: PathBuf::from("."),
+            session,
+            #[cfg(feature = "capture")]
+            host,
+            source: source.clone(),
+            #[cfg(feature = "capture")]
+            device,
+            #[cfg(feature = "capture")]
+            device_name: device_name.clone(),
+            channels,
+            sample_rate,
+            stream: None,
+            health: Arc::new(Health::new(Instant::now())),
+            lost: None,
+            #[cfg(feature = "capture")]
+            search: None,
+            #[cfg(feature = "capture")]
+            fallback: None,
+        })
+    }
+
+    /// plays the equalized input on the named output device, needs to be called before connect
+    #[cfg(feature = "capture")]
+    pub fn set_output(&mut self, device_name: &str) -> Result<(), Error> {
+        self.output = Some(PassThrough::new(&self.host, device_name)?);
+        Ok(())
+    }
+
+    #[cfg(not(feature = "capture"))]
+    pub fn set_output(&mut self, _device_name: &str) -> Result<(), Error> {
+        Err(no_capture())
+    }
+
+    /// lets the captured input be recorded to WAV files in directory, needs to be called before
+    /// connect; recording starts with set_recording
+    pub fn set_recorder(&mut self, directory: &Path, rotation: Rotation) {
+        self.record_dir = directory.to_path_buf();
+        self.recorder = Some(Recorder::new(
+            directory,
+            self.channels,
+            self.sample_rate,
+            rotation,
+        ));
+    }
+
+    /// starts the source, every block goes through the recorder, the filters and the output
+    /// before the analysis
+    pub fn connect(&mut self) -> Result<(), Error> {
+        let health = Arc::new(Health::new(Instant::now()));
+        self.health = health.clone();
+        #[cfg(feature = "capture")]
+        let errors = health.clone();
+        // the only way from the callback to the analysis, it neither locks nor allocates
+        let mut input = self.core()?.input();
+        let mut equalized_input = self.core()?.equalized_input();
+        let filters = self.filters.clone();
+        let post_eq = self.post_eq.clone();
+        #[cfg(feature = "capture")]
+        let mut output = self.output.as_mut().map(|output| output.buffer());
+        #[cfg(not(feature = "capture"))]
+        let mut output: Option<Producer<f32>> = None;
+        let mut recorder = self.recorder.as_ref().map(|recorder| recorder.tap());
+        let unfiltered = self.unfiltered.clone();
+        // the filters take the input in blocks up to this size, so the callback never allocates
+        let frame = (self.channels as usize).max(1);
+        let mut equalized = vec![0.; EQ_BLOCK / frame * frame];
+        // only the device stream calls it through a closure of its own
+        #[cfg_attr(not(feature = "capture"), allow(unused_mut))]
+        let mut callback = move |data: &[f32]| {
+            // note to self -> because rust moves all what closure captures, need a cloned Arc reference and thread safety -> Mutex
+            // stream events etc here
+            info!("Data received from CPAL, length {}", data.len());
+            health.data(Instant::now());
+            if let Some(recorder) = &mut recorder {
+                recorder.write(data);
+            }
+            let post_eq = post_eq.load(Ordering::Relaxed);
+            // only run the filters when someone listens to or looks at the result
+            let equalize = output.is_some() || post_eq;
+            if equalize {
+                for chunk in data.chunks(equalized.len()) {
+                    let block = &mut equalized[..chunk.len()];
+                    block.copy_from_slice(chunk);
+                    // never waits for a front-end changing the filters, the block goes through
+                    // as it is then and is counted in the flow stats
+                    match filters.try_lock() {
+                        Ok(mut filters) => filters.process(block),
+                        Err(_) => {
+                            unfiltered.fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                    if let Some(output) = &mut output {
+                        // drop what does not fit, the output device is lagging behind
+                        output.push_slice(block);
+                    }
+                    if post_eq {
+                        equalized_input.push(block);
+                    }
+                }
+            }
+
+            // a full queue drops the block, counted in the flow stats
+            input.push(data);
+        };
+
+        let channels = self.channels as usize;
+        let input = match &self.source {
+            Source::Generator { signal, level } => {
+                let generator = Generator::new(signal.clone(), self.sample_rate, *level);
+                Input::Generator(GeneratorStream::new(generator, channels, callback))
+            }
+            Source::Pipe { path, format } => {
+                let reader = PcmReader::new(pcm::open(path.as_deref())?, *format, channels);
+                Input::Pipe(PcmStream::new(reader, callback))
+            }
+            Source::Rtp {
+                address,
+                payload,
+                jitter,
+            } => Input::Rtp(RtpStream::new(
+                address,
+                *payload,
+                channels,
+                self.sample_rate,
+                *jitter,
+                callback,
+            )?),
+            #[cfg(feature = "capture")]
+            Source::Device => {
+                let device = self.device.as_ref().ok_or(Error::NoCpalDevice)?;
+                // supervise picks the error up and reconnects
+                let err_fn = move |err: cpal::StreamError| {
+                    error!("An error ocurred on stream: {}", err);
+                    errors.error(err.to_string());
+                };
+                Input::Device(device.build_input_stream(
+                    &self.stream_config(),
+                    move |data: &[f32], _: &cpal::InputCallbackInfo| callback(data),
+                    err_fn,
+                )?)
+            }
+            #[cfg(not(feature = "capture"))]
+            Source::Device => return Err(no_capture()),
+        };
+        self.stream = Some(input);
+        #[cfg(feature = "capture")]
+        {
+            let config = self.stream_config();
+            if let Some(output) = self.output.as_mut() {
+                output.connect(&config)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn play(&self) -> Result<(), Error> {
+        match &self.stream {
+            #[cfg(feature = "capture")]
+            Some(Input::Device(stream)) => stream.play()?,
+            Some(Input::Generator(generated)) => generated.play(),
+            Some(Input::Pipe(piped)) => piped.play(),
+            Some(Input::Rtp(received)) => received.play(),
+            None => return Err(Error::StreamOperation(StreamOp::Play)),
+        }
+        self.health.set_playing(true, Instant::now());
+        #[cfg(feature = "capture")]
+        if let Some(output) = &self.output {
+            output.play()?;
+        }
+        Ok(())
+    }
+
+    pub fn pause(&self) -> Result<(), Error> {
+        match &self.stream {
+            #[cfg(feature = "capture")]
+            Some(Input::Device(stream)) => stream.pause()?,
+            Some(Input::Generator(generated)) => generated.pause(),
+            Some(Input::Pipe(piped)) => piped.pause(),
+            Some(Input::Rtp(received)) => received.pause(),
+            None => return Err(Error::StreamOperation(StreamOp::Pause)),
+        }
+        self.health.set_playing(false, Instant::now());
+        #[cfg(feature = "capture")]
+        if let Some(output) = &self.output {
+            output.pause()?;
+        }
+        Ok(())
+    }
+
+    /// for the front-ends to call between frames: notices when the input device is gone and
+    /// reconnects to it, or to the default device, with a growing delay between the attempts;
+    /// on the default device it keeps looking for the requested one; never blocks
+    pub fn supervise(&mut self) {
+        // only a playing device can be lost, the other sources end or wait on their own
+        if self.source != Source::Device || (self.stream.is_none() && self.lost.is_none()) {
+            return;
+        }
+        let now = Instant::now();
+        if self.lost.is_none() {
+            if let Some(error) = self.health.failure(STALL, now) {
+                warn!("Lost the input device: {}", error);
+                self.lose(error, now);
+            }
+        }
+        self.search(now);
+    }
+
+    // drops the stream and any search for the requested device, reconnecting starts right away
+    fn lose(&mut self, error: String, now: Instant) {
+        self.stream = None;
+        #[cfg(feature = "capture")]
+        {
+            self.search = None;
+            self.fallback = None;
+        }
+        self.lost = Some(Lost {
+            error,
+            backoff: Backoff::new(now),
+        });
+    }
+
+    // starts a search when an attempt is due and picks up what it found; a lost device takes
+    // the default one when the requested one is missing, a fallback only the requested one
+    #[cfg(feature = "capture")]
+    fn search(&mut self, now: Instant) {
+        let searched = match &self.search {
+            Some(search) => search.searched(),
+            None => {
+                let due = match (&self.lost, &self.fallback) {
+                    (Some(lost), _) => lost.backoff.due(now),
+                    (None, Some(fallback)) => fallback.due(now),
+                    (None, None) => false,
+                };
+                if due {
+                    let only_requested = self.lost.is_none();
+                    let host = self.host.id();
+                    self.search = Some(Search::start(
+                        host,
+                        self.device_name.clone(),
+                        only_requested,
+                    ));
+                }
+                return;
+            }
+        };
+        let (device, requested) = match searched {
+            Searched::Looking => return,
+            Searched::Found { device, requested } => (device, requested),
+            Searched::Missing => {
+                self.search = None;
+                match (self.lost.as_mut(), self.fallback.as_mut()) {
+                    (Some(lost), _) => {
+                        lost.error = Error::NoCpalDevice.to_string();
+                        lost.backoff.failed(now);
                     }
+                    (None, Some(fallback)) => fallback.failed(now),
+                    (None, None) => {}
                 }
+                return;
             }
+        };
+        self.search = None;
+        self.device = Some(device);
+        match self.connect().and_then(|()| self.play()) {
+            Ok(()) => {
+                info!("Connected to the input device");
+                self.lost = None;
+                // the first look for the requested device comes after the first delay
+                self.fallback = match self.device_name {
+                    Some(_) if !requested => {
+                        let mut fallback = Backoff::new(now);
+                        fallback.failed(now);
+                        Some(fallback)
+                    }
+                    _ => None,
+                };
+            }
+            Err(err) if self.lost.is_none() => {
+                warn!("Could not switch to the requested input device: {}", err);
+                self.lose(err.to_string(), now);
+            }
+            Err(err) => {
+                self.stream = None;
+                if let Some(lost) = self.lost.as_mut() {
+                    lost.error = err.to_string();
+                    lost.backoff.failed(now);
+                }
+            }
+        }
+    }
+
+    // there is no device source to lose
+    #[cfg(not(feature = "capture"))]
+    fn search(&mut self, _now: Instant) {}
+
+    /// None unless capturing from a device
+    pub fn get_device_status(&self) -> Option<DeviceStatus> {
+        if self.source != Source::Device {
+            return None;
+        }
+        if let Some(lost) = &self.lost {
+            return Some(DeviceStatus::Lost {
+                error: lost.error.clone(),
+                attempts: lost.backoff.attempts(),
+                retry_in: lost.backoff.retry_in(Instant::now()),
+            });
+        }
+        #[cfg(feature = "capture")]
+        if let Some(name) = self.device.as_ref().and_then(|device| device.name().ok()) {
+            return Some(DeviceStatus::Connected {
+                fallback: self.device_name.as_deref() != Some(name.as_str()),
+                name,
+            });
         }
+        None
+    }
+
+    /// an error once the analysis thread failed, for the front-ends to check between frames
+    pub fn check(&self) -> Result<(), Error> {
+        self.core()?.check()
+    }
 
-        let mut device: Option<cpal::Device> = None;
+    /// stops the input and the output, lets the analysis finish what was captured, closes the
+    /// recordings and joins every thread; an error of the analysis thread comes first
+    pub fn shutdown(&mut self) -> Result<(), Error> {
+        // dropping the streams stops them and joins their threads, no more samples come in
+        self.stream = None;
+        #[cfg(feature = "capture")]
+        {
+            self.output = None;
+            self.search = None;
+        }
+        let analysis = self.core()?.shutdown();
+        let session = self.stop_session();
+        self.recorder = None; // finishes the current file
+        analysis.and(session)
+    }
+
+    /// a piped input reached its end, the views keep the last frames
+    pub fn ended(&self) -> bool {
+        matches!(&self.stream, Some(Input::Pipe(piped)) if piped.ended())
+    }
+
+    /// packet counts of a network input
+    pub fn get_network_status(&self) -> Option<RtpStats> {
+        match &self.stream {
+            Some(Input::Rtp(received)) => Some(received.stats()),
+            _ => None,
+        }
+    }
 
-        if let Some(devicename) = device_name.as_ref() {
-            for dev in host.input_devices().unwrap() {
-                if let Ok(dev_name) = dev.name() {
-                    if dev_name.eq(devicename) {
-                        device.replace(dev);
-                        info!("device {}", device.as_ref().unwrap().name().unwrap());
+    /// captures the input for a while on a stream of its own, without the analysis, returns the
+    /// interleaved samples and the sample rate
+    pub fn record(&self, duration: Duration) -> Result<(Vec<f32>, u32), Error> {
+        let channels = self.channels as usize;
+        let sample_rate = self.sample_rate;
+        let wanted = (duration.as_secs_f32() * sample_rate as f32) as usize * channels;
+        match &self.source {
+            Source::Generator { signal, level } => {
+                // no need to wait for a generator
+                let mut generator = Generator::new(signal.clone(), sample_rate, *level);
+                let mut interleaved = vec![0.; wanted];
+                generator.fill(&mut interleaved, channels);
+                Ok((interleaved, sample_rate))
+            }
+            Source::Pipe { path, format } => {
+                // up to the end of the stream if it comes first
+                let mut reader = PcmReader::new(pcm::open(path.as_deref())?, *format, channels);
+                let mut interleaved = Vec::with_capacity(wanted);
+                while interleaved.len() < wanted {
+                    // bounded, so that Ctrl-C and SIGTERM are seen while the writer is silent
+                    if shutdown::requested() {
+                        return Err(Error::Stopped);
+                    }
+                    match reader.poll(pcm::POLL)? {
+                        Some(samples) => interleaved.extend(samples),
+                        None => break,
                     }
                 }
+                interleaved.truncate(wanted);
+                Ok((interleaved, sample_rate))
+            }
+            Source::Rtp {
+                address,
+                payload,
+                jitter,
+            } => {
+                let (sender, receiver) = mpsc::channel();
+                let stream = RtpStream::new(
+                    address,
+                    *payload,
+                    channels,
+                    sample_rate,
+                    *jitter,
+                    move |data| {
+                        let _ = sender.send(data.to_vec());
+                    },
+                )?;
+                stream.play();
+                let mut interleaved = Vec::with_capacity(wanted);
+                while interleaved.len() < wanted {
+                    interleaved.extend(captured(&receiver)?);
+                }
+                interleaved.truncate(wanted);
+                Ok((interleaved, sample_rate))
+            }
+            #[cfg(feature = "capture")]
+            Source::Device => Ok((self.record_device(wanted)?, sample_rate)),
+            #[cfg(not(feature = "capture"))]
+            Source::Device => Err(no_capture()),
+        }
+    }
+
+    #[cfg(feature = "capture")]
+    fn record_device(&self, wanted: usize) -> Result<Vec<f32>, Error> {
+        let device = self.device.as_ref().ok_or(Error::NoCpalDevice)?;
+        let (sender, receiver) = mpsc::channel();
+        let stream = device.build_input_stream(
+            &self.stream_config(),
+            move |data: &[f32], _: &cpal::InputCallbackInfo| {
+                // the receiver hangs up once it has enough
+                let _ = sender.send(data.to_vec());
+            },
+            move |err| {
+                error!("An error ocurred on stream: {}", err);
+            },
+        )?;
+        stream.play()?;
+
+        let mut interleaved = Vec::with_capacity(wanted);
+        while interleaved.len() < wanted {
+            interleaved.extend(captured(&receiver)?);
+        }
+        drop(stream);
+        interleaved.truncate(wanted);
+        Ok(interleaved)
+    }
+
+    pub fn recording(&self) -> bool {
+        matches!(&self.recorder, Some(recorder) if recorder.recording())
+    }
+
+    /// starts or stops writing the captured input, does nothing without a recorder
+    pub fn set_recording(&self, recording: bool) {
+        if let Some(recorder) = &self.recorder {
+            recorder.set_recording(recording);
+        }
+    }
+
+    pub fn get_recording_status(&self) -> Option<RecordingStatus> {
+        self.recorder.as_ref().map(|recorder| recorder.status())
+    }
+
+    /// saves the spectrum frames from now on to a new session file in the recording directory
+    pub fn start_session(&self) -> Result<PathBuf, Error> {
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs());
+        let path = self
+            .record_dir
+            .join(format!("rustalizer-{}.session", started));
+        let writer = SessionWriter::create(&path, &self.session_header())?;
+        if let Ok(mut session) = self.session.lock() {
+            *session = Some(writer);
+        }
+        Ok(path)
+    }
+
+    pub fn stop_session(&self) -> Result<(), Error> {
+        let writer = match self.session.lock() {
+            Ok(mut session) => session.take(),
+            Err(_) => None,
+        };
+        match writer {
+            Some(writer) => writer.finish().map(|_| ()),
+            None => Ok(()),
+        }
+    }
+
+    pub fn session_recording(&self) -> bool {
+        matches!(self.session.lock(), Ok(session) if session.is_some())
+    }
+
+    /// the filter chain applied to the pass-through, can be changed while playing
+    pub fn filters(&self) -> Arc<Mutex<FilterChain>> {
+        self.filters.clone()
+    }
+
+    /// adds a 10 or 31 band graphic EQ after the parametric filters
+    pub fn set_graphic_eq(&self, bands: usize) -> Result<(), Error> {
+        match self.filters.lock() {
+            Ok(mut filters) => filters.set_graphic(bands).map_err(Error::Config),
+            Err(_) => Ok(()),
+        }
+    }
+
+    /// centre frequency and gain of every graphic EQ band
+    pub fn get_eq_bands(&self) -> Option<Vec<(f32, f32)>> {
+        let filters = self.filters.lock().ok()?;
+        let graphic = filters.graphic()?;
+        Some(
+            graphic
+                .centres()
+                .iter()
+                .cloned()
+                .zip(graphic.gains().iter().cloned())
+                .collect(),
+        )
+    }
+
+    pub fn set_eq_gain(&self, band: usize, gain: f32) {
+        if let Ok(mut filters) = self.filters.lock() {
+            if let Some(graphic) = filters.graphic_mut() {
+                graphic.set_gain(band, gain);
             }
         }
+    }
+
+    pub fn reset_eq(&self) {
+        if let Ok(mut filters) = self.filters.lock() {
+            if let Some(graphic) = filters.graphic_mut() {
+                graphic.reset();
+            }
+        }
+    }
+
+    /// convolves the pass-through with the impulse response in a WAV file, block is the
+    /// partition size in samples and sets the added latency
+    pub fn set_impulse_response(&self, path: &Path, block: usize) -> Result<(), Error> {
+        if !block.is_power_of_two() {
+            return Err(Error::Config(format!(
+                "The convolution block must be a power of two, not {}",
+                block
+            )));
+        }
+        let (ir, sample_rate) = wav::read(path)?;
+        if sample_rate != self.sample_rate {
+            return Err(Error::Config(format!(
+                "The impulse response is sampled at {} Hz, the stream at {} Hz",
+                sample_rate, self.sample_rate
+            )));
+        }
+        if let Ok(mut filters) = self.filters.lock() {
+            filters.set_impulse_response(&ir, block);
+        }
+        Ok(())
+    }
+
+    /// delay of the pass-through processing in seconds, without the device buffers
+    pub fn latency(&self) -> f32 {
+        let samples = self.filters.lock().map_or(0, |filters| filters.latency());
+        samples as f32 / self.sample_rate as f32
+    }
 
-        if device.is_none() {
-            return Err(Error::NoCpalDevice);
+    pub fn add_filter(&self, spec: FilterSpec) {
+        if let Ok(mut filters) = self.filters.lock() {
+            filters.add(spec);
         }
+    }
 
+    pub fn post_eq(&self) -> bool {
+        self.post_eq.load(Ordering::Relaxed)
+    }
+
+    /// analyses the equalized signal as well, its spectrum is shown next to the captured one
+    pub fn set_post_eq(&self, post_eq: bool) {
+        self.post_eq.store(post_eq, Ordering::Relaxed);
+    }
+
+    /// the bands of the next spectrum frames, known before the first one
+    pub fn bands(&self) -> Vec<Band> {
+        let header = self.session_header();
+        centred(&header.bands, header.settings.bins)
+    }
+
+    /// the spectrum settings, changed with the controls below
+    pub fn settings(&self) -> Settings {
+        self.session_header().settings
+    }
+
+    /// the spectrum controls take effect from the next frame, without restarting the capture; a
+    /// session being recorded ends when they change the spectrum
+    pub fn set_bins(&self, bins: Option<usize>) -> Result<(), Error> {
+        self.core()?.set_bins(bins)
+    }
+
+    pub fn set_window(&self, window: WindowType) -> Result<(), Error> {
+        self.core()?.set_window(window)
+    }
+
+    pub fn set_fft_size(&self, fft_size: usize) -> Result<(), Error> {
+        self.core()?.set_fft_size(fft_size)
+    }
+
+    pub fn set_weighting(&self, weighting: Weighting) -> Result<(), Error> {
+        self.core()?.set_weighting(weighting)
+    }
+
+    pub fn set_smoothing(&self, smoothing: Option<f32>) -> Result<(), Error> {
+        self.core()?.set_smoothing(smoothing)
+    }
+
+    #[cfg(feature = "capture")]
+    fn stream_config(&self) -> cpal::StreamConfig {
         /*
         let mut supported_configs_range = device
             .unwrap()
@@ -71,74 +771,137 @@ impl Equalizer {
         */
         // TODO: match on input parameters and construct the config
         // check them for correctness with supported range
-        let config = cpal::StreamConfig {
-            channels: 1, // TODO: crashes on more than one channel
-            sample_rate: cpal::SampleRate(44100),
+        cpal::StreamConfig {
+            channels: self.channels,
+            sample_rate: cpal::SampleRate(self.sample_rate),
             buffer_size: cpal::BufferSize::Default, // TODO: magic numbers for buffer cause ALSA panics
-        };
-        let device = device.unwrap();
+        }
+    }
 
-        Ok(Equalizer {
-            core: Arc::new(Mutex::new(DSP::new(bins))), // TODO: extend to different formats?
-            device,
-            config,
-            stream: None,
-            status: false,
-        })
+    fn core(&self) -> Result<MutexGuard<'_, DSP>, Error> {
+        self.core
+            .lock()
+            .map_err(|_| Error::Worker(String::from("the analysis state is poisoned")))
     }
 
-    pub fn connect(&mut self) -> Result<(), Error> {
-        let err_fn = move |err| {
-            error!("An error ocurred on stream: {}", err);
-        };
-        let core_arc_clone = self.core.clone(); // local reference that is shared with the closure
-        let stream = self.device.build_input_stream(
-            &self.config,
-            move |data, _: &cpal::InputCallbackInfo| {
-                // note to self -> because rust moves all what closure captures, need a cloned Arc reference and thread safety -> Mutex
-                // stream events etc here
-                info!("Data received from CPAL, length {}", data.len());
-                if let Ok(core) = core_arc_clone.try_lock() {
-                    core.send(data);
-                }
-            },
-            err_fn,
-        )?;
+    fn session_header(&self) -> SessionHeader {
+        match self.core.lock() {
+            Ok(core) => core.session_header(),
+            Err(poisoned) => poisoned.into_inner().session_header(),
+        }
+    }
 
-        self.stream = Some(stream);
-        Ok(())
+    /// a pipeline for this stream, the stages without a value follow the spectrum settings
+    pub fn pipeline(&self, spec: &PipelineSpec) -> Result<Pipeline, Error> {
+        let header = self.session_header();
+        Pipeline::from_spec(spec, &header.settings, header.sample_rate)
     }
 
-    pub fn play(&self) -> Result<(), Error> {
-        match &self.stream {
-            Some(stream) => {
-                stream.play()?;
-                Ok(())
-            }
-            None => Err(Error::StreamOperation(StreamOp::Play)),
-        }
+    /// replaces the spectrum stages while running, see `DSP::set_pipeline`
+    pub fn set_pipeline(&self, pipeline: Pipeline) -> Result<(), Error> {
+        self.core()?.set_pipeline(pipeline)
     }
 
-    pub fn pause(&self) -> Result<(), Error> {
-        match &self.stream {
-            Some(stream) => {
-                stream.pause()?;
-                Ok(())
-            }
-            None => Err(Error::StreamOperation(StreamOp::Pause)),
-        }
+    /// the newest spectrum, None when nothing was analysed since the last call
+    pub fn get_processed_samples(&self) -> Option<SpectrumFrame> {
+        self.frames.take_latest()
+    }
+
+    /// the newest spectrum, also when it was already shown; for renderers that redraw on their
+    /// own schedule
+    pub fn get_latest_spectrum(&self) -> Option<SpectrumFrame> {
+        self.frames.latest()
     }
 
-    pub fn get_processed_samples(&self) -> Option<Vec<usize>> {
-        if let Ok(core) = self.core.try_lock() {
-            core.receive()
+    /// the newest spectrum of the equalized signal, None while set_post_eq is off
+    pub fn get_equalized_spectrum(&self) -> Option<SpectrumFrame> {
+        if self.post_eq() {
+            self.equalized.latest()
         } else {
             None
         }
     }
 
+    /// every spectrum frame in order, for consumers that cannot skip any
+    pub fn frames(&self) -> Arc<FrameQueue<SpectrumFrame>> {
+        self.frames.clone()
+    }
+
+    /// a queue of its own for every further consumer of the spectrum frames, e.g. an exporter
+    /// next to the renderer; each one decides with its policy what happens when it lags behind
+    pub fn subscribe_frames(
+        &self,
+        capacity: usize,
+        policy: LagPolicy,
+    ) -> Result<Arc<FrameQueue<SpectrumFrame>>, Error> {
+        Ok(self.core()?.subscribe(capacity, policy))
+    }
+
+    /// samples and frames lost because the analysis or a consumer lagged behind
+    pub fn get_flow_stats(&self) -> FlowStats {
+        let flow = match self.core.lock() {
+            Ok(core) => core.flow_stats(),
+            Err(poisoned) => poisoned.into_inner().flow_stats(),
+        };
+        FlowStats {
+            unfiltered_blocks: self.unfiltered.load(Ordering::Relaxed),
+            ..flow
+        }
+    }
+
+    /// the scope settings are shared with the DSP thread, front-ends adjust them directly
+    pub fn scope(&self) -> Arc<Mutex<Scope>> {
+        self.scope.clone()
+    }
+
+    pub fn get_waveform(&self) -> Option<Vec<f32>> {
+        self.scope.lock().ok().map(|scope| scope.capture())
+    }
+
+    pub fn get_stereo_image(&self) -> Option<StereoImage> {
+        self.goniometer
+            .lock()
+            .ok()
+            .map(|goniometer| goniometer.snapshot())
+    }
+
+    /// A4 reference and temperament are changed through the shared tuner
+    pub fn tuner(&self) -> Arc<Mutex<Tuner>> {
+        self.tuner.clone()
+    }
+
+    pub fn get_pitch(&self) -> Option<Reading> {
+        self.tuner.lock().ok().and_then(|tuner| tuner.reading())
+    }
+
+    pub fn chroma(&self) -> Arc<Mutex<Chroma>> {
+        self.chroma.clone()
+    }
+
+    pub fn get_chromagram(&self) -> Option<Chromagram> {
+        self.chroma.lock().ok().map(|chroma| chroma.snapshot())
+    }
+
+    pub fn get_tempo(&self) -> Option<Tempo> {
+        self.beat.lock().ok().map(|beat| beat.tempo())
+    }
+
+    /// onsets and beats as they are detected, for anything that wants to react to them; like
+    /// the spectrum frames each subscriber gets a bounded queue with its own lag policy
+    pub fn subscribe_beats(
+        &self,
+        capacity: usize,
+        policy: LagPolicy,
+    ) -> Option<Arc<FrameQueue<BeatEvent>>> {
+        self.beat
+            .lock()
+            .ok()
+            .map(|beat| beat.subscribe(capacity, policy))
+    }
+
     // function for processing data, need special AudioCORE
 
+    #[cfg(feature = "capture")]
     pub fn query() -> () {
         let available_hosts = cpal::available_hosts();
         error!("Available hosts: \n {:?}", available_hosts);
@@ -160,3 +923,103 @@ impl Equalizer {
         }
     }
 }
+
+// the next block of a capture; a device or sender that stops would otherwise keep record
+// waiting forever
+fn captured(receiver: &mpsc::Receiver<Vec<f32>>) -> Result<Vec<f32>, Error> {
+    match receiver.recv_timeout(STALL) {
+        Ok(data) => Ok(data),
+        Err(mpsc::RecvTimeoutError::Timeout) => Err(Error::NoInput(STALL)),
+        Err(mpsc::RecvTimeoutError::Disconnected) => Err(mpsc::RecvError.into()),
+    }
+}
+
+// the named host, the default one otherwise, and for a device source the named input device
+#[cfg(feature = "capture")]
+fn open_host(
+    device_name: &Option<String>,
+    host_name: &Option<String>,
+    source: &Source,
+) -> Result<(cpal::Host, Option<cpal::Device>), Error> {
+    let mut host = cpal::default_host(); // default host [ALSA]
+    if let Some(hostname) = host_name.as_ref() {
+        for h in cpal::available_hosts() {
+            if h.name() == hostname {
+                if let Ok(host_enum) = cpal::host_from_id(h) {
+                    host = host_enum;
+                } else {
+                    warn!("There is no such host, defaulting to ALSA");
+                }
+            }
+        }
+    }
+
+    let mut device: Option<cpal::Device> = None;
+
+    // the generator needs no device, it is only looked up for capturing
+    if let (Source::Device, Some(devicename)) = (source, device_name.as_ref()) {
+        device = device::find_input(&host, devicename);
+        info!("device {}", devicename);
+    }
+
+    if device.is_none() && *source == Source::Device {
+        return Err(Error::NoCpalDevice);
+    }
+    Ok((host, device))
+}
+
+#[cfg(not(feature = "capture"))]
+fn no_capture() -> Error {
+    Error::Config(String::from(
+        "rustalizer was built without the capture feature, there are no audio devices",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::equalizer::pcm::SampleFormat;
+    use crate::equalizer::rtp::Payload;
+
+    fn equalizer(source: Source) -> Equalizer {
+        Equalizer::new(&None, &None, &source, Settings::default(), 1, 48000).unwrap()
+    }
+
+    // the readers wait for input that never comes; once shutdown returns their threads are
+    // gone, and with them the callback and what it holds
+    #[cfg(unix)]
+    #[test]
+    fn shutdown_joins_silent_readers() {
+        let fifo = std::env::temp_dir().join(format!("rustalizer-silent-{}", std::process::id()));
+        let path = std::ffi::CString::new(fifo.to_str().unwrap()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(path.as_ptr(), 0o600) }, 0);
+        // a writer that never writes, reading never ends
+        let writer = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&fifo)
+            .unwrap();
+
+        let mut piped = equalizer(Source::Pipe {
+            path: Some(fifo.clone()),
+            format: SampleFormat::S16,
+        });
+        let mut received = equalizer(Source::Rtp {
+            address: String::from("127.0.0.1:0"),
+            payload: Payload::L16,
+            jitter: 40,
+        });
+        for equalizer in [&mut piped, &mut received].iter_mut() {
+            equalizer.connect().unwrap();
+            equalizer.play().unwrap();
+        }
+        std::thread::sleep(Duration::from_millis(50));
+        for equalizer in [&mut piped, &mut received].iter_mut() {
+            equalizer.shutdown().unwrap();
+            assert_eq!(Arc::strong_count(&equalizer.filters), 1);
+        }
+
+        drop(writer);
+        std::fs::remove_file(&fifo).unwrap();
+    }
+}
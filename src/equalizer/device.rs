@@ -0,0 +1,277 @@
+//! keeps a capture device going: a stream error or a playing stream that stops delivering marks
+//! the device as lost, reconnecting is then retried with a growing delay; the devices are looked
+//! up on a thread of their own
+
+#[cfg(feature = "capture")]
+use cpal::traits::{DeviceTrait, HostTrait};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+#[cfg(feature = "capture")]
+use std::sync::mpsc;
+use std::sync::Mutex;
+#[cfg(feature = "capture")]
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+const FIRST_RETRY: Duration = Duration::from_millis(250);
+const LAST_RETRY: Duration = Duration::from_secs(8); // the delay stops growing here
+
+/// what the front-ends show about the capture device
+#[derive(Clone, Debug, PartialEq)]
+pub enum DeviceStatus {
+    Connected {
+        name: String,
+        fallback: bool, // the default device, the requested one was not found
+    },
+    Lost {
+        error: String,
+        attempts: u32, // reconnections failed so far
+        retry_in: Duration,
+    },
+}
+
+impl std::fmt::Display for DeviceStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DeviceStatus::Connected { name, fallback } => {
+                write!(f, "capturing {}", name)?;
+                if *fallback {
+                    write!(f, " (default device)")?;
+                }
+                Ok(())
+            }
+            DeviceStatus::Lost {
+                error,
+                attempts,
+                retry_in,
+            } => {
+                write!(f, "input lost: {}, reconnecting", error)?;
+                if *attempts > 0 {
+                    write!(
+                        f,
+                        " in {:.1} s, {} attempts failed",
+                        retry_in.as_secs_f32(),
+                        attempts
+                    )?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// shared with the callbacks of one stream, a new stream gets a new one; the callers pass the
+/// time in
+pub struct Health {
+    started: Instant,
+    last_data: AtomicU64, // ms after started
+    playing: AtomicBool,
+    error: Mutex<Option<String>>,
+}
+
+impl Health {
+    pub fn new(now: Instant) -> Health {
+        Health {
+            started: now,
+            last_data: AtomicU64::new(0),
+            playing: AtomicBool::new(false),
+            error: Mutex::new(None),
+        }
+    }
+
+    /// from the data callback, never blocks
+    pub fn data(&self, now: Instant) {
+        let now = now.saturating_duration_since(self.started).as_millis() as u64;
+        self.last_data.store(now, Ordering::Relaxed);
+    }
+
+    /// from the error callback, the first error is kept until it is taken
+    pub fn error(&self, error: String) {
+        if let Ok(mut current) = self.error.lock() {
+            current.get_or_insert(error);
+        }
+    }
+
+    /// a stream that just started or stopped has not stalled
+    pub fn set_playing(&self, playing: bool, now: Instant) {
+        self.data(now);
+        self.playing.store(playing, Ordering::Relaxed);
+    }
+
+    /// why the stream is gone, if it is: its error or no data for longer than stall while it
+    /// plays; an error is only reported once
+    pub fn failure(&self, stall: Duration, now: Instant) -> Option<String> {
+        if let Some(error) = self.error.lock().ok().and_then(|mut error| error.take()) {
+            return Some(error);
+        }
+        let last_data = Duration::from_millis(self.last_data.load(Ordering::Relaxed));
+        let silent = now
+            .saturating_duration_since(self.started)
+            .saturating_sub(last_data);
+        if self.playing.load(Ordering::Relaxed) && silent > stall {
+            Some(format!("no data for {:.1} s", silent.as_secs_f32()))
+        } else {
+            None
+        }
+    }
+}
+
+/// when to try again, the first attempt is right away
+pub struct Backoff {
+    attempts: u32,
+    next: Instant,
+}
+
+impl Backoff {
+    pub fn new(now: Instant) -> Backoff {
+        Backoff {
+            attempts: 0,
+            next: now,
+        }
+    }
+
+    pub fn due(&self, now: Instant) -> bool {
+        now >= self.next
+    }
+
+    /// doubles the delay up to LAST_RETRY
+    pub fn failed(&mut self, now: Instant) {
+        let delay = FIRST_RETRY
+            .checked_mul(1 << self.attempts.min(16))
+            .map_or(LAST_RETRY, |delay| delay.min(LAST_RETRY));
+        self.next = now + delay;
+        self.attempts += 1;
+    }
+
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    pub fn retry_in(&self, now: Instant) -> Duration {
+        self.next.saturating_duration_since(now)
+    }
+}
+
+/// the input device of the host with that name
+#[cfg(feature = "capture")]
+pub fn find_input(host: &cpal::Host, name: &str) -> Option<cpal::Device> {
+    host.input_devices()
+        .ok()?
+        .find(|device| matches!(device.name(), Ok(device_name) if device_name == name))
+}
+
+/// what a search came up with
+#[cfg(feature = "capture")]
+pub enum Searched {
+    Looking,
+    Found {
+        device: cpal::Device,
+        requested: bool, // false for the default device
+    },
+    Missing,
+}
+
+/// one lookup of an input device; enumerating them can take a while, so it runs on a thread of
+/// its own and is polled
+#[cfg(feature = "capture")]
+pub struct Search {
+    found: mpsc::Receiver<Option<(cpal::Device, bool)>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+#[cfg(feature = "capture")]
+impl Search {
+    /// looks for the named device of the host, and unless only_requested for its default
+    /// device when that one is missing
+    pub fn start(host: cpal::HostId, name: Option<String>, only_requested: bool) -> Search {
+        let (sender, found) = mpsc::channel();
+        let worker = thread::spawn(move || {
+            let host = match cpal::host_from_id(host) {
+                Ok(host) => host,
+                Err(_) => return,
+            };
+            let requested = name
+                .as_ref()
+                .and_then(|name| find_input(&host, name))
+                .map(|device| (device, true));
+            let device = match requested {
+                None if !only_requested => {
+                    host.default_input_device().map(|device| (device, false))
+                }
+                requested => requested,
+            };
+            // nobody waits for it any more when the search was dropped
+            let _ = sender.send(device);
+        });
+        Search {
+            found,
+            worker: Some(worker),
+        }
+    }
+
+    /// never blocks
+    pub fn searched(&self) -> Searched {
+        match self.found.try_recv() {
+            Ok(Some((device, requested))) => Searched::Found { device, requested },
+            Ok(None) | Err(mpsc::TryRecvError::Disconnected) => Searched::Missing,
+            Err(mpsc::TryRecvError::Empty) => Searched::Looking,
+        }
+    }
+}
+
+#[cfg(feature = "capture")]
+impl Drop for Search {
+    fn drop(&mut self) {
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_up_to_the_limit() {
+        let now = Instant::now();
+        let mut backoff = Backoff::new(now);
+        assert!(backoff.due(now));
+        let delays: Vec<Duration> = (0..8)
+            .map(|_| {
+                backoff.failed(now);
+                backoff.retry_in(now)
+            })
+            .collect();
+        assert_eq!(delays[0], FIRST_RETRY);
+        assert_eq!(delays[1], FIRST_RETRY * 2);
+        assert_eq!(delays[7], LAST_RETRY);
+        assert!(!backoff.due(now));
+        assert_eq!(backoff.attempts(), 8);
+    }
+
+    #[test]
+    fn health_reports_errors_once_and_stalls_while_playing() {
+        let started = Instant::now();
+        let at = |ms| started + Duration::from_millis(ms);
+        let stall = Duration::from_secs(1);
+        let health = Health::new(started);
+        health.error(String::from("device gone"));
+        health.error(String::from("later"));
+        assert_eq!(
+            health.failure(stall, at(0)),
+            Some(String::from("device gone"))
+        );
+        assert_eq!(health.failure(stall, at(0)), None);
+
+        // paused, silence is fine
+        assert_eq!(health.failure(stall, at(2000)), None);
+        health.set_playing(true, at(2000));
+        assert_eq!(health.failure(stall, at(2500)), None);
+        assert_eq!(
+            health.failure(stall, at(3500)),
+            Some(String::from("no data for 1.5 s"))
+        );
+        health.data(at(3500));
+        assert_eq!(health.failure(stall, at(4000)), None);
+    }
+}
@@ -1,94 +1,750 @@
-mod fft;
-mod window;
+//! the analysis thread: every block of samples sent to it feeds the spectrum, the scope, the
+//! vectorscope, the tuner, the chromagram and the beat tracker
 
-use std::cell::Cell;
-use std::sync::mpsc;
+pub mod beat;
+pub mod biquad;
+pub mod chroma;
+pub mod convolver;
+pub mod fft;
+pub mod graphic;
+pub mod pipeline;
+mod pitch;
+pub mod psd;
+pub mod queue;
+pub mod scope;
+pub mod spectrum;
+pub mod stereo;
+pub mod tuner;
+pub mod window;
+
+use crate::equalizer::session::{SessionHeader, SessionWriter};
+use crate::errors::Error;
+use beat::BeatTracker;
+use chroma::Chroma;
+use pipeline::Pipeline;
+use queue::{sample_queue, FrameHub, FrameQueue, LagPolicy, SampleConsumer, SampleProducer};
+use scope::Scope;
+use spectrum::{Analyzer, Band, Settings, SpectrumFrame, Weighting, MIN_DB};
+use std::fs::File;
+use std::io::BufWriter;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
+use std::time::Duration;
+use stereo::Goniometer;
+use tuner::Tuner;
+use window::WindowType;
+
+/// spectrum frames are saved here while a session is being recorded
+pub type SessionSlot = Arc<Mutex<Option<SessionWriter<BufWriter<File>>>>>;
 
+// what the worker is told between two blocks of samples
 enum Message {
-    Raw(Vec<Cell<f32>>),
-    Processed(Vec<usize>),
+    Input(SampleConsumer),
+    EqualizedInput(SampleConsumer),
+    Pipeline(Pipeline),
+    // the spectrum controls
+    SetBins(Option<usize>),
+    SetWindow(WindowType),
+    SetFftSize(usize),
+    SetWeighting(Weighting),
+    SetSmoothing(Option<f32>),
     Terminate,
 }
 
+const FRAME_QUEUE: usize = 64; // spectrum frames kept for the front-end lagging behind
+const IDLE: Duration = Duration::from_millis(10); // the worker sleeps at most this long between blocks
+
+/// what was lost between the threads so far
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct FlowStats {
+    pub dropped_samples: u64, // did not fit in the input queue, the worker lagged behind
+    pub dropped_frames: u64, // spectrum frames pushed out of the front-end queue before it took them
+    pub unfiltered_blocks: u64, // went through without the filters while they were being changed
+}
+
+/// owns the analysis thread, the analysers are shared with the front-ends behind mutexes
 pub struct DSP {
     worker: Option<thread::JoinHandle<()>>,
-    data_in_sender: mpsc::Sender<Message>, // TODO: change it to a generics, need traits?
-    data_out_receiver: mpsc::Receiver<Message>,
-    window_type: window::WindowType, // there is an idea for runtime-changeable window_type
-                                     // add windowing function closure?
+    sender: mpsc::Sender<Message>,
+    channels: usize,
+    sample_rate: u32,
+    hub: Arc<FrameHub<SpectrumFrame>>,
+    frames: Arc<FrameQueue<SpectrumFrame>>, // the front-end's subscription
+    equalized: Arc<FrameQueue<SpectrumFrame>>,
+    dropped_samples: Arc<AtomicU64>,
+    scope: Arc<Mutex<Scope>>,
+    goniometer: Arc<Mutex<Goniometer>>,
+    tuner: Arc<Mutex<Tuner>>,
+    chroma: Arc<Mutex<Chroma>>,
+    beat: Arc<Mutex<BeatTracker>>,
+    session: SessionSlot,
+    session_header: Arc<Mutex<SessionHeader>>, // follows the spectrum controls
+    failure: Option<String>,                   // why the worker stopped on its own
+    shut_down: bool,
+}
+
+// everything the analysis thread owns
+struct Worker {
+    channels: usize,
+    sample_rate: u32,
+    analyzer: Analyzer,
+    bands: Arc<[Band]>,
+    sequence: u64,
+    input: Option<SampleConsumer>,
+    block: Vec<f32>, // allocated once, a whole number of interleaved frames
+    hub: Arc<FrameHub<SpectrumFrame>>,
+    // the same spectrum of the equalized signal, next to the captured one
+    equalized_input: Option<SampleConsumer>,
+    equalized_analyzer: Analyzer,
+    equalized_sequence: u64,
+    equalized: Arc<FrameQueue<SpectrumFrame>>,
+    scope: Arc<Mutex<Scope>>,
+    goniometer: Arc<Mutex<Goniometer>>,
+    tuner: Arc<Mutex<Tuner>>,
+    chroma: Arc<Mutex<Chroma>>,
+    beat: Arc<Mutex<BeatTracker>>,
+    session: SessionSlot,
+    session_header: Arc<Mutex<SessionHeader>>,
 }
 
 impl DSP {
-    pub fn new(bins: Option<usize>) -> DSP {
-        let (data_in_sender, data_in_receiver) = mpsc::channel();
-        let (data_out_sender, data_out_receiver) = mpsc::channel();
-
-        let thread = thread::spawn(move || loop {
-            // This could be made async?
-            let data = data_in_receiver.recv().unwrap();
-
-            match data {
-                Message::Raw(payload) => {
-                    // TODO: can separate L/R channels? try some more advanced stuff later?
-                    info!("Received data for processing in DSP");
-                    // pass to fft
-                    let fft_data = fft::fft(payload);
-
-                    // bin the processed samples to several bins
-                    let binned = fft::to_bins(fft_data, bins);
-                    if binned.is_ok() {
-                        if let Err(err) = data_out_sender.send(Message::Processed(binned.unwrap()))
-                        {
-                            error!("Failed to send data to DSP: {}", err); //TODO: add timing and debug
-                        }
-                    } else {
-                        error!("{}", binned.err().unwrap());
+    /// starts the worker, it analyses what is pushed to the queue from `input`
+    pub fn new(settings: Settings, sample_rate: u32, channels: usize) -> DSP {
+        let (sender, receiver) = mpsc::channel();
+        let analyzer = Analyzer::new(settings, sample_rate);
+        let session_header = Arc::new(Mutex::new(SessionHeader {
+            sample_rate,
+            channels: channels as u16,
+            settings,
+            bands: analyzer.bands(),
+        }));
+        let worker = Worker {
+            channels,
+            sample_rate,
+            bands: analyzer.centred_bands().into(),
+            analyzer,
+            sequence: 0,
+            input: None,
+            block: vec![0.; 4096 * channels.max(1)],
+            hub: Arc::new(FrameHub::new()),
+            equalized_input: None,
+            equalized_analyzer: Analyzer::new(settings, sample_rate),
+            equalized_sequence: 0,
+            equalized: Arc::new(FrameQueue::new(FRAME_QUEUE)),
+            scope: Arc::new(Mutex::new(Scope::new(sample_rate))),
+            goniometer: Arc::new(Mutex::new(Goniometer::new())),
+            tuner: Arc::new(Mutex::new(Tuner::new(sample_rate))),
+            chroma: Arc::new(Mutex::new(Chroma::new(sample_rate))),
+            beat: Arc::new(Mutex::new(BeatTracker::new(sample_rate))),
+            session: Arc::new(Mutex::new(None)),
+            session_header: session_header.clone(),
+        };
+        let hub = worker.hub.clone();
+        let frames = hub.subscribe(FRAME_QUEUE, LagPolicy::DropOldest);
+        let equalized = worker.equalized.clone();
+        let scope = worker.scope.clone();
+        let goniometer = worker.goniometer.clone();
+        let tuner = worker.tuner.clone();
+        let chroma = worker.chroma.clone();
+        let beat = worker.beat.clone();
+        let session = worker.session.clone();
+
+        DSP {
+            worker: Some(thread::spawn(move || worker.run(receiver))),
+            sender,
+            channels,
+            sample_rate,
+            hub,
+            frames,
+            equalized,
+            dropped_samples: Arc::new(AtomicU64::new(0)),
+            scope,
+            goniometer,
+            tuner,
+            chroma,
+            beat,
+            session,
+            session_header,
+            failure: None,
+            shut_down: false,
+        }
+    }
+
+    /// a new queue from an input to the worker, which stops reading the previous one; it holds
+    /// about a second of audio and the samples that do not fit are dropped and counted
+    pub fn input(&self) -> SampleProducer {
+        let (producer, consumer) = self.sample_queue();
+        // only fails when the worker is gone, the producer then fills up and drops everything
+        let _ = self.sender.send(Message::Input(consumer));
+        producer
+    }
+
+    /// like input, for the equalized signal; its spectrum goes to `equalized` and the other
+    /// analysers only look at the captured signal
+    pub fn equalized_input(&self) -> SampleProducer {
+        let (producer, consumer) = self.sample_queue();
+        let _ = self.sender.send(Message::EqualizedInput(consumer));
+        producer
+    }
+
+    fn sample_queue(&self) -> (SampleProducer, SampleConsumer) {
+        let worker = self.worker.as_ref().map(|worker| worker.thread().clone());
+        sample_queue(
+            self.sample_rate as usize * self.channels.max(1),
+            self.dropped_samples.clone(),
+            worker,
+        )
+    }
+
+    pub fn flow_stats(&self) -> FlowStats {
+        FlowStats {
+            dropped_samples: self.dropped_samples.load(Ordering::Relaxed),
+            dropped_frames: self.frames.dropped(),
+            unfiltered_blocks: 0, // counted by the input callback
+        }
+    }
+
+    /// every spectrum frame in order, up to the newest `FRAME_QUEUE` ones
+    pub fn frames(&self) -> Arc<FrameQueue<SpectrumFrame>> {
+        self.frames.clone()
+    }
+
+    /// the spectrum of the equalized signal, with the same settings as the captured one; a
+    /// pipeline given to set_pipeline only replaces the stages of the captured one
+    pub fn equalized(&self) -> Arc<FrameQueue<SpectrumFrame>> {
+        self.equalized.clone()
+    }
+
+    /// another queue receiving every spectrum frame from now on, next to the front-end's; it
+    /// lasts until it is dropped or closed
+    pub fn subscribe(&self, capacity: usize, policy: LagPolicy) -> Arc<FrameQueue<SpectrumFrame>> {
+        self.hub.subscribe(capacity, policy)
+    }
+
+    pub fn scope(&self) -> Arc<Mutex<Scope>> {
+        self.scope.clone()
+    }
+
+    pub fn goniometer(&self) -> Arc<Mutex<Goniometer>> {
+        self.goniometer.clone()
+    }
+
+    pub fn tuner(&self) -> Arc<Mutex<Tuner>> {
+        self.tuner.clone()
+    }
+
+    pub fn chroma(&self) -> Arc<Mutex<Chroma>> {
+        self.chroma.clone()
+    }
+
+    pub fn beat(&self) -> Arc<Mutex<BeatTracker>> {
+        self.beat.clone()
+    }
+
+    pub fn session(&self) -> SessionSlot {
+        self.session.clone()
+    }
+
+    /// the bands and stream parameters every recorded session starts with, up to date with the
+    /// controls applied so far
+    pub fn session_header(&self) -> SessionHeader {
+        match self.session_header.lock() {
+            Ok(header) => header.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+
+    pub fn settings(&self) -> Settings {
+        self.session_header().settings
+    }
+
+    /// linear bins, or the third octaves for None
+    pub fn set_bins(&self, bins: Option<usize>) -> Result<(), Error> {
+        let settings = Settings {
+            bins,
+            ..self.settings()
+        };
+        self.control(Message::SetBins(bins), settings)
+    }
+
+    pub fn set_window(&self, window: WindowType) -> Result<(), Error> {
+        let settings = Settings {
+            window,
+            ..self.settings()
+        };
+        self.control(Message::SetWindow(window), settings)
+    }
+
+    /// the samples already buffered are kept, the next frame comes with the new size
+    pub fn set_fft_size(&self, fft_size: usize) -> Result<(), Error> {
+        let settings = Settings {
+            fft_size,
+            ..self.settings()
+        };
+        self.control(Message::SetFftSize(fft_size), settings)
+    }
+
+    pub fn set_weighting(&self, weighting: Weighting) -> Result<(), Error> {
+        let settings = Settings {
+            weighting,
+            ..self.settings()
+        };
+        self.control(Message::SetWeighting(weighting), settings)
+    }
+
+    /// None turns the smoothing off
+    pub fn set_smoothing(&self, smoothing: Option<f32>) -> Result<(), Error> {
+        let settings = Settings {
+            smoothing,
+            ..self.settings()
+        };
+        self.control(Message::SetSmoothing(smoothing), settings)
+    }
+
+    // checked here so that the caller hears about bad values, the worker applies them in order
+    fn control(&self, message: Message, settings: Settings) -> Result<(), Error> {
+        settings.validate().map_err(Error::Config)?;
+        self.send(message)
+    }
+
+    // the worker only goes away when it panicked or was shut down
+    fn send(&self, message: Message) -> Result<(), Error> {
+        self.sender
+            .send(message)
+            .map_err(|_| Error::Worker(String::from("the analysis thread is not running")))
+    }
+
+    /// swaps the spectrum stages from the next frame on; the bands are in every session header
+    /// and the frames already sent, so they have to stay the same
+    pub fn set_pipeline(&self, pipeline: Pipeline) -> Result<(), Error> {
+        let edges: Vec<(f32, f32)> = pipeline
+            .bands()
+            .iter()
+            .map(|band| (band.lower, band.upper))
+            .collect();
+        if edges != self.session_header().bands {
+            return Err(Error::Config(String::from(
+                "The pipeline has to keep the bands of the spectrum",
+            )));
+        }
+        self.send(Message::Pipeline(pipeline))
+    }
+
+    /// the newest spectrum, None when there is nothing new; never waits for the worker
+    pub fn receive(&self) -> Option<SpectrumFrame> {
+        self.frames.take_latest()
+    }
+
+    /// the newest spectrum, whether it was received or not
+    pub fn latest(&self) -> Option<SpectrumFrame> {
+        self.frames.latest()
+    }
+
+    /// an error once the worker failed, it only stops on its own when it panicked
+    pub fn check(&mut self) -> Result<(), Error> {
+        if self
+            .worker
+            .as_ref()
+            .map_or(false, |worker| worker.is_finished())
+        {
+            if let Some(worker) = self.worker.take() {
+                let failure = join(worker).err();
+                self.failure = Some(failure.unwrap_or_else(|| String::from("stopped")));
+            }
+        }
+        self.failure()
+    }
+
+    /// lets the worker analyse what is still queued, then joins it; stop the input first so
+    /// that the queue stays drained. The error is the worker's failure, also when check saw it;
+    /// only the first call does anything
+    pub fn shutdown(&mut self) -> Result<(), Error> {
+        if self.shut_down {
+            return Ok(());
+        }
+        self.shut_down = true;
+        info!("Closing the DSP backend, joining thread.");
+        if let Some(worker) = self.worker.take() {
+            // fails when the worker is gone already, joining tells why
+            let _ = self.sender.send(Message::Terminate);
+            worker.thread().unpark();
+            if let Err(failure) = join(worker) {
+                self.failure = Some(failure);
+            }
+        }
+        self.failure()
+    }
+
+    fn failure(&self) -> Result<(), Error> {
+        match &self.failure {
+            Some(failure) => Err(Error::Worker(failure.clone())),
+            None => Ok(()),
+        }
+    }
+}
+
+// the message of a panic of the worker, when it has one
+fn join(worker: thread::JoinHandle<()>) -> Result<(), String> {
+    worker
+        .join()
+        .map_err(|panic| match panic.downcast_ref::<&str>() {
+            Some(message) => message.to_string(),
+            None => panic
+                .downcast_ref::<String>()
+                .cloned()
+                .unwrap_or_else(|| String::from("panicked")),
+        })
+}
+
+impl Worker {
+    fn run(mut self, receiver: mpsc::Receiver<Message>) {
+        loop {
+            let mut idle = true;
+            loop {
+                match receiver.try_recv() {
+                    Ok(Message::Terminate) => return self.drain(),
+                    Err(mpsc::TryRecvError::Disconnected) => return,
+                    Ok(message) => {
+                        self.control(message);
+                        idle = false;
                     }
+                    Err(mpsc::TryRecvError::Empty) => break,
                 }
-                Message::Terminate | Message::Processed(_) => {
-                    break;
-                }
             }
-        }); //TODO: fill the thread's processing pipeline -> receive from queue, pass through processes, push to receiver
+            let mut block = std::mem::take(&mut self.block);
+            let count = match self.input.as_mut() {
+                Some(input) => input.pop(&mut block),
+                None => 0,
+            };
+            if count > 0 {
+                self.analyse(&block[..count]);
+                idle = false;
+            }
+            let count = match self.equalized_input.as_mut() {
+                Some(input) => input.pop(&mut block),
+                None => 0,
+            };
+            if count > 0 {
+                self.analyse_equalized(&block[..count]);
+                idle = false;
+            }
+            self.block = block;
+            if idle {
+                // woken up by the input as soon as it pushes
+                thread::park_timeout(IDLE);
+            }
+        }
+    }
 
-        DSP {
-            worker: Some(thread),
-            data_in_sender: data_in_sender,
-            data_out_receiver: data_out_receiver,
-            window_type: window::WindowType::Hann,
+    // the rest of the input, before the worker stops
+    fn drain(&mut self) {
+        let mut block = std::mem::take(&mut self.block);
+        while let Some(count) = self
+            .input
+            .as_mut()
+            .map(|input| input.pop(&mut block))
+            .filter(|count| *count > 0)
+        {
+            self.analyse(&block[..count]);
         }
+        while let Some(count) = self
+            .equalized_input
+            .as_mut()
+            .map(|input| input.pop(&mut block))
+            .filter(|count| *count > 0)
+        {
+            self.analyse_equalized(&block[..count]);
+        }
+        self.block = block;
     }
 
-    //send method -> on callback from the application
-    pub fn send(&self, data: &[f32]) {
-        // copy the data and already extend it
-        info!("Sending data to DSP mpsc");
-        self.data_in_sender
-            .send(Message::Raw(fft::prepare_data(
-                data,
-                data.len(),
-                window::choose_window(self.window_type.clone()), // window the data prior to FFTing (TODO: maybe some kind of composable pipeline of actions? it would make it easier in the future)
-            )))
-            .expect("Could not send data via MPSC from the CPAL core");
+    fn analyse(&mut self, interleaved: &[f32]) {
+        info!("Received data for processing in DSP");
+        if let Ok(mut goniometer) = self.goniometer.lock() {
+            goniometer.feed(interleaved, self.channels);
+        }
+
+        // the rest of the analysis looks at the channels mixed together
+        let payload = downmix(interleaved, self.channels);
+        if let Ok(mut scope) = self.scope.lock() {
+            scope.feed(&payload);
+        }
+        if let Ok(mut tuner) = self.tuner.lock() {
+            tuner.feed(&payload);
+        }
+        if let Ok(mut chroma) = self.chroma.lock() {
+            chroma.feed(&payload);
+        }
+        if let Ok(mut beat) = self.beat.lock() {
+            beat.feed(&payload);
+        }
+
+        // a block can complete several frames, or none
+        let frames = self.analyzer.feed(&payload);
+        if let Ok(mut session) = self.session.lock() {
+            if let Some(writer) = session.as_mut() {
+                for frame in frames.iter() {
+                    if let Err(err) = writer.write(frame) {
+                        error!("Session recording stopped: {}", err);
+                        *session = None;
+                        break;
+                    }
+                }
+            }
+        }
+        let settings = self.analyzer.settings();
+        for frame in frames {
+            self.hub.publish(SpectrumFrame::new(
+                frame,
+                self.bands.clone(),
+                &settings,
+                self.sample_rate,
+                self.sequence,
+            ));
+            self.sequence += 1;
+        }
     }
 
-    pub fn receive(&self) -> Option<Vec<usize>> {
-        match self.data_out_receiver.recv().unwrap() {
-            Message::Processed(payload) => Some(payload),
-            Message::Terminate | Message::Raw(_) => None, // will not happen?
+    fn analyse_equalized(&mut self, interleaved: &[f32]) {
+        let frames = self
+            .equalized_analyzer
+            .feed(&downmix(interleaved, self.channels));
+        let settings = self.equalized_analyzer.settings();
+        for frame in frames {
+            self.equalized.push(SpectrumFrame::new(
+                frame,
+                self.bands.clone(),
+                &settings,
+                self.sample_rate,
+                self.equalized_sequence,
+            ));
+            self.equalized_sequence += 1;
         }
     }
+
+    fn control(&mut self, message: Message) {
+        let current = self.analyzer.settings();
+        let settings = match message {
+            Message::Input(input) => {
+                self.input = Some(input);
+                return;
+            }
+            Message::EqualizedInput(input) => {
+                self.equalized_input = Some(input);
+                return;
+            }
+            Message::Pipeline(pipeline) => {
+                if let Err(err) = self.analyzer.set_pipeline(pipeline) {
+                    error!("The pipeline was not changed: {}", err);
+                }
+                return;
+            }
+            Message::SetBins(bins) => Settings { bins, ..current },
+            Message::SetWindow(window) => Settings { window, ..current },
+            Message::SetFftSize(fft_size) => Settings {
+                fft_size,
+                ..current
+            },
+            Message::SetWeighting(weighting) => Settings {
+                weighting,
+                ..current
+            },
+            Message::SetSmoothing(smoothing) => Settings {
+                smoothing,
+                ..current
+            },
+            Message::Terminate => return,
+        };
+
+        // the frames sent so far keep the old bands, the next ones get the new ones
+        if let Err(err) = self.analyzer.reconfigure(settings) {
+            error!("The spectrum settings were not changed: {}", err);
+            return;
+        }
+        // the same settings, they only fail if the main one failed
+        if let Err(err) = self.equalized_analyzer.reconfigure(settings) {
+            error!("The equalized spectrum settings were not changed: {}", err);
+        }
+        self.bands = self.analyzer.centred_bands().into();
+        if let Ok(mut header) = self.session_header.lock() {
+            header.settings = settings;
+            header.bands = self.analyzer.bands();
+        }
+        // a session file has one header, it ends where the spectrum changes
+        if let Ok(mut session) = self.session.lock() {
+            if let Some(writer) = session.take() {
+                info!("The spectrum settings changed, session recording stopped");
+                if let Err(err) = writer.finish() {
+                    error!("Session recording failed: {}", err);
+                }
+            }
+        }
+    }
+}
+
+/// maps dB to the bar heights the graph and the bar chart were tuned for, MIN_DB..0 to 0..3000
+pub fn display_level(db: f32) -> usize {
+    ((db - MIN_DB) / -MIN_DB * 3000.).max(0.) as usize
+}
+
+/// averages interleaved frames into a single channel, an incomplete last frame is dropped
+pub fn downmix(data: &[f32], channels: usize) -> Vec<f32> {
+    if channels <= 1 {
+        return data.to_vec();
+    }
+    data.chunks_exact(channels)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        .collect()
 }
 
 impl Drop for DSP {
     fn drop(&mut self) {
-        info!("Closing the DSP backend, joining thread.");
+        // nothing left to do after Equalizer::shutdown
+        if let Err(err) = self.shutdown() {
+            error!("{}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pipeline::{Domain, Stage};
+    use std::time::Instant;
+
+    // polls until the worker sent a frame
+    fn next_frame(dsp: &DSP) -> SpectrumFrame {
+        let start = Instant::now();
+        loop {
+            if let Some(frame) = dsp.receive() {
+                return frame;
+            }
+            assert!(start.elapsed() < Duration::from_secs(5), "no frame");
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn mixes_channels() {
+        assert_eq!(downmix(&[1., 0., 0.5, 0.5, 1.], 2), vec![0.5, 0.5]);
+        assert_eq!(downmix(&[1., 0.5], 1), vec![1., 0.5]);
+    }
+
+    #[test]
+    fn controls_apply_between_frames() {
+        let dsp = DSP::new(Settings::default(), 44100, 1);
+        let mut input = dsp.input();
+        input.push(&[0.; 2048]);
+        assert_eq!(next_frame(&dsp).magnitudes.len(), 31);
+
+        dsp.set_bins(Some(8)).unwrap();
+        dsp.set_fft_size(1024).unwrap();
+        dsp.set_smoothing(Some(0.5)).unwrap();
+        input.push(&[0.; 2048]);
+        let frame = next_frame(&dsp);
+        assert_eq!(frame.magnitudes.len(), 8);
+        assert_eq!(frame.bands.len(), 8);
+        assert_eq!(frame.fft_size, 1024);
+        let header = dsp.session_header();
+        assert_eq!(header.bands.len(), 8);
+        assert_eq!(header.settings.smoothing, Some(0.5));
 
-        self.data_in_sender.send(Message::Terminate).unwrap();
+        assert!(dsp.set_fft_size(1000).is_err());
+        assert!(dsp.set_smoothing(Some(1.)).is_err());
+        assert_eq!(dsp.settings().fft_size, 1024);
+    }
+
+    struct Failing;
+
+    impl Stage for Failing {
+        fn name(&self) -> &str {
+            "failing"
+        }
+
+        fn accepts(&self, _: Domain) -> bool {
+            true
+        }
+
+        fn process(&mut self, _: &mut Vec<f32>) {
+            panic!("stage failed");
+        }
+    }
+
+    #[test]
+    fn analyses_the_equalized_signal_apart() {
+        let dsp = DSP::new(Settings::default(), 44100, 1);
+        let mut input = dsp.input();
+        let mut equalized = dsp.equalized_input();
+        input.push(&[0.; 2048]);
+        let tone: Vec<f32> = (0..2048)
+            .map(|i| (2. * std::f32::consts::PI * 1000. * i as f32 / 44100.).sin())
+            .collect();
+        equalized.push(&tone);
+        let captured = next_frame(&dsp);
+        let start = Instant::now();
+        let equalized = loop {
+            if let Some(frame) = dsp.equalized().pop() {
+                break frame;
+            }
+            assert!(start.elapsed() < Duration::from_secs(5), "no frame");
+            thread::sleep(Duration::from_millis(5));
+        };
+        assert_eq!(equalized.bands, captured.bands);
+        let loudest =
+            |frame: &SpectrumFrame| frame.magnitudes.iter().cloned().fold(MIN_DB, f32::max);
+        assert_eq!(loudest(&captured), MIN_DB);
+        assert!(loudest(&equalized) > -10.);
+    }
+
+    #[test]
+    fn worker_panics_become_errors() {
+        let mut dsp = DSP::new(Settings::default(), 44100, 1);
+        let mut pipeline = Pipeline::from_settings(&dsp.settings(), 44100);
+        pipeline.insert(0, Box::new(Failing)).unwrap();
+        dsp.set_pipeline(pipeline).unwrap();
+        let mut input = dsp.input();
+        input.push(&[0.; 2048]);
+        let start = Instant::now();
+        while dsp.check().is_ok() {
+            assert!(start.elapsed() < Duration::from_secs(5), "still running");
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert!(dsp.set_bins(Some(8)).is_err());
+        match dsp.shutdown() {
+            Err(Error::Worker(message)) => assert_eq!(message, "stage failed"),
+            other => panic!("{:?}", other),
+        }
+        // reported once, dropping the DSP shuts it down again
+        assert!(dsp.shutdown().is_ok());
+    }
+
+    #[test]
+    fn shutdown_drains_the_input() {
+        let mut dsp = DSP::new(Settings::default(), 44100, 1);
+        let exporter = dsp.subscribe(16, LagPolicy::DropOldest);
+        let mut input = dsp.input();
+        input.push(&[0.; 8192]);
+        dsp.shutdown().unwrap();
+        assert_eq!(exporter.pop().map(|frame| frame.sequence), Some(0));
+        assert!(dsp.check().is_ok());
+        assert!(dsp.set_bins(Some(8)).is_err());
+    }
 
-        if let Some(thread) = self.worker.take() {
-            thread.join().unwrap();
+    #[test]
+    fn every_subscriber_gets_every_frame() {
+        let dsp = DSP::new(Settings::default(), 44100, 1);
+        let exporter = dsp.subscribe(16, LagPolicy::Disconnect);
+        let mut input = dsp.input();
+        input.push(&[0.; 4096]);
+        let taken = next_frame(&dsp);
+        let mut exported = Vec::new();
+        while let Some(frame) = exporter.pop_timeout(Duration::from_millis(200)) {
+            exported.push(frame.sequence);
         }
+        // the front-end skips to the newest frame, the exporter keeps them all
+        assert!(exported.len() >= 2);
+        assert!(exported.iter().copied().eq(0..exported.len() as u64));
+        assert!(exported.contains(&taken.sequence));
     }
 }
@@ -0,0 +1,334 @@
+//! onset detection (spectral flux) and tempo tracking (autocorrelation of the onset envelope)
+
+use super::fft;
+use super::queue::{FrameHub, FrameQueue, LagPolicy};
+use super::window::{choose_window, WindowType};
+use std::sync::Arc;
+
+const FRAME: usize = 1024;
+const HOP: usize = 512; // ~86 STFT frames per second at 44.1 kHz
+const COMPRESSION: f32 = 100.; // log(1 + C * |X|) makes the flux less dominated by loud bins
+const THRESHOLD_WINDOW: usize = 16; // frames taken into account by the adaptive threshold
+const THRESHOLD_MULTIPLIER: f32 = 1.5;
+const THRESHOLD_DELTA: f32 = 0.5;
+const MIN_ONSET_GAP: f32 = 0.05; // seconds
+const TEMPO_HISTORY: f32 = 6.; // seconds of onset envelope used for the tempo estimate
+const TEMPO_UPDATE: usize = 16; // frames between tempo estimates
+const MIN_BPM: f32 = 60.;
+const MAX_BPM: f32 = 200.;
+const PREFERRED_BPM: f32 = 120.; // centre of the tempo prior, resolves octave ambiguity
+const PHASE_TOLERANCE: f32 = 0.2; // fraction of a beat period in which onsets pull the phase
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BeatEvent {
+    // time is in seconds since the start of the stream
+    Onset { time: f32, strength: f32 },
+    Beat { time: f32, bpm: f32 },
+}
+
+/// current state for the front-ends
+#[derive(Copy, Clone, Debug)]
+pub struct Tempo {
+    pub bpm: Option<f32>,
+    pub phase: f32, // 0 on the beat, going up to 1 just before the next one
+}
+
+pub struct BeatTracker {
+    buffer: Vec<f32>,
+    frame_rate: f32, // STFT frames per second
+    frame: usize,
+    previous: Vec<f32>,
+    flux: Vec<f32>,
+    last_onset: Option<usize>,
+    next_estimate: usize, // frame of the next tempo estimate
+    period: Option<f32>,  // in frames
+    last_beat: f32,       // in frames, fractional so the period does not drift
+    events: FrameHub<BeatEvent>,
+}
+
+impl BeatTracker {
+    pub fn new(sample_rate: u32) -> BeatTracker {
+        BeatTracker {
+            buffer: Vec::with_capacity(FRAME + HOP),
+            frame_rate: sample_rate as f32 / HOP as f32,
+            frame: 0,
+            previous: Vec::new(),
+            flux: Vec::new(),
+            last_onset: None,
+            next_estimate: 0,
+            period: None,
+            last_beat: 0.,
+            events: FrameHub::new(),
+        }
+    }
+
+    /// every subscriber receives all onsets and beats from now on, in a queue of its own that
+    /// keeps at most capacity of them; policy decides what happens when it lags behind
+    pub fn subscribe(&self, capacity: usize, policy: LagPolicy) -> Arc<FrameQueue<BeatEvent>> {
+        self.events.subscribe(capacity, policy)
+    }
+
+    pub fn feed(&mut self, samples: &[f32]) {
+        self.buffer.extend_from_slice(samples);
+        while self.buffer.len() >= FRAME {
+            let spectrum = magnitudes(&self.buffer[..FRAME]);
+            self.buffer.drain(..HOP);
+            self.process(spectrum);
+        }
+    }
+
+    pub fn tempo(&self) -> Tempo {
+        let phase = match self.period {
+            Some(period) => ((self.frame as f32 - self.last_beat) / period).rem_euclid(1.),
+            None => 0.,
+        };
+        Tempo {
+            bpm: self.period.map(|p| 60. * self.frame_rate / p),
+            phase,
+        }
+    }
+
+    fn process(&mut self, spectrum: Vec<f32>) {
+        // spectral flux, only rising energy counts
+        let flux = if self.previous.is_empty() {
+            0.
+        } else {
+            spectrum
+                .iter()
+                .zip(&self.previous)
+                .map(|(curr, prev)| (curr - prev).max(0.))
+                .sum()
+        };
+        self.previous = spectrum;
+        self.flux.push(flux);
+        let history = (TEMPO_HISTORY * self.frame_rate) as usize;
+        if self.flux.len() > history {
+            let excess = self.flux.len() - history;
+            self.flux.drain(..excess);
+        }
+
+        if let Some(strength) = self.pick_peak() {
+            // the peak is the previous frame, it needed the current one to be confirmed
+            let onset = self.frame - 1;
+            self.emit(BeatEvent::Onset {
+                time: onset as f32 / self.frame_rate,
+                strength,
+            });
+            self.align_phase(onset);
+            self.last_onset = Some(onset);
+        }
+
+        if self.frame >= self.next_estimate {
+            self.estimate_tempo();
+            self.next_estimate = self.frame + TEMPO_UPDATE;
+        }
+        self.advance_beat();
+        self.frame += 1;
+    }
+
+    // the previous frame is an onset if it is a local maximum above the adaptive threshold
+    fn pick_peak(&self) -> Option<f32> {
+        let n = self.flux.len();
+        if n < 3 {
+            return None;
+        }
+        let (before, peak, after) = (self.flux[n - 3], self.flux[n - 2], self.flux[n - 1]);
+        if peak <= before || peak < after {
+            return None;
+        }
+
+        let window = &self.flux[n.saturating_sub(THRESHOLD_WINDOW + 1)..n - 1];
+        let threshold = median(window) * THRESHOLD_MULTIPLIER + THRESHOLD_DELTA;
+        if peak < threshold {
+            return None;
+        }
+
+        let min_gap = (MIN_ONSET_GAP * self.frame_rate) as usize;
+        if let Some(last) = self.last_onset {
+            if self.frame - 1 < last + min_gap {
+                return None;
+            }
+        }
+        Some(peak)
+    }
+
+    // autocorrelation of the onset envelope weighted by a log-gaussian tempo prior
+    fn estimate_tempo(&mut self) {
+        let min_lag = (60. * self.frame_rate / MAX_BPM) as usize;
+        let max_lag = (60. * self.frame_rate / MIN_BPM) as usize;
+        if self.flux.len() < 2 * max_lag {
+            return;
+        }
+
+        let mean = self.flux.iter().sum::<f32>() / self.flux.len() as f32;
+        let envelope: Vec<f32> = self.flux.iter().map(|f| f - mean).collect();
+        let acf = |lag: usize| -> f32 {
+            envelope
+                .iter()
+                .zip(&envelope[lag..])
+                .map(|(a, b)| a * b)
+                .sum::<f32>()
+                / (envelope.len() - lag) as f32
+        };
+
+        let scores: Vec<f32> = (min_lag..=max_lag + 1)
+            .map(|lag| {
+                let bpm = 60. * self.frame_rate / lag as f32;
+                let prior = (-0.5 * (bpm / PREFERRED_BPM).log2().powi(2)).exp();
+                // comb: a true period also correlates at its double
+                let comb = acf(lag) + 0.5 * acf((2 * lag).min(envelope.len() - 1));
+                comb * prior
+            })
+            .collect();
+
+        let best = (1..scores.len() - 1)
+            .max_by(|a, b| scores[*a].total_cmp(&scores[*b]))
+            .unwrap();
+        if scores[best] <= 0. {
+            return;
+        }
+        // parabolic interpolation for a finer lag than the frame rate allows
+        let (prev, curr, next) = (scores[best - 1], scores[best], scores[best + 1]);
+        let denominator = prev - 2. * curr + next;
+        let offset = if denominator.abs() > f32::EPSILON {
+            (0.5 * (prev - next) / denominator).clamp(-0.5, 0.5)
+        } else {
+            0.
+        };
+        self.period = Some((min_lag + best) as f32 + offset);
+    }
+
+    // onsets close to where a beat was expected pull the beat grid towards them
+    fn align_phase(&mut self, onset: usize) {
+        if let Some(period) = self.period {
+            let since = onset as f32 - self.last_beat;
+            let phase = (since / period).rem_euclid(1.);
+            if !(PHASE_TOLERANCE..=1. - PHASE_TOLERANCE).contains(&phase) {
+                let error = if phase > 0.5 { phase - 1. } else { phase };
+                self.last_beat += 0.5 * error * period;
+            }
+        } else {
+            self.last_beat = onset as f32;
+        }
+    }
+
+    fn advance_beat(&mut self) {
+        if let Some(period) = self.period {
+            if self.frame as f32 - self.last_beat >= period {
+                self.last_beat += period * ((self.frame as f32 - self.last_beat) / period).floor();
+                self.emit(BeatEvent::Beat {
+                    time: self.last_beat / self.frame_rate,
+                    bpm: 60. * self.frame_rate / period,
+                });
+            }
+        }
+    }
+
+    fn emit(&self, event: BeatEvent) {
+        self.events.publish(event);
+    }
+}
+
+fn magnitudes(frame: &[f32]) -> Vec<f32> {
+    let prepared = fft::prepare_data(frame, frame.len(), choose_window(WindowType::Hann));
+    let spectrum = fft::fft(prepared);
+    (0..spectrum.len() / 4)
+        .map(|k| {
+            let (re, im) = (spectrum[2 * k].get(), spectrum[2 * k + 1].get());
+            (1. + COMPRESSION * (re * re + im * im).sqrt()).ln()
+        })
+        .collect()
+}
+
+fn median(values: &[f32]) -> f32 {
+    if values.is_empty() {
+        return 0.;
+    }
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    sorted[sorted.len() / 2]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // short decaying noise bursts, like a hi-hat or a kick on every beat
+    fn click_track(bpm: f32, seconds: f32) -> Vec<f32> {
+        let len = (44100. * seconds) as usize;
+        let period = (44100. * 60. / bpm) as usize;
+        let mut seed: u32 = 1;
+        (0..len)
+            .map(|i| {
+                let since = i % period;
+                if since > 2000 {
+                    return 0.;
+                }
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                let noise = (seed >> 16) as f32 / 32768. - 1.;
+                noise * (-(since as f32) / 300.).exp()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn detects_onsets() {
+        let mut tracker = BeatTracker::new(44100);
+        let events = tracker.subscribe(64, LagPolicy::DropOldest);
+        tracker.feed(&click_track(120., 4.));
+        let onsets: Vec<f32> = std::iter::from_fn(|| events.pop())
+            .filter_map(|e| match e {
+                BeatEvent::Onset { time, .. } => Some(time),
+                _ => None,
+            })
+            .collect();
+        // one click every half a second
+        assert!(onsets.len() >= 7 && onsets.len() <= 8, "{:?}", onsets);
+        for pair in onsets.windows(2) {
+            assert!((pair[1] - pair[0] - 0.5).abs() < 0.03, "{:?}", onsets);
+        }
+    }
+
+    #[test]
+    fn tracks_tempo() {
+        for &bpm in &[90., 120., 140.] {
+            let mut tracker = BeatTracker::new(44100);
+            tracker.feed(&click_track(bpm, 10.));
+            let detected = tracker.tempo().bpm.unwrap();
+            assert!(
+                (detected - bpm).abs() < 2.,
+                "Expected {} BPM, detected {}",
+                bpm,
+                detected
+            );
+        }
+    }
+
+    #[test]
+    fn emits_beats() {
+        let mut tracker = BeatTracker::new(44100);
+        let events = tracker.subscribe(64, LagPolicy::DropOldest);
+        tracker.feed(&click_track(120., 10.));
+        let beats = std::iter::from_fn(|| events.pop())
+            .filter(|e| matches!(e, BeatEvent::Beat { .. }))
+            .count();
+        assert!(beats > 5);
+    }
+
+    #[test]
+    fn slow_subscribers_stay_bounded() {
+        let mut tracker = BeatTracker::new(44100);
+        let events = tracker.subscribe(4, LagPolicy::DropOldest);
+        tracker.feed(&click_track(120., 10.));
+        let kept = std::iter::from_fn(|| events.pop()).count();
+        assert_eq!(kept, 4);
+        assert!(events.dropped() > 10);
+    }
+
+    #[test]
+    fn silence_has_no_tempo() {
+        let mut tracker = BeatTracker::new(44100);
+        tracker.feed(&vec![0.; 44100 * 8]);
+        assert!(tracker.tempo().bpm.is_none());
+    }
+}
@@ -0,0 +1,345 @@
+//! second order IIR filters with the coefficients from the RBJ audio EQ cookbook
+
+use super::convolver::Convolver;
+use super::graphic::GraphicEq;
+use std::f64::consts::PI;
+use std::str::FromStr;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FilterType {
+    Peaking,
+    LowShelf,
+    HighShelf,
+    LowPass,
+    HighPass,
+    Notch,
+    BandPass,
+}
+
+impl FromStr for FilterType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "peak" | "peaking" => Ok(FilterType::Peaking),
+            "lowshelf" => Ok(FilterType::LowShelf),
+            "highshelf" => Ok(FilterType::HighShelf),
+            "lowpass" => Ok(FilterType::LowPass),
+            "highpass" => Ok(FilterType::HighPass),
+            "notch" => Ok(FilterType::Notch),
+            "bandpass" => Ok(FilterType::BandPass),
+            _ => Err(format!(
+                "Unknown filter {}, expected one of peak, lowshelf, highshelf, lowpass, highpass, notch, bandpass",
+                s
+            )),
+        }
+    }
+}
+
+/// what the user configures, parsed from `type:frequency[:q[:gain]]`, e.g. `peak:1000:1.4:-6`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FilterSpec {
+    pub kind: FilterType,
+    pub frequency: f32,
+    pub q: f32,
+    pub gain: f32, // dB
+}
+
+impl FromStr for FilterSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split(':').collect();
+        if parts.len() < 2 || parts.len() > 4 {
+            return Err(format!(
+                "Invalid filter {}, expected type:frequency[:q[:gain]]",
+                s
+            ));
+        }
+        let number = |part: &str, what: &str| -> Result<f32, String> {
+            part.parse::<f32>()
+                .map_err(|_| format!("Invalid {} {} in filter {}", what, part, s))
+        };
+        let kind = parts[0].parse()?;
+        let frequency = number(parts[1], "frequency")?;
+        let q = match parts.get(2) {
+            Some(q) => number(q, "Q")?,
+            None => std::f32::consts::FRAC_1_SQRT_2,
+        };
+        let gain = match parts.get(3) {
+            Some(gain) => number(gain, "gain")?,
+            None => 0.,
+        };
+        if frequency <= 0. || q <= 0. {
+            return Err(format!("Frequency and Q must be positive in filter {}", s));
+        }
+        Ok(FilterSpec {
+            kind,
+            frequency,
+            q,
+            gain,
+        })
+    }
+}
+
+pub struct Biquad {
+    // normalized so that a0 is 1
+    b: [f64; 3],
+    a: [f64; 2],
+    // transposed direct form II state, one pair per channel
+    state: Vec<[f64; 2]>,
+}
+
+impl Biquad {
+    pub fn new(spec: FilterSpec, sample_rate: u32, channels: usize) -> Biquad {
+        let (b, a) = coefficients(&spec, sample_rate);
+        Biquad {
+            b,
+            a,
+            state: vec![[0.; 2]; channels.max(1)],
+        }
+    }
+
+    /// new coefficients, the state is kept so that moving a slider does not click
+    pub fn retune(&mut self, spec: FilterSpec, sample_rate: u32) {
+        let (b, a) = coefficients(&spec, sample_rate);
+        self.b = b;
+        self.a = a;
+    }
+
+    /// filters interleaved samples in place
+    pub fn process(&mut self, data: &mut [f32]) {
+        let channels = self.state.len();
+        for frame in data.chunks_mut(channels) {
+            for (sample, state) in frame.iter_mut().zip(self.state.iter_mut()) {
+                let x = f64::from(*sample);
+                let y = self.b[0] * x + state[0];
+                state[0] = self.b[1] * x - self.a[0] * y + state[1];
+                state[1] = self.b[2] * x - self.a[1] * y;
+                *sample = y as f32;
+            }
+        }
+    }
+}
+
+fn coefficients(spec: &FilterSpec, sample_rate: u32) -> ([f64; 3], [f64; 2]) {
+    let amp = 10f64.powf(f64::from(spec.gain) / 40.);
+    let w0 = 2. * PI * f64::from(spec.frequency) / f64::from(sample_rate);
+    let (sin, cos) = w0.sin_cos();
+    let alpha = sin / (2. * f64::from(spec.q));
+    let shelf = 2. * amp.sqrt() * alpha;
+
+    let (b, a) = match spec.kind {
+        FilterType::Peaking => (
+            [1. + alpha * amp, -2. * cos, 1. - alpha * amp],
+            [1. + alpha / amp, -2. * cos, 1. - alpha / amp],
+        ),
+        FilterType::LowShelf => (
+            [
+                amp * ((amp + 1.) - (amp - 1.) * cos + shelf),
+                2. * amp * ((amp - 1.) - (amp + 1.) * cos),
+                amp * ((amp + 1.) - (amp - 1.) * cos - shelf),
+            ],
+            [
+                (amp + 1.) + (amp - 1.) * cos + shelf,
+                -2. * ((amp - 1.) + (amp + 1.) * cos),
+                (amp + 1.) + (amp - 1.) * cos - shelf,
+            ],
+        ),
+        FilterType::HighShelf => (
+            [
+                amp * ((amp + 1.) + (amp - 1.) * cos + shelf),
+                -2. * amp * ((amp - 1.) + (amp + 1.) * cos),
+                amp * ((amp + 1.) + (amp - 1.) * cos - shelf),
+            ],
+            [
+                (amp + 1.) - (amp - 1.) * cos + shelf,
+                2. * ((amp - 1.) - (amp + 1.) * cos),
+                (amp + 1.) - (amp - 1.) * cos - shelf,
+            ],
+        ),
+        FilterType::LowPass => (
+            [(1. - cos) / 2., 1. - cos, (1. - cos) / 2.],
+            [1. + alpha, -2. * cos, 1. - alpha],
+        ),
+        FilterType::HighPass => (
+            [(1. + cos) / 2., -(1. + cos), (1. + cos) / 2.],
+            [1. + alpha, -2. * cos, 1. - alpha],
+        ),
+        FilterType::Notch => ([1., -2. * cos, 1.], [1. + alpha, -2. * cos, 1. - alpha]),
+        // constant 0 dB peak gain
+        FilterType::BandPass => ([alpha, 0., -alpha], [1. + alpha, -2. * cos, 1. - alpha]),
+    };
+    (
+        [b[0] / a[0], b[1] / a[0], b[2] / a[0]],
+        [a[1] / a[0], a[2] / a[0]],
+    )
+}
+
+/// a cascade of biquads applied one after another, followed by the graphic EQ and the FIR
+/// convolution if there are any
+pub struct FilterChain {
+    sample_rate: u32,
+    channels: usize,
+    filters: Vec<Biquad>,
+    graphic: Option<GraphicEq>,
+    convolver: Option<Convolver>,
+}
+
+impl FilterChain {
+    pub fn new(sample_rate: u32, channels: usize) -> FilterChain {
+        FilterChain {
+            sample_rate,
+            channels,
+            filters: Vec::new(),
+            graphic: None,
+            convolver: None,
+        }
+    }
+
+    pub fn add(&mut self, spec: FilterSpec) {
+        self.filters
+            .push(Biquad::new(spec, self.sample_rate, self.channels));
+    }
+
+    pub fn set_graphic(&mut self, bands: usize) -> Result<(), String> {
+        self.graphic = Some(GraphicEq::new(bands, self.sample_rate, self.channels)?);
+        Ok(())
+    }
+
+    pub fn graphic(&self) -> Option<&GraphicEq> {
+        self.graphic.as_ref()
+    }
+
+    pub fn graphic_mut(&mut self) -> Option<&mut GraphicEq> {
+        self.graphic.as_mut()
+    }
+
+    pub fn set_impulse_response(&mut self, ir: &[Vec<f32>], block: usize) {
+        self.convolver = Some(Convolver::new(ir, block, self.channels));
+    }
+
+    /// delay added by the chain in samples, the biquads have none worth mentioning
+    pub fn latency(&self) -> usize {
+        self.convolver.as_ref().map_or(0, |c| c.latency())
+    }
+
+    pub fn process(&mut self, data: &mut [f32]) {
+        for filter in self.filters.iter_mut() {
+            filter.process(data);
+        }
+        if let Some(graphic) = self.graphic.as_mut() {
+            graphic.process(data);
+        }
+        if let Some(convolver) = self.convolver.as_mut() {
+            convolver.process(data);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(freq: f32, len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|i| (2. * std::f32::consts::PI * freq * i as f32 / 44100.).sin())
+            .collect()
+    }
+
+    // measured gain in dB of the filtered sine, after the transient settled
+    fn measured_gain(chain: &mut FilterChain, freq: f32) -> f32 {
+        let mut data = sine(freq, 44100);
+        chain.process(&mut data);
+        let tail = &data[22050..];
+        let rms = (tail.iter().map(|s| s * s).sum::<f32>() / tail.len() as f32).sqrt();
+        20. * (rms * std::f32::consts::SQRT_2).log10()
+    }
+
+    // analytic magnitude response of the whole chain in dB
+    fn response(chain: &FilterChain, frequency: f32) -> f32 {
+        let w = 2. * PI * f64::from(frequency) / f64::from(chain.sample_rate);
+        // H(z) evaluated at z = e^jw, as |b0 + b1 z^-1 + b2 z^-2| / |1 + a1 z^-1 + a2 z^-2|
+        let magnitude = |c: [f64; 3]| {
+            let re = c[0] + c[1] * w.cos() + c[2] * (2. * w).cos();
+            let im = -c[1] * w.sin() - c[2] * (2. * w).sin();
+            (re * re + im * im).sqrt()
+        };
+        chain
+            .filters
+            .iter()
+            .map(|f| 20. * (magnitude(f.b) / magnitude([1., f.a[0], f.a[1]])).log10() as f32)
+            .sum()
+    }
+
+    fn chain(spec: &str) -> FilterChain {
+        let mut chain = FilterChain::new(44100, 1);
+        chain.add(spec.parse().unwrap());
+        chain
+    }
+
+    #[test]
+    fn parses_specs() {
+        let spec: FilterSpec = "peak:1000:1.4:-6".parse().unwrap();
+        assert_eq!(spec.kind, FilterType::Peaking);
+        assert_eq!((spec.frequency, spec.q, spec.gain), (1000., 1.4, -6.));
+        let spec: FilterSpec = "lowpass:200".parse().unwrap();
+        assert_eq!(spec.kind, FilterType::LowPass);
+        assert!((spec.q - 0.707).abs() < 1e-3);
+        assert!("wobble:200".parse::<FilterSpec>().is_err());
+        assert!("peak".parse::<FilterSpec>().is_err());
+        assert!("peak:-5".parse::<FilterSpec>().is_err());
+    }
+
+    #[test]
+    fn peaking_boosts_centre() {
+        let mut eq = chain("peak:1000:1:6");
+        assert!((response(&eq, 1000.) - 6.).abs() < 0.01);
+        assert!((measured_gain(&mut eq, 1000.) - 6.).abs() < 0.2);
+        let mut eq = chain("peak:1000:1:6");
+        assert!(measured_gain(&mut eq, 100.).abs() < 0.5);
+    }
+
+    #[test]
+    fn shelves_follow_gain() {
+        let eq = chain("lowshelf:200:0.707:-12");
+        assert!((response(&eq, 20.) + 12.).abs() < 0.5);
+        assert!(response(&eq, 10000.).abs() < 0.1);
+        let eq = chain("highshelf:5000:0.707:9");
+        assert!((response(&eq, 20000.) - 9.).abs() < 0.5);
+        assert!(response(&eq, 50.).abs() < 0.1);
+    }
+
+    #[test]
+    fn pass_filters_cut() {
+        let mut eq = chain("lowpass:500");
+        assert!((response(&eq, 500.) + 3.01).abs() < 0.1);
+        assert!(measured_gain(&mut eq, 8000.) < -40.);
+        let mut eq = chain("highpass:500");
+        assert!(measured_gain(&mut eq, 50.) < -35.);
+        let mut eq = chain("highpass:500");
+        assert!(measured_gain(&mut eq, 8000.).abs() < 0.1);
+    }
+
+    #[test]
+    fn notch_and_band_pass() {
+        let mut eq = chain("notch:1000:2");
+        assert!(measured_gain(&mut eq, 1000.) < -40.);
+        let eq = chain("bandpass:1000:2");
+        assert!(response(&eq, 1000.).abs() < 0.01);
+        assert!(response(&eq, 100.) < -20.);
+    }
+
+    #[test]
+    fn filters_channels_independently() {
+        let mut chain = FilterChain::new(44100, 2);
+        chain.add("lowpass:500".parse().unwrap());
+        // left is a loud high tone, right is silent, the silence must stay untouched
+        let mut data: Vec<f32> = sine(8000., 1024)
+            .into_iter()
+            .flat_map(|s| vec![s, 0.])
+            .collect();
+        chain.process(&mut data);
+        assert!(data.iter().skip(1).step_by(2).all(|s| *s == 0.));
+    }
+}
@@ -0,0 +1,239 @@
+//! chroma features (energy per pitch class) and rolling key estimation
+
+use super::fft;
+use super::tuner::NOTE_NAMES;
+use super::window::{choose_window, WindowType};
+
+const FRAME: usize = 8192; // ~2.7 Hz resolution at 44.1 kHz after zero padding, enough for bass notes
+const HOP: usize = 4096;
+const MIN_FREQ: f32 = 55.; // A1
+const MAX_FREQ: f32 = 5000.;
+const KEY_DECAY: f32 = 0.95; // per frame, a few seconds of memory at the default hop
+
+// Krumhansl-Kessler key profiles, starting at the tonic
+const MAJOR_PROFILE: [f32; 12] = [
+    6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88,
+];
+const MINOR_PROFILE: [f32; 12] = [
+    6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17,
+];
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Mode {
+    Major,
+    Minor,
+}
+
+#[derive(Clone, Debug)]
+pub struct Key {
+    pub tonic: usize, // pitch class, 0 is C
+    pub mode: Mode,
+    pub confidence: f32, // correlation with the winning profile
+}
+
+impl Key {
+    pub fn name(&self) -> String {
+        match self.mode {
+            Mode::Major => format!("{} major", NOTE_NAMES[self.tonic]),
+            Mode::Minor => format!("{} minor", NOTE_NAMES[self.tonic]),
+        }
+    }
+}
+
+/// what the front-ends draw, the chroma is normalized so that the strongest class is 1
+#[derive(Clone, Debug)]
+pub struct Chromagram {
+    pub chroma: [f32; 12],
+    pub key: Option<Key>,
+}
+
+pub struct Chroma {
+    buffer: Vec<f32>,
+    sample_rate: u32,
+    reference: f32,
+    chroma: [f32; 12],
+    history: [f32; 12],
+}
+
+impl Chroma {
+    pub fn new(sample_rate: u32) -> Chroma {
+        Chroma {
+            buffer: Vec::with_capacity(FRAME + HOP),
+            sample_rate,
+            reference: 440.,
+            chroma: [0.; 12],
+            history: [0.; 12],
+        }
+    }
+
+    pub fn feed(&mut self, samples: &[f32]) {
+        self.buffer.extend_from_slice(samples);
+        while self.buffer.len() >= FRAME {
+            self.chroma = self.extract(&self.buffer[..FRAME]);
+            for (h, c) in self.history.iter_mut().zip(self.chroma.iter()) {
+                *h = *h * KEY_DECAY + c;
+            }
+            self.buffer.drain(..HOP);
+        }
+    }
+
+    // folds the magnitude spectrum into 12 pitch classes
+    fn extract(&self, frame: &[f32]) -> [f32; 12] {
+        let prepared = fft::prepare_data(frame, frame.len(), choose_window(WindowType::Hann));
+        let spectrum = fft::fft(prepared);
+        let points = spectrum.len() / 2;
+        let resolution = self.sample_rate as f32 / points as f32;
+
+        let mut chroma = [0.; 12];
+        for k in 1..points / 2 {
+            let freq = k as f32 * resolution;
+            if !(MIN_FREQ..=MAX_FREQ).contains(&freq) {
+                continue;
+            }
+            let (re, im) = (spectrum[2 * k].get(), spectrum[2 * k + 1].get());
+            let class = (12. * (freq / self.reference).log2()).round() as i32 + 9; // A is 9
+            chroma[class.rem_euclid(12) as usize] += (re * re + im * im).sqrt();
+        }
+
+        let max = chroma.iter().cloned().fold(0., f32::max);
+        if max > 0. {
+            for c in chroma.iter_mut() {
+                *c /= max;
+            }
+        }
+        chroma
+    }
+
+    /// correlates the recent chroma with all 24 rotated key profiles
+    pub fn key(&self) -> Option<Key> {
+        if self.history.iter().all(|h| *h == 0.) {
+            return None;
+        }
+        let mut best: Option<Key> = None;
+        for tonic in 0..12 {
+            for &(mode, profile) in &[(Mode::Major, &MAJOR_PROFILE), (Mode::Minor, &MINOR_PROFILE)]
+            {
+                let rotated: Vec<f32> = (0..12).map(|i| profile[(i + 12 - tonic) % 12]).collect();
+                let confidence = correlation(&self.history, &rotated);
+                let better = match &best {
+                    Some(b) => confidence > b.confidence,
+                    None => true,
+                };
+                if better {
+                    best = Some(Key {
+                        tonic,
+                        mode,
+                        confidence,
+                    });
+                }
+            }
+        }
+        best
+    }
+
+    pub fn snapshot(&self) -> Chromagram {
+        Chromagram {
+            chroma: self.chroma,
+            key: self.key(),
+        }
+    }
+
+    pub fn set_reference(&mut self, reference: f32) {
+        self.reference = reference;
+    }
+}
+
+// Pearson correlation coefficient
+fn correlation(a: &[f32], b: &[f32]) -> f32 {
+    let n = a.len() as f32;
+    let (mean_a, mean_b) = (a.iter().sum::<f32>() / n, b.iter().sum::<f32>() / n);
+    let (mut cov, mut var_a, mut var_b) = (0., 0., 0.);
+    for (x, y) in a.iter().zip(b) {
+        cov += (x - mean_a) * (y - mean_b);
+        var_a += (x - mean_a) * (x - mean_a);
+        var_b += (y - mean_b) * (y - mean_b);
+    }
+    if var_a == 0. || var_b == 0. {
+        return 0.;
+    }
+    cov / (var_a * var_b).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chord(freqs: &[f32], len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|i| {
+                let t = i as f32 / 44100.;
+                freqs
+                    .iter()
+                    .map(|f| (2. * std::f32::consts::PI * f * t).sin())
+                    .sum::<f32>()
+                    / freqs.len() as f32
+            })
+            .collect()
+    }
+
+    fn strongest(chroma: &[f32; 12]) -> usize {
+        (0..12)
+            .max_by(|a, b| chroma[*a].partial_cmp(&chroma[*b]).unwrap())
+            .unwrap()
+    }
+
+    #[test]
+    fn tone_lands_in_its_pitch_class() {
+        let mut chroma = Chroma::new(44100);
+        chroma.feed(&chord(&[440.], FRAME));
+        assert_eq!(NOTE_NAMES[strongest(&chroma.snapshot().chroma)], "A");
+        chroma.feed(&chord(&[130.81], FRAME));
+        assert_eq!(NOTE_NAMES[strongest(&chroma.snapshot().chroma)], "C");
+    }
+
+    #[test]
+    fn follows_reference() {
+        // 453 Hz is half way between A and A# for A4 = 440, but an A with a raised reference
+        let mut chroma = Chroma::new(44100);
+        chroma.set_reference(453.);
+        chroma.feed(&chord(&[453.], FRAME));
+        assert_eq!(NOTE_NAMES[strongest(&chroma.snapshot().chroma)], "A");
+    }
+
+    #[test]
+    fn estimates_major_key() {
+        let mut chroma = Chroma::new(44100);
+        // I - IV - V - I in C major
+        for triad in &[
+            [261.63, 329.63, 392.],
+            [349.23, 440., 523.25],
+            [392., 493.88, 587.33],
+            [261.63, 329.63, 392.],
+        ] {
+            chroma.feed(&chord(triad, 2 * FRAME));
+        }
+        assert_eq!(chroma.key().unwrap().name(), "C major");
+    }
+
+    #[test]
+    fn estimates_minor_key() {
+        let mut chroma = Chroma::new(44100);
+        // i - iv - V - i in A minor
+        for triad in &[
+            [220., 261.63, 329.63],
+            [293.66, 349.23, 440.],
+            [329.63, 415.3, 493.88],
+            [220., 261.63, 329.63],
+        ] {
+            chroma.feed(&chord(triad, 2 * FRAME));
+        }
+        assert_eq!(chroma.key().unwrap().name(), "A minor");
+    }
+
+    #[test]
+    fn silence_has_no_key() {
+        let mut chroma = Chroma::new(44100);
+        chroma.feed(&[0.; FRAME]);
+        assert!(chroma.key().is_none());
+    }
+}
@@ -0,0 +1,208 @@
+//! FIR filtering with an impulse response, uniformly partitioned overlap-save convolution
+
+use super::fft;
+use std::cell::Cell;
+use std::collections::VecDeque;
+
+// interleaved complex spectrum of 2 * block points
+type Spectrum = Vec<f32>;
+
+// state of one channel
+struct Lane {
+    partitions: Vec<Spectrum>,   // the impulse response, one spectrum per block
+    history: VecDeque<Spectrum>, // frequency-domain delay line of the input, newest first
+    input: Vec<f32>,             // last two blocks of input
+    output: Vec<f32>,            // one block of output, played while the next input block fills
+}
+
+pub struct Convolver {
+    block: usize,
+    lanes: Vec<Lane>,
+    position: usize, // within the current block
+}
+
+impl Convolver {
+    /// block must be a power of two, a channel uses the impulse response channel of the same
+    /// index, or the first one if the response has fewer channels
+    pub fn new(ir: &[Vec<f32>], block: usize, channels: usize) -> Convolver {
+        assert!(
+            block.is_power_of_two(),
+            "The convolution block must be a power of two"
+        );
+        let lanes = (0..channels.max(1))
+            .map(|channel| {
+                let response = ir.get(channel).or_else(|| ir.first());
+                let partitions: Vec<Spectrum> = match response {
+                    Some(response) if !response.is_empty() => response
+                        .chunks(block)
+                        .map(|p| transform(p, block))
+                        .collect(),
+                    _ => vec![transform(&[], block)],
+                };
+                let history = (0..partitions.len()).map(|_| vec![0.; 4 * block]).collect();
+                Lane {
+                    partitions,
+                    history,
+                    input: vec![0.; 2 * block],
+                    output: vec![0.; block],
+                }
+            })
+            .collect();
+        Convolver {
+            block,
+            lanes,
+            position: 0,
+        }
+    }
+
+    /// samples of delay added by the block buffering
+    pub fn latency(&self) -> usize {
+        self.block
+    }
+
+    /// filters interleaved samples in place, delayed by latency() frames
+    pub fn process(&mut self, data: &mut [f32]) {
+        let channels = self.lanes.len();
+        for frame in data.chunks_mut(channels) {
+            for (sample, lane) in frame.iter_mut().zip(self.lanes.iter_mut()) {
+                lane.input[self.block + self.position] = *sample;
+                *sample = lane.output[self.position];
+            }
+            self.position += 1;
+            if self.position == self.block {
+                self.position = 0;
+                for lane in self.lanes.iter_mut() {
+                    lane.convolve(self.block);
+                }
+            }
+        }
+    }
+}
+
+impl Lane {
+    fn convolve(&mut self, block: usize) {
+        // newest input spectrum in front, the oldest one falls off
+        let mut spectrum = self.history.pop_back().unwrap_or_default();
+        let transformed = fft::fft(interleave(&self.input, 2 * block));
+        for (dst, src) in spectrum.iter_mut().zip(transformed.iter()) {
+            *dst = src.get();
+        }
+        self.history.push_front(spectrum);
+
+        // sum of input blocks times the matching impulse response partitions
+        let mut sum = vec![0f32; 4 * block];
+        for (x, h) in self.history.iter().zip(self.partitions.iter()) {
+            for k in 0..2 * block {
+                let (xr, xi, hr, hi) = (x[2 * k], x[2 * k + 1], h[2 * k], h[2 * k + 1]);
+                sum[2 * k] += xr * hr - xi * hi;
+                sum[2 * k + 1] += xr * hi + xi * hr;
+            }
+        }
+
+        // the first half is wrapped around by the circular convolution, the second is valid
+        let result = fft::ifft(sum.into_iter().map(Cell::new).collect());
+        for (i, out) in self.output.iter_mut().enumerate() {
+            *out = result[2 * (block + i)].get();
+        }
+        self.input.copy_within(block.., 0);
+    }
+}
+
+// zero padded complex interleaving of real samples
+fn interleave(data: &[f32], len: usize) -> Vec<Cell<f32>> {
+    let interleaved = vec![Cell::new(0.); 2 * len];
+    for (i, s) in data.iter().enumerate() {
+        interleaved[2 * i].set(*s);
+    }
+    interleaved
+}
+
+fn transform(partition: &[f32], block: usize) -> Spectrum {
+    fft::fft(interleave(partition, 2 * block))
+        .iter()
+        .map(|c| c.get())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn noise(len: usize, mut seed: u32) -> Vec<f32> {
+        (0..len)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (seed >> 16) as f32 / 32768. - 1.
+            })
+            .collect()
+    }
+
+    fn direct(input: &[f32], ir: &[f32]) -> Vec<f32> {
+        (0..input.len())
+            .map(|n| {
+                ir.iter()
+                    .enumerate()
+                    .take_while(|(k, _)| *k <= n)
+                    .map(|(k, h)| h * input[n - k])
+                    .sum()
+            })
+            .collect()
+    }
+
+    fn assert_matches(block: usize, ir_len: usize) {
+        let ir = noise(ir_len, 7);
+        let input = noise(4000, 3);
+        let expected = direct(&input, &ir);
+
+        let mut convolver = Convolver::new(&[ir], block, 1);
+        let mut output = input.clone();
+        // odd chunk sizes, the callback does not deliver whole blocks
+        for chunk in output.chunks_mut(100) {
+            convolver.process(chunk);
+        }
+
+        let latency = convolver.latency();
+        for n in 0..input.len() - latency {
+            assert!(
+                (output[n + latency] - expected[n]).abs() < 1e-3,
+                "sample {} expected {} got {}",
+                n,
+                expected[n],
+                output[n + latency]
+            );
+        }
+    }
+
+    #[test]
+    fn single_partition_matches_direct() {
+        assert_matches(64, 50);
+    }
+
+    #[test]
+    fn many_partitions_match_direct() {
+        assert_matches(64, 1000);
+        assert_matches(128, 128 * 3);
+    }
+
+    #[test]
+    fn unit_impulse_is_a_delay() {
+        let mut convolver = Convolver::new(&[vec![1.]], 32, 2);
+        let mut data: Vec<f32> = (0..256).map(|i| i as f32).collect();
+        convolver.process(&mut data);
+        assert_eq!(convolver.latency(), 32);
+        // 32 frames of stereo
+        assert!(data[..64].iter().all(|s| *s == 0.));
+        for (i, s) in data[64..].iter().enumerate() {
+            assert!((s - i as f32).abs() < 1e-2);
+        }
+    }
+
+    #[test]
+    fn channels_use_their_own_response() {
+        let mut convolver = Convolver::new(&[vec![1.], vec![0.5]], 16, 2);
+        let mut data = vec![1.; 128];
+        convolver.process(&mut data);
+        assert!((data[126] - 1.).abs() < 1e-3);
+        assert!((data[127] - 0.5).abs() < 1e-3);
+    }
+}
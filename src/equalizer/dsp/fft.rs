@@ -1,8 +1,7 @@
-use crate::errors::Error;
 use std::cell::Cell;
 
-// receives already extended vector of both real and imaginary values
-// requires the input data to be a power of two, lest wrong indexing happens!
+/// receives already extended vector of both real and imaginary values
+/// requires the input data to be a power of two, lest wrong indexing happens!
 pub fn fft<T>(mut data: Vec<Cell<T>>) -> Vec<Cell<T>>
 where
     T: Copy
@@ -23,11 +22,12 @@ where
         if j > i {
             data.swap(j, i); // swap real
             data.swap(j + 1, i + 1); // swap complex
-        }
 
-        if (j / 2) < (n / 4) {
-            data.swap(n - (i + 2), n - (j + 2));
-            data.swap(n - (i + 2) + 1, n - (j + 2) + 1);
+            // mirrored pair from the upper half, only valid when the lower pair was swapped
+            if (j / 2) < (n / 4) {
+                data.swap(n - (i + 2), n - (j + 2));
+                data.swap(n - (i + 2) + 1, n - (j + 2) + 1);
+            }
         }
 
         m = n / 2;
@@ -87,8 +87,22 @@ where
     data
 }
 
-// finds the nearest power of 2 the length satisfies and zero-extends the buffer
-// after preparing the data for FFT (interleaving)
+/// inverse transform, conjugating before and after flips the sign of the exponent
+pub fn ifft(data: Vec<Cell<f32>>) -> Vec<Cell<f32>> {
+    let n = (data.len() / 2) as f32;
+    for im in data.iter().skip(1).step_by(2) {
+        im.set(-im.get());
+    }
+    let data = fft(data);
+    for (i, val) in data.iter().enumerate() {
+        let sign = if i % 2 == 1 { -1. } else { 1. };
+        val.set(sign * val.get() / n);
+    }
+    data
+}
+
+/// finds the nearest power of 2 the length satisfies and zero-extends the buffer
+/// after preparing the data for FFT (interleaving)
 pub fn prepare_data<T>(
     data: &[T],
     len: usize,
@@ -112,139 +126,6 @@ where
     extended
 }
 
-// TODO: write docs
-// converts the input FFT data to num_bins size
-pub fn to_bins<T>(data: Vec<Cell<T>>, num_bins: Option<usize>) -> Result<Vec<usize>, Error>
-// Two choices for frequency bins, either even_spaced or industry
-// TODO: probably need a result for checking, the buffer should be discarded asap?
-// INCOMING DATA IS 0?>????
-where
-    T: Copy
-        + std::fmt::Display
-        + std::ops::Mul<Output = T>
-        + std::ops::Add<Output = T>
-        + std::ops::AddAssign
-        + std::convert::From<f32>,
-    f32: std::convert::From<T>,
-{
-    // care only to N/2 frequencies
-    // real intreleaved with imaginary
-    // 0 is the dc offset
-    // TODO: probably need a place for magic numbers definitions? FFT constants etc
-    //debug!("to bins data in len {}", data.len());
-    if data.len() > 44100 {
-        return Err(Error::FFTOperation);
-    }
-
-    match num_bins {
-        Some(bins) => Ok(bins_custom(data, bins)),
-        None => Ok(bins_standard(data)),
-    }
-}
-
-// This is the most often seen bins partitioning on the internet
-fn bins_standard<T>(data: Vec<Cell<T>>) -> Vec<usize>
-where
-    T: Copy
-        + std::fmt::Display
-        + std::ops::Mul<Output = T>
-        + std::ops::Add<Output = T>
-        + std::ops::AddAssign
-        + std::convert::From<f32>,
-    f32: std::convert::From<T>,
-{
-    // normal bins algo with 31 frequency bins and their centers
-    let upper_frequencies = vec![
-        20, 25, 31, 40, 50, 63, 80, 100, 125, 160, 200, 250, 315, 400, 500, 630, 800, 1000, 1200,
-        1600, 2000, 2500, 3100, 4000, 5000, 6300, 8000, 10000, 12000, 16000, 20000,
-    ];
-    let mut upper_indices_remapped = Vec::<usize>::with_capacity(upper_frequencies.len());
-    for i in 0..upper_frequencies.len() {
-        upper_indices_remapped
-            .push((upper_frequencies[i] / upper_frequencies.last().unwrap() * data.len()) as usize);
-    }
-    let mut bin_idx: usize = 1; // index from 1 but store at 0
-    let mut freq_magnitude: f32;
-    let mut bins = Vec::<f32>::with_capacity(upper_frequencies.len());
-    for _ in 0..upper_frequencies.len() {
-        bins.push(0.);
-    }
-
-    // iterate through the input vector and bin the data -> remap len to frequency!
-    for i in (0..data.len() / 2).step_by(2) {
-        if i / 2 > bin_idx * upper_indices_remapped[bin_idx - 1] {
-            bin_idx += 1;
-        }
-        //debug!("incoming data for i {} is {}", i, data[i].get());
-        freq_magnitude = Into::<f32>::into(
-            data[i].get() * data[i].get() + data[i + 1].get() * data[i + 1].get(),
-        );
-        //debug!("the magnitude at idx {} is {}", i, freq_magnitude);
-        bins[bin_idx - 1] += freq_magnitude;
-    }
-
-    // finally they should be normalized
-    let normalized = bins
-        .iter()
-        .enumerate()
-        .map(|(i, val)| {
-            if i == 0 {
-                return *val as usize / upper_frequencies[0];
-            }
-            *val as usize / (upper_frequencies[i] - upper_frequencies[i - 1])
-        })
-        .collect::<Vec<usize>>();
-
-    for i in 0..normalized.len() {
-        info!("NormBin {} value {}", i, normalized[i]);
-    }
-    normalized
-}
-
-fn bins_custom<T>(data: Vec<Cell<T>>, num_bins: usize) -> Vec<usize>
-where
-    T: Copy
-        + std::fmt::Display
-        + std::ops::Mul<Output = T>
-        + std::ops::Add<Output = T>
-        + std::ops::AddAssign
-        + std::convert::From<f32>,
-    f32: std::convert::From<T>,
-{
-    // custom bins algo
-    let bin_width: usize = ((data.len() / 4) as f32 / num_bins as f32).ceil() as usize;
-    let mut bin_idx: usize = 1; // index from 1 but store at 0
-    let mut freq_magnitude: f32;
-    let mut bins = Vec::<f32>::with_capacity(num_bins);
-    for _ in 0..num_bins {
-        bins.push(0.);
-    }
-
-    info!("bin_width {} data.len() {}", bin_width, data.len());
-    // Here they need to be thrown into appropriate bin
-    for i in (0..data.len() / 2).step_by(2) {
-        if i / 2 > bin_idx * bin_width {
-            bin_idx += 1;
-        }
-        //debug!("incoming data for i {} is {}", i, data[i].get());
-        freq_magnitude = Into::<f32>::into(
-            data[i].get() * data[i].get() + data[i + 1].get() * data[i + 1].get(),
-        );
-        //debug!("the magnitude at idx {} is {}", i, freq_magnitude);
-        bins[bin_idx - 1] += freq_magnitude;
-    }
-
-    // finally they should be normalized
-    let normalized = bins
-        .iter()
-        .map(|&b| b as usize / bin_width)
-        .collect::<Vec<usize>>();
-    for i in 0..normalized.len() {
-        info!("NormBin {} value {}", i, normalized[i]);
-    }
-    normalized
-}
-
 // tests on floats TODO: add tests for i16?
 #[cfg(test)]
 mod tests {
@@ -283,4 +164,93 @@ mod tests {
 
         assert_eq!(onlyZeroes, false);
     }
+
+    #[test]
+    fn inverse_round_trip() {
+        let signal: Vec<f32> = (0..256)
+            .map(|i| (i as f32 * 0.1).cos() * i as f32)
+            .collect();
+        let mut vec = Vec::with_capacity(512);
+        for s in &signal {
+            vec.push(Cell::new(*s));
+            vec.push(Cell::new(0.0));
+        }
+        let restored = ifft(fft(vec));
+        for (i, s) in signal.iter().enumerate() {
+            assert!((restored[2 * i].get() - s).abs() < 1e-2);
+            assert!(restored[2 * i + 1].get().abs() < 1e-2);
+        }
+    }
+
+    // the bit-reversal once scrambled the order of transforms longer than 8 points, compares the
+    // complex bins and not just the magnitudes, at the sizes the spectrum uses
+    #[test]
+    fn bins_are_in_order() {
+        for n in [16usize, 256, 2048].iter().copied() {
+            let signal: Vec<f32> = (0..n).map(|i| ((i * 13) % 7) as f32 - 3.).collect();
+            let mut vec = Vec::with_capacity(2 * n);
+            for s in &signal {
+                vec.push(Cell::new(*s));
+                vec.push(Cell::new(0.0));
+            }
+            let transformed = fft(vec);
+            for k in 0..n {
+                let (mut re, mut im) = (0f64, 0f64);
+                for (t, s) in signal.iter().enumerate() {
+                    let angle = 2. * std::f64::consts::PI * ((k * t) % n) as f64 / n as f64;
+                    re += f64::from(*s) * angle.cos();
+                    im += f64::from(*s) * angle.sin();
+                }
+                let (actual_re, actual_im) =
+                    (transformed[2 * k].get(), transformed[2 * k + 1].get());
+                // the sign of the exponent is the same for every bin, either is fine
+                let tolerance = 1e-3 * n as f32;
+                assert!(
+                    (actual_re - re as f32).abs() < tolerance
+                        && (actual_im.abs() - im.abs() as f32).abs() < tolerance,
+                    "{} points, bin {}: expected {} {} got {} {}",
+                    n,
+                    k,
+                    re,
+                    im,
+                    actual_re,
+                    actual_im
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn matches_dft() {
+        const N: usize = 64;
+        let signal: Vec<f32> = (0..N)
+            .map(|i| (i as f32 * 0.3).sin() + ((i * 7) % 5) as f32 - 2.)
+            .collect();
+        let mut vec = Vec::with_capacity(2 * N);
+        for s in &signal {
+            vec.push(Cell::new(*s));
+            vec.push(Cell::new(0.0));
+        }
+        let transformed = fft(vec);
+
+        // magnitudes only, the sign of the exponent does not matter for binning
+        for k in 0..N {
+            let (mut re, mut im) = (0f32, 0f32);
+            for (t, s) in signal.iter().enumerate() {
+                let angle = 2. * std::f32::consts::PI * (k * t) as f32 / N as f32;
+                re += s * angle.cos();
+                im += s * angle.sin();
+            }
+            let expected = (re * re + im * im).sqrt();
+            let actual =
+                (transformed[2 * k].get().powi(2) + transformed[2 * k + 1].get().powi(2)).sqrt();
+            assert!(
+                (expected - actual).abs() < 1e-3 * expected.max(1.),
+                "bin {} expected {} got {}",
+                k,
+                expected,
+                actual
+            );
+        }
+    }
 }
@@ -0,0 +1,141 @@
+//! graphic equalizer: a fixed bank of constant-Q peaking filters on the octave or third-octave centres
+
+use super::biquad::{Biquad, FilterSpec, FilterType};
+
+pub const MAX_GAIN: f32 = 12.; // dB, both ways
+
+/// ISO 266 centres, the same the spectrum is binned and labelled with
+pub const OCTAVE_CENTRES: [f32; 10] = [
+    31.5, 63., 125., 250., 500., 1000., 2000., 4000., 8000., 16000.,
+];
+pub const THIRD_OCTAVE_CENTRES: [f32; 31] = [
+    20., 25., 31.5, 40., 50., 63., 80., 100., 125., 160., 200., 250., 315., 400., 500., 630., 800.,
+    1000., 1250., 1600., 2000., 2500., 3150., 4000., 5000., 6300., 8000., 10000., 12500., 16000.,
+    20000.,
+];
+
+pub struct GraphicEq {
+    centres: &'static [f32],
+    gains: Vec<f32>,
+    q: f32,
+    sample_rate: u32,
+    filters: Vec<Biquad>,
+}
+
+impl GraphicEq {
+    /// 10 octave or 31 third-octave bands, everything else is refused
+    pub fn new(bands: usize, sample_rate: u32, channels: usize) -> Result<GraphicEq, String> {
+        let (centres, fraction): (&'static [f32], f32) = match bands {
+            10 => (&OCTAVE_CENTRES, 1.),
+            31 => (&THIRD_OCTAVE_CENTRES, 3.),
+            _ => return Err(format!("A graphic EQ has 10 or 31 bands, not {}", bands)),
+        };
+        // Q of a band as wide as the distance between two centres
+        let ratio = 2f32.powf(1. / fraction);
+        let q = ratio.sqrt() / (ratio - 1.);
+
+        let mut eq = GraphicEq {
+            centres,
+            gains: vec![0.; centres.len()],
+            q,
+            sample_rate,
+            filters: Vec::new(),
+        };
+        eq.filters = (0..centres.len())
+            .map(|band| Biquad::new(eq.spec(band), sample_rate, channels))
+            .collect();
+        Ok(eq)
+    }
+
+    fn spec(&self, band: usize) -> FilterSpec {
+        // the highest centres are above Nyquist for low sample rates, keep them just below it
+        let nyquist = self.sample_rate as f32 / 2.;
+        FilterSpec {
+            kind: FilterType::Peaking,
+            frequency: self.centres[band].min(nyquist * 0.95),
+            q: self.q,
+            gain: self.gains[band],
+        }
+    }
+
+    pub fn centres(&self) -> &[f32] {
+        self.centres
+    }
+
+    pub fn gains(&self) -> &[f32] {
+        &self.gains
+    }
+
+    pub fn set_gain(&mut self, band: usize, gain: f32) {
+        if band >= self.gains.len() {
+            return;
+        }
+        self.gains[band] = gain.clamp(-MAX_GAIN, MAX_GAIN);
+        let spec = self.spec(band);
+        self.filters[band].retune(spec, self.sample_rate);
+    }
+
+    pub fn reset(&mut self) {
+        for band in 0..self.gains.len() {
+            self.set_gain(band, 0.);
+        }
+    }
+
+    pub fn process(&mut self, data: &mut [f32]) {
+        // flat bands are run too, so that their state is ready once they get a gain
+        for filter in self.filters.iter_mut() {
+            filter.process(data);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn measured_gain(eq: &mut GraphicEq, freq: f32) -> f32 {
+        let mut data: Vec<f32> = (0..44100)
+            .map(|i| (2. * std::f32::consts::PI * freq * i as f32 / 44100.).sin())
+            .collect();
+        eq.process(&mut data);
+        let tail = &data[22050..];
+        let rms = (tail.iter().map(|s| s * s).sum::<f32>() / tail.len() as f32).sqrt();
+        20. * (rms * std::f32::consts::SQRT_2).log10()
+    }
+
+    #[test]
+    fn only_10_or_31_bands() {
+        assert!(GraphicEq::new(10, 44100, 1).is_ok());
+        assert!(GraphicEq::new(31, 44100, 1).is_ok());
+        assert!(GraphicEq::new(12, 44100, 1).is_err());
+    }
+
+    #[test]
+    fn flat_is_transparent() {
+        let mut eq = GraphicEq::new(31, 44100, 1).unwrap();
+        for &freq in &[50., 1000., 10000.] {
+            assert!(measured_gain(&mut eq, freq).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn boosts_only_its_band() {
+        let mut eq = GraphicEq::new(10, 44100, 1).unwrap();
+        eq.set_gain(5, 6.); // 1 kHz
+        assert!((measured_gain(&mut eq, 1000.) - 6.).abs() < 0.2);
+        // an octave away the boost is mostly gone, two octaves away it is negligible
+        assert!(measured_gain(&mut eq, 2000.) < 2.);
+        assert!(measured_gain(&mut eq, 250.).abs() < 0.5);
+    }
+
+    #[test]
+    fn clamps_gain() {
+        let mut eq = GraphicEq::new(31, 44100, 1).unwrap();
+        eq.set_gain(3, 40.);
+        eq.set_gain(4, -40.);
+        assert_eq!(eq.gains()[3], MAX_GAIN);
+        assert_eq!(eq.gains()[4], -MAX_GAIN);
+        eq.reset();
+        assert!(eq.gains().iter().all(|g| *g == 0.));
+    }
+}
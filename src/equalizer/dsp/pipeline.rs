@@ -0,0 +1,922 @@
+//! the spectrum of a frame as a chain of stages, from its samples to band levels in dB. The
+//! default chain follows the `Settings`, others are put together with `Pipeline::builder` or from
+//! a spec such as `dc,window,fft,weighting=a,bins,smoothing=0.6,db`, and can be rearranged while
+//! running
+
+use super::fft;
+use super::spectrum::{bands, centred, Band, Settings, Weighting, MIN_DB};
+use super::window::WindowType;
+use crate::errors::Error;
+use std::cell::Cell;
+use std::fmt;
+use std::str::FromStr;
+
+/// what a block holds between two stages
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Domain {
+    Time,     // the samples of a frame
+    Spectrum, // power of every FFT bin from DC to Nyquist, 1 for a full scale sine
+    Bands,    // power of every band
+    Levels,   // dB of every band, or of every FFT bin when nothing bins them
+}
+
+/// one step of the pipeline, stages run on the DSP thread
+pub trait Stage: Send {
+    /// the name in a pipeline spec, also how the stage is found when rearranging
+    fn name(&self) -> &str;
+
+    fn accepts(&self, input: Domain) -> bool;
+
+    /// what the stage makes of a block in the input domain
+    fn output(&self, input: Domain) -> Domain {
+        input
+    }
+
+    /// works in place, a stage may change the length of the block
+    fn process(&mut self, block: &mut Vec<f32>);
+
+    /// forgets the previous frames, called whenever the pipeline changes
+    fn reset(&mut self) {}
+
+    /// the bands of the blocks it outputs, for stages that bin the spectrum
+    fn bands(&self) -> Option<Vec<Band>> {
+        None
+    }
+}
+
+/// subtracts the mean of the frame
+pub struct DcRemoval;
+
+impl Stage for DcRemoval {
+    fn name(&self) -> &str {
+        "dc"
+    }
+
+    fn accepts(&self, input: Domain) -> bool {
+        input == Domain::Time
+    }
+
+    fn process(&mut self, block: &mut Vec<f32>) {
+        let mean = block.iter().sum::<f32>() / block.len().max(1) as f32;
+        block.iter_mut().for_each(|sample| *sample -= mean);
+    }
+}
+
+/// first order high-pass, `y[n] = x[n] - coefficient * x[n - 1]` within the frame
+pub struct PreEmphasis {
+    coefficient: f32,
+}
+
+impl PreEmphasis {
+    pub fn new(coefficient: f32) -> PreEmphasis {
+        PreEmphasis { coefficient }
+    }
+}
+
+impl Stage for PreEmphasis {
+    fn name(&self) -> &str {
+        "preemphasis"
+    }
+
+    fn accepts(&self, input: Domain) -> bool {
+        input == Domain::Time
+    }
+
+    fn process(&mut self, block: &mut Vec<f32>) {
+        for i in (1..block.len()).rev() {
+            block[i] -= self.coefficient * block[i - 1];
+        }
+    }
+}
+
+/// the window, scaled to a mean power of 1 so that the level of a tone does not depend on it
+pub struct Windowing {
+    coefficients: Vec<f32>,
+}
+
+impl Windowing {
+    pub fn new(window: WindowType, size: usize) -> Windowing {
+        let coefficients: Vec<f32> = (0..size).map(|i| window.coefficient(i, size)).collect();
+        let power = coefficients.iter().map(|w| w * w).sum::<f32>() / size as f32;
+        let scale = 1. / power.sqrt();
+        Windowing {
+            coefficients: coefficients.iter().map(|w| w * scale).collect(),
+        }
+    }
+}
+
+impl Stage for Windowing {
+    fn name(&self) -> &str {
+        "window"
+    }
+
+    fn accepts(&self, input: Domain) -> bool {
+        input == Domain::Time
+    }
+
+    fn process(&mut self, block: &mut Vec<f32>) {
+        for (sample, w) in block.iter_mut().zip(self.coefficients.iter()) {
+            *sample *= w;
+        }
+    }
+}
+
+/// power spectrum of the frame, whose length must be a power of two
+pub struct Fft;
+
+impl Stage for Fft {
+    fn name(&self) -> &str {
+        "fft"
+    }
+
+    fn accepts(&self, input: Domain) -> bool {
+        input == Domain::Time
+    }
+
+    fn output(&self, _: Domain) -> Domain {
+        Domain::Spectrum
+    }
+
+    fn process(&mut self, block: &mut Vec<f32>) {
+        let size = block.len();
+        let mut interleaved = Vec::with_capacity(2 * size);
+        for sample in block.iter() {
+            interleaved.push(Cell::new(*sample));
+            interleaved.push(Cell::new(0.));
+        }
+        let spectrum = fft::fft(interleaved);
+        // Parseval, a full scale sine comes out at 1
+        let normalization = 4. / (size as f32 * size as f32);
+        block.clear();
+        block.extend((0..=size / 2).map(|k| {
+            let (re, im) = (spectrum[2 * k].get(), spectrum[2 * k + 1].get());
+            (re * re + im * im) * normalization
+        }));
+    }
+}
+
+/// applies a weighting curve to every FFT bin
+pub struct FrequencyWeighting {
+    weights: Vec<f32>,
+}
+
+impl FrequencyWeighting {
+    pub fn new(weighting: Weighting, sample_rate: u32, fft_size: usize) -> FrequencyWeighting {
+        FrequencyWeighting {
+            weights: (0..=fft_size / 2)
+                .map(|k| weighting.gain(k as f32 * sample_rate as f32 / fft_size as f32))
+                .collect(),
+        }
+    }
+}
+
+impl Stage for FrequencyWeighting {
+    fn name(&self) -> &str {
+        "weighting"
+    }
+
+    fn accepts(&self, input: Domain) -> bool {
+        input == Domain::Spectrum
+    }
+
+    fn process(&mut self, block: &mut Vec<f32>) {
+        for (power, weight) in block.iter_mut().zip(self.weights.iter()) {
+            *power *= weight;
+        }
+    }
+}
+
+/// sums the FFT bins into third octaves or linear bands
+pub struct Binning {
+    bands: Vec<Band>,
+    bin_bands: Vec<Option<usize>>, // band of every FFT bin
+}
+
+impl Binning {
+    pub fn new(bins: Option<usize>, sample_rate: u32, fft_size: usize) -> Binning {
+        let edges = bands(sample_rate, bins);
+        // DC is left out, it would only show up in the lowest linear band
+        let bin_bands = (0..=fft_size / 2)
+            .map(|k| {
+                let freq = k as f32 * sample_rate as f32 / fft_size as f32;
+                edges
+                    .iter()
+                    .position(|(lower, upper)| k > 0 && freq >= *lower && freq < *upper)
+            })
+            .collect();
+        Binning {
+            bands: centred(&edges, bins),
+            bin_bands,
+        }
+    }
+}
+
+impl Stage for Binning {
+    fn name(&self) -> &str {
+        "bins"
+    }
+
+    fn accepts(&self, input: Domain) -> bool {
+        input == Domain::Spectrum
+    }
+
+    fn output(&self, _: Domain) -> Domain {
+        Domain::Bands
+    }
+
+    fn process(&mut self, block: &mut Vec<f32>) {
+        let mut power = vec![0f32; self.bands.len()];
+        for (bin, band) in block.iter().zip(self.bin_bands.iter()) {
+            if let Some(band) = *band {
+                power[band] += bin;
+            }
+        }
+        *block = power;
+    }
+
+    fn bands(&self) -> Option<Vec<Band>> {
+        Some(self.bands.clone())
+    }
+}
+
+/// exponential average over frames, the closer the factor is to 1 the slower it follows
+pub struct Smoothing {
+    factor: f32,
+    previous: Vec<f32>,
+}
+
+impl Smoothing {
+    pub fn new(factor: f32) -> Smoothing {
+        Smoothing {
+            factor,
+            previous: Vec::new(),
+        }
+    }
+}
+
+impl Stage for Smoothing {
+    fn name(&self) -> &str {
+        "smoothing"
+    }
+
+    fn accepts(&self, input: Domain) -> bool {
+        input != Domain::Time
+    }
+
+    fn process(&mut self, block: &mut Vec<f32>) {
+        if self.previous.len() == block.len() {
+            for (value, previous) in block.iter_mut().zip(self.previous.iter()) {
+                *value = self.factor * previous + (1. - self.factor) * *value;
+            }
+        }
+        self.previous.clone_from(block);
+    }
+
+    fn reset(&mut self) {
+        self.previous.clear();
+    }
+}
+
+/// power to dB, floored at MIN_DB
+pub struct Decibels;
+
+impl Stage for Decibels {
+    fn name(&self) -> &str {
+        "db"
+    }
+
+    fn accepts(&self, input: Domain) -> bool {
+        input == Domain::Spectrum || input == Domain::Bands
+    }
+
+    fn output(&self, _: Domain) -> Domain {
+        Domain::Levels
+    }
+
+    fn process(&mut self, block: &mut Vec<f32>) {
+        for value in block.iter_mut() {
+            *value = (10. * value.log10()).max(MIN_DB);
+        }
+    }
+}
+
+/// a stage in a pipeline spec, the ones without a value take it from the `Settings`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum StageSpec {
+    Dc,
+    PreEmphasis(f32),
+    Window(Option<WindowType>),
+    Fft,
+    Weighting(Option<Weighting>),
+    Bins(Option<usize>),
+    Smoothing(f32),
+    Db,
+}
+
+impl StageSpec {
+    fn stage(self, settings: &Settings, sample_rate: u32) -> Box<dyn Stage> {
+        let size = settings.fft_size;
+        match self {
+            StageSpec::Dc => Box::new(DcRemoval),
+            StageSpec::PreEmphasis(coefficient) => Box::new(PreEmphasis::new(coefficient)),
+            StageSpec::Window(window) => {
+                Box::new(Windowing::new(window.unwrap_or(settings.window), size))
+            }
+            StageSpec::Fft => Box::new(Fft),
+            StageSpec::Weighting(weighting) => Box::new(FrequencyWeighting::new(
+                weighting.unwrap_or(settings.weighting),
+                sample_rate,
+                size,
+            )),
+            StageSpec::Bins(bins) => {
+                Box::new(Binning::new(bins.or(settings.bins), sample_rate, size))
+            }
+            StageSpec::Smoothing(factor) => Box::new(Smoothing::new(factor)),
+            StageSpec::Db => Box::new(Decibels),
+        }
+    }
+}
+
+impl FromStr for StageSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.trim().splitn(2, '=');
+        let name = parts.next().unwrap_or("").to_lowercase();
+        let value = parts.next();
+        let number = |default: f32| -> Result<f32, String> {
+            match value {
+                Some(value) => value
+                    .parse()
+                    .map_err(|_| format!("Invalid value {} of stage {}", value, name)),
+                None => Ok(default),
+            }
+        };
+        let spec = match name.as_str() {
+            "dc" => StageSpec::Dc,
+            "preemphasis" => StageSpec::PreEmphasis(number(0.97)?),
+            "window" => StageSpec::Window(value.map(str::parse).transpose()?),
+            "fft" => StageSpec::Fft,
+            "weighting" => StageSpec::Weighting(value.map(str::parse).transpose()?),
+            "bins" => StageSpec::Bins(
+                value
+                    .map(str::parse)
+                    .transpose()
+                    .map_err(|_| format!("Invalid number of bins {}", value.unwrap_or("")))?,
+            ),
+            "smoothing" => StageSpec::Smoothing(number(0.5)?),
+            "db" => StageSpec::Db,
+            _ => {
+                return Err(format!(
+                    "Unknown stage {}, expected one of dc, preemphasis, window, fft, weighting, \
+                     bins, smoothing, db",
+                    name
+                ))
+            }
+        };
+        if let StageSpec::Smoothing(factor) | StageSpec::PreEmphasis(factor) = spec {
+            if !(0. ..1.).contains(&factor) {
+                return Err(format!(
+                    "The {} factor must be in 0..1, not {}",
+                    name, factor
+                ));
+            }
+        }
+        Ok(spec)
+    }
+}
+
+/// comma separated stages, in order
+#[derive(Clone, Debug, PartialEq)]
+pub struct PipelineSpec(pub Vec<StageSpec>);
+
+impl PipelineSpec {
+    /// the settings with the values given in the spec, so that both describe the same spectrum
+    pub fn settings(&self, mut settings: Settings) -> Settings {
+        for stage in self.0.iter() {
+            match *stage {
+                StageSpec::Window(Some(window)) => settings.window = window,
+                StageSpec::Weighting(Some(weighting)) => settings.weighting = weighting,
+                StageSpec::Bins(Some(bins)) => settings.bins = Some(bins),
+                StageSpec::Smoothing(factor) => settings.smoothing = Some(factor),
+                _ => {}
+            }
+        }
+        settings
+    }
+}
+
+impl FromStr for PipelineSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(str::parse)
+            .collect::<Result<Vec<_>, _>>()
+            .map(PipelineSpec)
+    }
+}
+
+/// the stages a frame goes through, from `Domain::Time` to `Domain::Levels`
+pub struct Pipeline {
+    stages: Vec<Box<dyn Stage>>,
+    sample_rate: u32,
+    fft_size: usize,
+    bands: Vec<Band>,
+}
+
+impl Pipeline {
+    pub fn builder(sample_rate: u32, fft_size: usize) -> PipelineBuilder {
+        PipelineBuilder {
+            sample_rate,
+            fft_size,
+            stages: Vec::new(),
+        }
+    }
+
+    /// window, FFT, weighting, binning, smoothing if set, and dB
+    pub fn from_settings(settings: &Settings, sample_rate: u32) -> Pipeline {
+        let builder = Pipeline::builder(sample_rate, settings.fft_size)
+            .window(settings.window)
+            .fft()
+            .weighting(settings.weighting)
+            .binning(settings.bins);
+        match settings.smoothing {
+            Some(factor) => builder.smoothing(factor),
+            None => builder,
+        }
+        .decibels()
+        .build()
+        .expect("the default pipeline is valid")
+    }
+
+    pub fn from_spec(
+        spec: &PipelineSpec,
+        settings: &Settings,
+        sample_rate: u32,
+    ) -> Result<Pipeline, Error> {
+        spec.0
+            .iter()
+            .fold(
+                Pipeline::builder(sample_rate, settings.fft_size),
+                |builder, stage| builder.stage(stage.stage(settings, sample_rate)),
+            )
+            .build()
+    }
+
+    /// runs a frame of `fft_size` samples through every stage
+    pub fn process(&mut self, frame: &[f32]) -> Vec<f32> {
+        let mut block = frame.to_vec();
+        for stage in self.stages.iter_mut() {
+            stage.process(&mut block);
+        }
+        block
+    }
+
+    /// the bands of the levels, every FFT bin is its own band when nothing bins them
+    pub fn bands(&self) -> &[Band] {
+        &self.bands
+    }
+
+    pub fn fft_size(&self) -> usize {
+        self.fft_size
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.stages.iter().map(|stage| stage.name()).collect()
+    }
+
+    pub fn insert(&mut self, index: usize, stage: Box<dyn Stage>) -> Result<(), Error> {
+        if index > self.stages.len() {
+            return Err(Error::Config(format!(
+                "No position {} in the pipeline",
+                index
+            )));
+        }
+        self.stages.insert(index, stage);
+        if let Err(err) = self.update() {
+            self.stages.remove(index);
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// takes the first stage with this name out
+    pub fn remove(&mut self, name: &str) -> Result<Box<dyn Stage>, Error> {
+        let index = self.position(name)?;
+        let stage = self.stages.remove(index);
+        match self.update() {
+            Ok(()) => Ok(stage),
+            Err(err) => {
+                self.stages.insert(index, stage);
+                Err(err)
+            }
+        }
+    }
+
+    /// puts a stage in place of the first one with this name, returns the old one
+    pub fn replace(&mut self, name: &str, stage: Box<dyn Stage>) -> Result<Box<dyn Stage>, Error> {
+        let index = self.position(name)?;
+        let old = std::mem::replace(&mut self.stages[index], stage);
+        match self.update() {
+            Ok(()) => Ok(old),
+            Err(err) => {
+                self.stages[index] = old;
+                Err(err)
+            }
+        }
+    }
+
+    /// moves the first stage with this name to another position
+    pub fn reorder(&mut self, name: &str, index: usize) -> Result<(), Error> {
+        let from = self.position(name)?;
+        if index >= self.stages.len() {
+            return Err(Error::Config(format!(
+                "No position {} in the pipeline",
+                index
+            )));
+        }
+        let stage = self.stages.remove(from);
+        self.stages.insert(index, stage);
+        if let Err(err) = self.update() {
+            let stage = self.stages.remove(index);
+            self.stages.insert(from, stage);
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// rebuilds the window, weighting, binning and smoothing stages for new settings, smoothing
+    /// goes in before the dB conversion when it is turned on; other stages are kept as they are
+    pub fn reconfigure(&mut self, settings: &Settings) -> Result<(), Error> {
+        let (sample_rate, size) = (self.sample_rate, settings.fft_size);
+        // planned next to the current stages, which stay untouched unless the plan checks out
+        let mut plan: Vec<Slot> = Vec::with_capacity(self.stages.len() + 1);
+        let mut smoothing = None;
+        for (index, stage) in self.stages.iter().enumerate() {
+            match stage.name() {
+                "window" => plan.push(Slot::New(Box::new(Windowing::new(settings.window, size)))),
+                "weighting" => plan.push(Slot::New(Box::new(FrequencyWeighting::new(
+                    settings.weighting,
+                    sample_rate,
+                    size,
+                )))),
+                "bins" => plan.push(Slot::New(Box::new(Binning::new(
+                    settings.bins,
+                    sample_rate,
+                    size,
+                )))),
+                "smoothing" => smoothing = Some(plan.len()),
+                _ => plan.push(Slot::Keep(index)),
+            }
+        }
+        if let Some(factor) = settings.smoothing {
+            let index = smoothing
+                .or_else(|| {
+                    plan.iter()
+                        .position(|slot| self.planned(slot).name() == "db")
+                })
+                .unwrap_or(plan.len());
+            plan.insert(index, Slot::New(Box::new(Smoothing::new(factor))));
+        }
+        let planned = plan.iter().map(|slot| self.planned(slot));
+        let bands = check(planned, sample_rate, size)?;
+
+        let mut current: Vec<Option<Box<dyn Stage>>> = self.stages.drain(..).map(Some).collect();
+        self.stages = plan
+            .into_iter()
+            .filter_map(|slot| match slot {
+                Slot::Keep(index) => current[index].take(),
+                Slot::New(stage) => Some(stage),
+            })
+            .collect();
+        self.stages.iter_mut().for_each(|stage| stage.reset());
+        self.fft_size = size;
+        self.bands = bands;
+        Ok(())
+    }
+
+    fn planned<'a>(&'a self, slot: &'a Slot) -> &'a dyn Stage {
+        match slot {
+            Slot::Keep(index) => self.stages[*index].as_ref(),
+            Slot::New(stage) => stage.as_ref(),
+        }
+    }
+
+    fn position(&self, name: &str) -> Result<usize, Error> {
+        self.stages
+            .iter()
+            .position(|stage| stage.name() == name)
+            .ok_or_else(|| Error::Config(format!("No stage {} in the pipeline", name)))
+    }
+
+    // checks the new order and starts over from the next frame
+    fn update(&mut self) -> Result<(), Error> {
+        let stages = self.stages.iter().map(|stage| stage.as_ref());
+        self.bands = check(stages, self.sample_rate, self.fft_size)?;
+        self.stages.iter_mut().for_each(|stage| stage.reset());
+        Ok(())
+    }
+}
+
+// every stage has to accept what the previous one outputs and the last one has to give levels
+// a stage of the pipeline reconfigure builds
+enum Slot {
+    Keep(usize), // index of a current stage
+    New(Box<dyn Stage>),
+}
+
+fn check<'a, I>(stages: I, sample_rate: u32, fft_size: usize) -> Result<Vec<Band>, Error>
+where
+    I: IntoIterator<Item = &'a dyn Stage>,
+{
+    let resolution = sample_rate as f32 / fft_size as f32;
+    let mut bands: Vec<Band> = (0..=fft_size / 2)
+        .map(|k| Band {
+            lower: ((k as f32 - 0.5) * resolution).max(0.),
+            centre: k as f32 * resolution,
+            upper: (k as f32 + 0.5) * resolution,
+        })
+        .collect();
+    let mut domain = Domain::Time;
+    for stage in stages {
+        if !stage.accepts(domain) {
+            return Err(Error::Config(format!(
+                "Stage {} cannot take {:?} blocks",
+                stage.name(),
+                domain
+            )));
+        }
+        domain = stage.output(domain);
+        if let Some(binned) = stage.bands() {
+            bands = binned;
+        }
+    }
+    if domain != Domain::Levels {
+        return Err(Error::Config(format!(
+            "The pipeline ends with {:?} blocks instead of levels, add a db stage",
+            domain
+        )));
+    }
+    Ok(bands)
+}
+
+impl fmt::Debug for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Pipeline({})", self.names().join(","))
+    }
+}
+
+/// puts stages together in order, `build` checks that they fit
+pub struct PipelineBuilder {
+    sample_rate: u32,
+    fft_size: usize,
+    stages: Vec<Box<dyn Stage>>,
+}
+
+impl PipelineBuilder {
+    pub fn dc_removal(self) -> PipelineBuilder {
+        self.stage(Box::new(DcRemoval))
+    }
+
+    pub fn pre_emphasis(self, coefficient: f32) -> PipelineBuilder {
+        self.stage(Box::new(PreEmphasis::new(coefficient)))
+    }
+
+    pub fn window(self, window: WindowType) -> PipelineBuilder {
+        let size = self.fft_size;
+        self.stage(Box::new(Windowing::new(window, size)))
+    }
+
+    pub fn fft(self) -> PipelineBuilder {
+        self.stage(Box::new(Fft))
+    }
+
+    pub fn weighting(self, weighting: Weighting) -> PipelineBuilder {
+        let (sample_rate, size) = (self.sample_rate, self.fft_size);
+        self.stage(Box::new(FrequencyWeighting::new(
+            weighting,
+            sample_rate,
+            size,
+        )))
+    }
+
+    pub fn binning(self, bins: Option<usize>) -> PipelineBuilder {
+        let (sample_rate, size) = (self.sample_rate, self.fft_size);
+        self.stage(Box::new(Binning::new(bins, sample_rate, size)))
+    }
+
+    pub fn smoothing(self, factor: f32) -> PipelineBuilder {
+        self.stage(Box::new(Smoothing::new(factor)))
+    }
+
+    pub fn decibels(self) -> PipelineBuilder {
+        self.stage(Box::new(Decibels))
+    }
+
+    /// any stage, including ones made outside of this module
+    pub fn stage(mut self, stage: Box<dyn Stage>) -> PipelineBuilder {
+        self.stages.push(stage);
+        self
+    }
+
+    pub fn build(self) -> Result<Pipeline, Error> {
+        if !self.fft_size.is_power_of_two() {
+            return Err(Error::Config(format!(
+                "The FFT size must be a power of two, not {}",
+                self.fft_size
+            )));
+        }
+        let stages = self.stages.iter().map(|stage| stage.as_ref());
+        let bands = check(stages, self.sample_rate, self.fft_size)?;
+        Ok(Pipeline {
+            stages: self.stages,
+            sample_rate: self.sample_rate,
+            fft_size: self.fft_size,
+            bands,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(freq: f32, offset: f32, len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|i| offset + 0.5 * (2. * std::f32::consts::PI * freq * i as f32 / 44100.).sin())
+            .collect()
+    }
+
+    // doubles every value, to check that a stage from outside fits in
+    struct Gain;
+
+    impl Stage for Gain {
+        fn name(&self) -> &str {
+            "gain"
+        }
+
+        fn accepts(&self, input: Domain) -> bool {
+            input == Domain::Bands
+        }
+
+        fn process(&mut self, block: &mut Vec<f32>) {
+            block.iter_mut().for_each(|value| *value *= 2.);
+        }
+    }
+
+    #[test]
+    fn checks_the_order() {
+        assert!(Pipeline::builder(44100, 1024).fft().build().is_err());
+        assert!(Pipeline::builder(44100, 1024)
+            .window(WindowType::Hann)
+            .decibels()
+            .build()
+            .is_err());
+        // no binning, one level per FFT bin
+        let pipeline = Pipeline::builder(44100, 1024)
+            .fft()
+            .decibels()
+            .build()
+            .unwrap();
+        assert_eq!(pipeline.bands().len(), 513);
+    }
+
+    #[test]
+    fn own_stages_and_rearranging() {
+        let mut pipeline = Pipeline::builder(44100, 2048)
+            .window(WindowType::Hann)
+            .fft()
+            .binning(Some(10))
+            .stage(Box::new(Gain))
+            .decibels()
+            .build()
+            .unwrap();
+        let frame = sine(5000., 0., 2048);
+        let doubled = pipeline.process(&frame);
+        pipeline.remove("gain").unwrap();
+        let plain = pipeline.process(&frame);
+        assert!((doubled[2] - plain[2] - 10. * 2f32.log10()).abs() < 1e-3);
+
+        // binning cannot come before the FFT, the pipeline is left as it was
+        assert!(pipeline.reorder("bins", 0).is_err());
+        assert_eq!(pipeline.names(), vec!["window", "fft", "bins", "db"]);
+        assert!(pipeline.insert(4, Box::new(Gain)).is_err());
+        pipeline.insert(0, Box::new(DcRemoval)).unwrap();
+        pipeline.reorder("dc", 1).unwrap();
+        assert_eq!(pipeline.names(), vec!["window", "dc", "fft", "bins", "db"]);
+        let old = pipeline
+            .replace("bins", Box::new(Binning::new(None, 44100, 2048)))
+            .unwrap();
+        assert_eq!(old.name(), "bins");
+        assert_eq!(pipeline.bands().len(), 31);
+    }
+
+    #[test]
+    fn dc_removal_clears_the_offset() {
+        let spec: PipelineSpec = "dc,window=rectangular,fft,db".parse().unwrap();
+        let mut pipeline = Pipeline::from_spec(&spec, &Settings::default(), 44100).unwrap();
+        let levels = pipeline.process(&sine(1000., 0.3, 2048));
+        assert!(levels[0] < -60.);
+    }
+
+    #[test]
+    fn smoothing_follows_slowly() {
+        let mut pipeline = Pipeline::builder(44100, 2048)
+            .window(WindowType::Hann)
+            .fft()
+            .binning(None)
+            .smoothing(0.5)
+            .decibels()
+            .build()
+            .unwrap();
+        let loud = sine(1000., 0., 2048);
+        let first = pipeline.process(&loud)[17];
+        let silent = pipeline.process(&[0.; 2048])[17];
+        // half the power is left, 3 dB down
+        assert!((first - silent - 3.).abs() < 0.1, "{} {}", first, silent);
+    }
+
+    #[test]
+    fn reconfigures_in_place() {
+        let spec: PipelineSpec = "dc,window,fft,bins,db".parse().unwrap();
+        let mut pipeline = Pipeline::from_spec(&spec, &Settings::default(), 44100).unwrap();
+        let settings = Settings {
+            fft_size: 1024,
+            bins: Some(8),
+            smoothing: Some(0.5),
+            ..Settings::default()
+        };
+        pipeline.reconfigure(&settings).unwrap();
+        assert_eq!(
+            pipeline.names(),
+            vec!["dc", "window", "fft", "bins", "smoothing", "db"]
+        );
+        assert_eq!(pipeline.fft_size(), 1024);
+        assert_eq!(pipeline.bands().len(), 8);
+        assert_eq!(pipeline.process(&[0.; 1024]).len(), 8);
+        pipeline.reconfigure(&Settings::default()).unwrap();
+        assert_eq!(pipeline.names(), vec!["dc", "window", "fft", "bins", "db"]);
+        assert_eq!(pipeline.bands().len(), 31);
+    }
+
+    // named like the window but taking the spectrum, reconfigure would put a real window there
+    struct Misnamed;
+
+    impl Stage for Misnamed {
+        fn name(&self) -> &str {
+            "window"
+        }
+
+        fn accepts(&self, input: Domain) -> bool {
+            input == Domain::Spectrum
+        }
+
+        fn process(&mut self, _block: &mut Vec<f32>) {}
+    }
+
+    #[test]
+    fn failed_reconfigure_keeps_the_pipeline() {
+        let mut pipeline = Pipeline::builder(44100, 1024)
+            .fft()
+            .stage(Box::new(Misnamed))
+            .binning(Some(8))
+            .decibels()
+            .build()
+            .unwrap();
+        let settings = Settings {
+            fft_size: 2048,
+            bins: Some(16),
+            smoothing: Some(0.5),
+            ..Settings::default()
+        };
+        assert!(pipeline.reconfigure(&settings).is_err());
+        assert_eq!(pipeline.names(), vec!["fft", "window", "bins", "db"]);
+        assert_eq!(pipeline.fft_size(), 1024);
+        assert_eq!(pipeline.bands().len(), 8);
+        assert_eq!(pipeline.process(&[0.; 1024]).len(), 8);
+    }
+
+    #[test]
+    fn parses_specs() {
+        let spec: PipelineSpec = "dc, preemphasis=0.9,window,fft,weighting=a,bins=20,smoothing,db"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            spec.0,
+            vec![
+                StageSpec::Dc,
+                StageSpec::PreEmphasis(0.9),
+                StageSpec::Window(None),
+                StageSpec::Fft,
+                StageSpec::Weighting(Some(Weighting::A)),
+                StageSpec::Bins(Some(20)),
+                StageSpec::Smoothing(0.5),
+                StageSpec::Db,
+            ]
+        );
+        assert!("fft,magic".parse::<PipelineSpec>().is_err());
+        assert!("smoothing=1.5".parse::<PipelineSpec>().is_err());
+        assert!("bins=many".parse::<PipelineSpec>().is_err());
+    }
+}
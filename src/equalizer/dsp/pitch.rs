@@ -0,0 +1,111 @@
+//! YIN fundamental frequency estimator (de Cheveigné & Kawahara, 2002)
+
+const THRESHOLD: f32 = 0.15; // absolute threshold on the normalized difference
+const MIN_FREQ: f32 = 30.;
+const MAX_FREQ: f32 = 4200.;
+
+/// returns the detected frequency in Hz or None if the frame has no clear periodicity,
+/// the integration window is half of the frame so it should hold at least two periods of MIN_FREQ
+pub fn yin(samples: &[f32], sample_rate: u32) -> Option<f32> {
+    let window = samples.len() / 2;
+    let min_tau = ((sample_rate as f32 / MAX_FREQ) as usize).max(2);
+    let max_tau = ((sample_rate as f32 / MIN_FREQ) as usize).min(window);
+    if min_tau >= max_tau {
+        return None;
+    }
+
+    // difference function and its cumulative mean normalized version in one pass
+    let mut cmnd = vec![1.; max_tau + 1];
+    let mut running_sum = 0.;
+    for tau in 1..=max_tau {
+        let diff: f32 = samples[..window]
+            .iter()
+            .zip(&samples[tau..tau + window])
+            .map(|(a, b)| (a - b) * (a - b))
+            .sum();
+        running_sum += diff;
+        cmnd[tau] = if running_sum > 0. {
+            diff * tau as f32 / running_sum
+        } else {
+            1.
+        };
+    }
+
+    // first dip under the threshold, followed down to its local minimum
+    let mut tau = min_tau;
+    while tau < max_tau {
+        if cmnd[tau] < THRESHOLD {
+            while tau + 1 < max_tau && cmnd[tau + 1] < cmnd[tau] {
+                tau += 1;
+            }
+            return Some(sample_rate as f32 / interpolate(&cmnd, tau));
+        }
+        tau += 1;
+    }
+    None
+}
+
+// parabolic interpolation around the minimum for sub-sample accuracy
+fn interpolate(cmnd: &[f32], tau: usize) -> f32 {
+    if tau < 1 || tau + 1 >= cmnd.len() {
+        return tau as f32;
+    }
+    let (prev, curr, next) = (cmnd[tau - 1], cmnd[tau], cmnd[tau + 1]);
+    let denominator = prev - 2. * curr + next;
+    if denominator.abs() < f32::EPSILON {
+        return tau as f32;
+    }
+    tau as f32 + 0.5 * (prev - next) / denominator
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // fundamental with a few decaying harmonics, like a plucked string
+    fn harmonic_tone(freq: f32, sample_rate: u32, len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|i| {
+                let t = i as f32 / sample_rate as f32;
+                (1..=5)
+                    .map(|h| (2. * std::f32::consts::PI * freq * h as f32 * t).sin() / h as f32)
+                    .sum::<f32>()
+                    * 0.4
+            })
+            .collect()
+    }
+
+    #[test]
+    fn detects_harmonic_tones() {
+        for &freq in &[41.2, 82.41, 110., 196., 261.63, 440., 659.25, 1318.5] {
+            let detected = yin(&harmonic_tone(freq, 44100, 4096), 44100).unwrap();
+            assert!(
+                (detected - freq).abs() / freq < 0.002,
+                "Expected {} Hz, detected {} Hz",
+                freq,
+                detected
+            );
+        }
+    }
+
+    #[test]
+    fn missing_fundamental() {
+        // only the 2nd..5th harmonics of 110 Hz, the ear still hears 110 Hz and so should YIN
+        let tone: Vec<f32> = (0..4096)
+            .map(|i| {
+                let t = i as f32 / 44100.;
+                (2..=5)
+                    .map(|h| (2. * std::f32::consts::PI * 110. * h as f32 * t).sin())
+                    .sum::<f32>()
+                    * 0.2
+            })
+            .collect();
+        let detected = yin(&tone, 44100).unwrap();
+        assert!((detected - 110.).abs() < 0.5, "detected {} Hz", detected);
+    }
+
+    #[test]
+    fn silence_has_no_pitch() {
+        assert!(yin(&[0.; 4096], 44100).is_none());
+    }
+}
@@ -0,0 +1,186 @@
+//! long-term power spectral density by Welch's method: the average periodogram of overlapping
+//! windowed segments, one-sided and in power per Hz
+
+use super::fft;
+use super::graphic::OCTAVE_CENTRES;
+use super::window::WindowType;
+use std::cell::Cell;
+
+pub struct Band {
+    pub lower: f32,
+    pub centre: f32,
+    pub upper: f32,
+    pub density: f32, // mean density of the bins in the band
+}
+
+pub struct Welch {
+    segment: usize,
+    hop: usize,
+    sample_rate: u32,
+    window: Vec<f32>,
+    scale: f32,    // 1 / (fs * sum of squared window), makes a periodogram a density
+    sum: Vec<f64>, // of the periodograms so far
+    segments: usize,
+    buffer: Vec<f32>,
+}
+
+impl Welch {
+    pub fn new(
+        segment: usize,
+        overlap: f32,
+        window: WindowType,
+        sample_rate: u32,
+    ) -> Result<Welch, String> {
+        if !segment.is_power_of_two() || segment < 16 {
+            return Err(format!(
+                "The segment length must be a power of two of at least 16, not {}",
+                segment
+            ));
+        }
+        if !(0. ..1.).contains(&overlap) {
+            return Err(format!("The overlap must be in 0..1, not {}", overlap));
+        }
+        let window: Vec<f32> = (0..segment)
+            .map(|i| window.coefficient(i, segment))
+            .collect();
+        let energy: f32 = window.iter().map(|w| w * w).sum();
+        Ok(Welch {
+            segment,
+            hop: ((segment as f32 * (1. - overlap)).round() as usize).max(1),
+            sample_rate,
+            window,
+            scale: 1. / (sample_rate as f32 * energy),
+            sum: vec![0.; segment / 2 + 1],
+            segments: 0,
+            buffer: Vec::with_capacity(2 * segment),
+        })
+    }
+
+    pub fn feed(&mut self, samples: &[f32]) {
+        self.buffer.extend_from_slice(samples);
+        while self.buffer.len() >= self.segment {
+            let mut interleaved = Vec::with_capacity(2 * self.segment);
+            for (sample, w) in self.buffer.iter().zip(self.window.iter()) {
+                interleaved.push(Cell::new(sample * w));
+                interleaved.push(Cell::new(0.));
+            }
+            let spectrum = fft::fft(interleaved);
+            for (k, sum) in self.sum.iter_mut().enumerate() {
+                let (re, im) = (spectrum[2 * k].get(), spectrum[2 * k + 1].get());
+                *sum += f64::from(re * re + im * im);
+            }
+            self.segments += 1;
+            self.buffer.drain(..self.hop);
+        }
+    }
+
+    /// number of segments averaged so far, the variance of the estimate goes down with it
+    pub fn segments(&self) -> usize {
+        self.segments
+    }
+
+    /// width of an FFT bin in Hz
+    pub fn resolution(&self) -> f32 {
+        self.sample_rate as f32 / self.segment as f32
+    }
+
+    /// density of every bin from DC to Nyquist, all zero before the first full segment
+    pub fn density(&self) -> Vec<f32> {
+        let count = self.segments.max(1) as f64;
+        let last = self.sum.len() - 1;
+        self.sum
+            .iter()
+            .enumerate()
+            .map(|(k, sum)| {
+                // the negative frequencies are folded onto the positive ones
+                let fold = if k == 0 || k == last { 1. } else { 2. };
+                (sum / count) as f32 * self.scale * fold
+            })
+            .collect()
+    }
+}
+
+/// averages a density over the octave bands below Nyquist
+pub fn octave_bands(density: &[f32], resolution: f32) -> Vec<Band> {
+    let half_step = 2f32.sqrt();
+    OCTAVE_CENTRES
+        .iter()
+        .filter_map(|&centre| {
+            let (lower, upper) = (centre / half_step, centre * half_step);
+            let bins: Vec<f32> = density
+                .iter()
+                .enumerate()
+                .filter(|(k, _)| {
+                    let freq = *k as f32 * resolution;
+                    freq >= lower && freq < upper
+                })
+                .map(|(_, d)| *d)
+                .collect();
+            if bins.is_empty() {
+                return None;
+            }
+            Some(Band {
+                lower,
+                centre,
+                upper,
+                density: bins.iter().sum::<f32>() / bins.len() as f32,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn noise(len: usize, mut seed: u32) -> Vec<f32> {
+        (0..len)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (seed >> 16) as f32 / 32768. - 1.
+            })
+            .collect()
+    }
+
+    #[test]
+    fn white_noise_is_flat() {
+        let mut welch = Welch::new(1024, 0.5, WindowType::Hann, 48000).unwrap();
+        welch.feed(&noise(48000 * 4, 1));
+        assert_eq!(welch.segments(), 374);
+        // uniform noise in -1..1 has a variance of 1/3, spread over 0..fs/2
+        let expected = 1. / 3. / 24000.;
+        let density = welch.density();
+        let mean = density[1..512].iter().sum::<f32>() / 511.;
+        assert!((mean / expected - 1.).abs() < 0.05, "{}", mean);
+        for band in octave_bands(&density, welch.resolution()).iter().skip(1) {
+            assert!(
+                (band.density / expected - 1.).abs() < 0.25,
+                "{}",
+                band.centre
+            );
+        }
+    }
+
+    #[test]
+    fn sine_power_is_preserved() {
+        let mut welch = Welch::new(2048, 0.75, WindowType::Blackman, 44100).unwrap();
+        let sine: Vec<f32> = (0..44100)
+            .map(|i| 0.5 * (2. * std::f32::consts::PI * 1000. * i as f32 / 44100.).sin())
+            .collect();
+        welch.feed(&sine);
+        let power: f32 = welch.density().iter().sum::<f32>() * welch.resolution();
+        assert!((power - 0.125).abs() < 0.005, "{}", power);
+        let bands = octave_bands(&welch.density(), welch.resolution());
+        let loudest = bands
+            .iter()
+            .max_by(|a, b| a.density.partial_cmp(&b.density).unwrap())
+            .unwrap();
+        assert_eq!(loudest.centre, 1000.);
+    }
+
+    #[test]
+    fn refuses_bad_settings() {
+        assert!(Welch::new(1000, 0.5, WindowType::Hann, 44100).is_err());
+        assert!(Welch::new(1024, 1., WindowType::Hann, 44100).is_err());
+    }
+}
@@ -0,0 +1,334 @@
+//! the queues between the threads: samples go from the audio callback to the DSP thread through a
+//! lock-free ring buffer allocated up front, frames go out through bounded queues that drop the
+//! oldest one when their consumer lags; both count what they dropped. A [`FrameHub`] hands every
+//! frame to any number of such queues, each with its own capacity and [`LagPolicy`]
+
+use crate::ring_buffer::{self, RingBuffer};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, Weak};
+use std::thread::Thread;
+use std::time::Duration;
+
+/// the audio callback side, it never blocks nor allocates
+pub struct SampleProducer {
+    samples: ring_buffer::Producer<f32>,
+    dropped: Arc<AtomicU64>,
+    consumer: Option<Thread>,
+}
+
+/// the DSP side
+pub struct SampleConsumer {
+    samples: ring_buffer::Consumer<f32>,
+}
+
+/// room for at least `capacity` samples; samples that do not fit are counted into `dropped`,
+/// which may outlive the queue, and `consumer` is unparked after every push
+pub fn sample_queue(
+    capacity: usize,
+    dropped: Arc<AtomicU64>,
+    consumer: Option<Thread>,
+) -> (SampleProducer, SampleConsumer) {
+    let (producer, samples) = RingBuffer::spsc(capacity.max(1).next_power_of_two());
+    (
+        SampleProducer {
+            samples: producer,
+            dropped,
+            consumer,
+        },
+        SampleConsumer { samples },
+    )
+}
+
+impl SampleProducer {
+    /// queues the whole block or, when it does not fit, drops all of it so that interleaved
+    /// frames stay whole; false when it was dropped
+    pub fn push(&mut self, block: &[f32]) -> bool {
+        if block.len() > self.samples.free() {
+            self.dropped
+                .fetch_add(block.len() as u64, Ordering::Relaxed);
+            return false;
+        }
+        self.samples.push_slice(block);
+        if let Some(consumer) = &self.consumer {
+            consumer.unpark();
+        }
+        true
+    }
+}
+
+impl SampleConsumer {
+    /// fills the front of `out` with the oldest samples, returns how many there were
+    pub fn pop(&mut self, out: &mut [f32]) -> usize {
+        self.samples.pop_slice(out)
+    }
+
+    pub fn len(&mut self) -> usize {
+        self.samples.size()
+    }
+
+    pub fn is_empty(&mut self) -> bool {
+        self.samples.empty()
+    }
+}
+
+struct Entries<T> {
+    queued: VecDeque<T>,
+    latest: Option<T>,
+}
+
+/// what a full queue does with the next entry, the producer never waits either way
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LagPolicy {
+    DropOldest, // renderers, the newest entries matter most
+    DropNewest, // exporters that want an unbroken run up to the point they fell behind
+    Disconnect, // consumers that cannot handle gaps, the queue stops taking entries
+}
+
+/// keeps the newest `capacity` entries, pushing onto a full queue drops the oldest one unless
+/// another [`LagPolicy`] is chosen
+pub struct FrameQueue<T> {
+    entries: Mutex<Entries<T>>,
+    pushed: Condvar,
+    capacity: usize,
+    policy: LagPolicy,
+    dropped: AtomicU64,
+    closed: AtomicBool,
+}
+
+impl<T: Clone> FrameQueue<T> {
+    pub fn new(capacity: usize) -> FrameQueue<T> {
+        FrameQueue::with_policy(capacity, LagPolicy::DropOldest)
+    }
+
+    pub fn with_policy(capacity: usize, policy: LagPolicy) -> FrameQueue<T> {
+        FrameQueue {
+            entries: Mutex::new(Entries {
+                queued: VecDeque::with_capacity(capacity),
+                latest: None,
+            }),
+            pushed: Condvar::new(),
+            capacity: capacity.max(1),
+            policy,
+            dropped: AtomicU64::new(0),
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    /// false once the queue is closed and takes no more entries
+    pub fn push(&self, entry: T) -> bool {
+        if let Ok(mut entries) = self.entries.lock() {
+            if self.is_closed() {
+                return false;
+            }
+            if entries.queued.len() == self.capacity {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                match self.policy {
+                    LagPolicy::DropOldest => {
+                        entries.queued.pop_front();
+                    }
+                    LagPolicy::DropNewest => return true,
+                    LagPolicy::Disconnect => {
+                        self.closed.store(true, Ordering::Relaxed);
+                        self.pushed.notify_all();
+                        return false;
+                    }
+                }
+            }
+            entries.latest = Some(entry.clone());
+            entries.queued.push_back(entry);
+        }
+        self.pushed.notify_one();
+        true
+    }
+
+    /// the oldest entry, for consumers that want every one
+    pub fn pop(&self) -> Option<T> {
+        self.entries.lock().ok()?.queued.pop_front()
+    }
+
+    /// like pop but waits up to timeout for an entry, for consumers on their own thread
+    pub fn pop_timeout(&self, timeout: Duration) -> Option<T> {
+        let entries = self.entries.lock().ok()?;
+        let (mut entries, _) = self
+            .pushed
+            .wait_timeout_while(entries, timeout, |entries| {
+                entries.queued.is_empty() && !self.is_closed()
+            })
+            .ok()?;
+        entries.queued.pop_front()
+    }
+
+    /// the newest entry, the older ones are skipped; None when nothing came since the last call
+    /// or the producer holds the lock, renderers never wait
+    pub fn take_latest(&self) -> Option<T> {
+        let mut entries = self.entries.try_lock().ok()?;
+        let latest = entries.queued.pop_back();
+        entries.queued.clear();
+        latest
+    }
+
+    /// the newest entry ever pushed, whether it was taken or not
+    pub fn latest(&self) -> Option<T> {
+        self.entries.try_lock().ok()?.latest.clone()
+    }
+
+    /// entries lost because the queue was full
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// stops taking entries, what is queued can still be popped; a hub forgets closed queues
+    pub fn close(&self) {
+        // under the lock, a pop_timeout between checking the flag and waiting would miss it
+        let _entries = self.entries.lock();
+        self.closed.store(true, Ordering::Relaxed);
+        self.pushed.notify_all();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Relaxed)
+    }
+}
+
+/// hands every published entry to all its subscribers; it only holds on to their queues weakly,
+/// dropping a subscription or closing it unsubscribes
+pub struct FrameHub<T> {
+    subscribers: Mutex<Vec<Weak<FrameQueue<T>>>>,
+}
+
+impl<T: Clone> FrameHub<T> {
+    pub fn new() -> FrameHub<T> {
+        FrameHub {
+            subscribers: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// a queue that receives every entry published from now on
+    pub fn subscribe(&self, capacity: usize, policy: LagPolicy) -> Arc<FrameQueue<T>> {
+        let queue = Arc::new(FrameQueue::with_policy(capacity, policy));
+        if let Ok(mut subscribers) = self.subscribers.lock() {
+            subscribers.push(Arc::downgrade(&queue));
+        }
+        queue
+    }
+
+    pub fn publish(&self, entry: T) {
+        if let Ok(mut subscribers) = self.subscribers.lock() {
+            subscribers.retain(|subscriber| match subscriber.upgrade() {
+                Some(queue) => queue.push(entry.clone()),
+                None => false,
+            });
+        }
+    }
+
+    pub fn subscribers(&self) -> usize {
+        self.subscribers
+            .lock()
+            .map_or(0, |subscribers| subscribers.len())
+    }
+}
+
+impl<T: Clone> Default for FrameHub<T> {
+    fn default() -> FrameHub<T> {
+        FrameHub::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn drops_whole_blocks() {
+        let dropped = Arc::new(AtomicU64::new(0));
+        let (mut producer, mut consumer) = sample_queue(6, dropped.clone(), None);
+        assert!(producer.push(&[1., 2., 3., 4.]));
+        assert!(!producer.push(&[5., 6., 7., 8., 9.]));
+        assert_eq!(dropped.load(Ordering::Relaxed), 5);
+        let mut out = [0.; 3];
+        assert_eq!(consumer.pop(&mut out), 3);
+        assert_eq!(out, [1., 2., 3.]);
+        // wraps around the end of the 8 sample buffer
+        assert!(producer.push(&[5., 6., 7., 8., 9.]));
+        let mut out = [0.; 8];
+        assert_eq!(consumer.pop(&mut out), 6);
+        assert_eq!(out[..6], [4., 5., 6., 7., 8., 9.]);
+        assert!(consumer.is_empty());
+    }
+
+    #[test]
+    fn keeps_the_order_across_threads() {
+        let dropped = Arc::new(AtomicU64::new(0));
+        let (mut producer, mut consumer) =
+            sample_queue(64, dropped.clone(), Some(thread::current()));
+        let writer = thread::spawn(move || {
+            for block in 0..10_000 {
+                let start = block as f32 * 4.;
+                while !producer.push(&[start, start + 1., start + 2., start + 3.]) {
+                    thread::yield_now();
+                }
+            }
+        });
+        let mut next = 0.;
+        let mut out = [0.; 24];
+        while next < 40_000. {
+            let count = consumer.pop(&mut out);
+            for sample in out[..count].iter() {
+                assert_eq!(*sample, next);
+                next += 1.;
+            }
+        }
+        writer.join().unwrap();
+        assert!(consumer.is_empty());
+    }
+
+    #[test]
+    fn frame_queue_drops_the_oldest() {
+        let queue = FrameQueue::new(2);
+        for frame in 0..5 {
+            queue.push(frame);
+        }
+        assert_eq!(queue.dropped(), 3);
+        assert_eq!(queue.pop(), Some(3));
+        queue.push(5);
+        assert_eq!(queue.take_latest(), Some(5));
+        assert_eq!(queue.take_latest(), None);
+        assert_eq!(queue.pop(), None);
+        assert_eq!(queue.latest(), Some(5));
+    }
+
+    #[test]
+    fn hub_applies_each_policy() {
+        let hub = FrameHub::new();
+        let oldest = hub.subscribe(2, LagPolicy::DropOldest);
+        let newest = hub.subscribe(2, LagPolicy::DropNewest);
+        let strict = hub.subscribe(2, LagPolicy::Disconnect);
+        let gone = hub.subscribe(2, LagPolicy::DropOldest);
+        drop(gone);
+        for frame in 0..4 {
+            hub.publish(frame);
+        }
+        assert_eq!(hub.subscribers(), 2);
+        assert_eq!((oldest.pop(), oldest.pop()), (Some(2), Some(3)));
+        assert_eq!((newest.pop(), newest.pop()), (Some(0), Some(1)));
+        assert_eq!(newest.dropped(), 2);
+        assert!(strict.is_closed());
+        assert_eq!(
+            (strict.pop(), strict.pop(), strict.pop()),
+            (Some(0), Some(1), None)
+        );
+    }
+
+    #[test]
+    fn subscribers_wait_for_frames() {
+        let hub = Arc::new(FrameHub::new());
+        let queue = hub.subscribe(4, LagPolicy::DropOldest);
+        let publisher = hub.clone();
+        let sender = thread::spawn(move || publisher.publish(7));
+        assert_eq!(queue.pop_timeout(Duration::from_secs(10)), Some(7));
+        sender.join().unwrap();
+        assert_eq!(queue.pop_timeout(Duration::from_millis(1)), None);
+    }
+}
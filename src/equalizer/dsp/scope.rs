@@ -0,0 +1,176 @@
+//! time-domain view of the incoming signal, an oscilloscope with edge triggering
+
+// 1-2-5 sequence of screen widths in milliseconds, as found on most scopes
+const TIMEBASES: [f32; 9] = [1., 2., 5., 10., 20., 50., 100., 200., 500.];
+const DEFAULT_TIMEBASE: usize = 3; // 10 ms
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TriggerEdge {
+    Rising,
+    Falling,
+}
+
+pub struct Scope {
+    history: Vec<f32>,
+    capacity: usize,
+    sample_rate: u32,
+    edge: TriggerEdge,
+    level: f32,
+    timebase_idx: usize,
+    hold: bool,
+}
+
+impl Scope {
+    pub fn new(sample_rate: u32) -> Scope {
+        // keep two screens of the widest timebase so there is always room to look for a trigger
+        let capacity = 2 * Scope::samples_for(sample_rate, *TIMEBASES.last().unwrap());
+        Scope {
+            history: Vec::with_capacity(2 * capacity),
+            capacity,
+            sample_rate,
+            edge: TriggerEdge::Rising,
+            level: 0.,
+            timebase_idx: DEFAULT_TIMEBASE,
+            hold: false,
+        }
+    }
+
+    /// append new samples, dropping the oldest ones, unless the display is frozen
+    pub fn feed(&mut self, samples: &[f32]) {
+        if self.hold {
+            return;
+        }
+        self.history.extend_from_slice(samples);
+        if self.history.len() > self.capacity {
+            let excess = self.history.len() - self.capacity;
+            self.history.drain(..excess);
+        }
+    }
+
+    /// returns a single screen of samples starting at the most recent trigger point,
+    /// if the signal never crosses the level the scope free-runs and shows the newest samples
+    pub fn capture(&self) -> Vec<f32> {
+        let width = self.window_len();
+        if self.history.len() <= width {
+            return self.history.clone();
+        }
+
+        let last_start = self.history.len() - width;
+        let start = (1..=last_start)
+            .rev()
+            .find(|&i| self.triggers_at(i))
+            .unwrap_or(last_start);
+        self.history[start..start + width].to_vec()
+    }
+
+    fn triggers_at(&self, idx: usize) -> bool {
+        let (prev, curr) = (self.history[idx - 1], self.history[idx]);
+        match self.edge {
+            TriggerEdge::Rising => prev < self.level && curr >= self.level,
+            TriggerEdge::Falling => prev > self.level && curr <= self.level,
+        }
+    }
+
+    pub fn edge(&self) -> TriggerEdge {
+        self.edge
+    }
+
+    pub fn toggle_edge(&mut self) {
+        self.edge = match self.edge {
+            TriggerEdge::Rising => TriggerEdge::Falling,
+            TriggerEdge::Falling => TriggerEdge::Rising,
+        };
+    }
+
+    pub fn set_edge(&mut self, edge: TriggerEdge) {
+        self.edge = edge;
+    }
+
+    pub fn level(&self) -> f32 {
+        self.level
+    }
+
+    /// the samples are normalized floats, so is the trigger level
+    pub fn set_level(&mut self, level: f32) {
+        self.level = level.clamp(-1., 1.);
+    }
+
+    /// screen width in milliseconds
+    pub fn timebase(&self) -> f32 {
+        TIMEBASES[self.timebase_idx]
+    }
+
+    pub fn zoom_in(&mut self) {
+        self.timebase_idx = self.timebase_idx.saturating_sub(1);
+    }
+
+    pub fn zoom_out(&mut self) {
+        self.timebase_idx = (self.timebase_idx + 1).min(TIMEBASES.len() - 1);
+    }
+
+    pub fn hold(&self) -> bool {
+        self.hold
+    }
+
+    pub fn set_hold(&mut self, hold: bool) {
+        self.hold = hold;
+    }
+
+    pub fn window_len(&self) -> usize {
+        Scope::samples_for(self.sample_rate, self.timebase())
+    }
+
+    fn samples_for(sample_rate: u32, millis: f32) -> usize {
+        (sample_rate as f32 * millis / 1000.) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(freq: f32, sample_rate: u32, len: usize, phase: f32) -> Vec<f32> {
+        (0..len)
+            .map(|i| {
+                (2. * std::f32::consts::PI * freq * i as f32 / sample_rate as f32 + phase).sin()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn rising_trigger_starts_at_level() {
+        let mut scope = Scope::new(44100);
+        scope.feed(&sine(100., 44100, 8192, 1.));
+        let screen = scope.capture();
+        assert_eq!(screen.len(), scope.window_len());
+        assert!(screen[0] >= 0. && screen[0] < 0.05);
+        assert!(
+            screen[1] > screen[0],
+            "The trigger should fire on a rising edge"
+        );
+    }
+
+    #[test]
+    fn falling_trigger_starts_at_level() {
+        let mut scope = Scope::new(44100);
+        scope.set_edge(TriggerEdge::Falling);
+        scope.set_level(0.5);
+        scope.feed(&sine(100., 44100, 8192, 0.));
+        let screen = scope.capture();
+        assert!(screen[0] <= 0.5 && screen[0] > 0.45);
+        assert!(
+            screen[1] < screen[0],
+            "The trigger should fire on a falling edge"
+        );
+    }
+
+    #[test]
+    fn hold_freezes_display() {
+        let mut scope = Scope::new(44100);
+        scope.feed(&sine(100., 44100, 4096, 0.));
+        let before = scope.capture();
+        scope.set_hold(true);
+        scope.feed(&vec![0.; 4096]);
+        assert_eq!(before, scope.capture());
+    }
+}
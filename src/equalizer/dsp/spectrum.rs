@@ -0,0 +1,462 @@
+//! the spectrum shared by the live worker and the offline analysis: frames cut with overlap go
+//! through a `Pipeline`, by default window, FFT, frequency weighting and binning into bands, in
+//! dB relative to a full scale sine
+
+use super::graphic::THIRD_OCTAVE_CENTRES;
+use super::pipeline::Pipeline;
+use super::window::WindowType;
+use crate::errors::Error;
+use std::str::FromStr;
+use std::sync::Arc;
+
+pub const MIN_DB: f32 = -120.; // floor for silent bands, keeps the output finite
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Weighting {
+    Z, // flat
+    A,
+    C,
+}
+
+impl Weighting {
+    /// IEC 61672 weighting as a power factor
+    pub fn gain(self, freq: f32) -> f32 {
+        let f2 = f64::from(freq) * f64::from(freq);
+        let db = match self {
+            Weighting::Z => return 1.,
+            Weighting::A => {
+                let r = 12194f64.powi(2) * f2 * f2
+                    / ((f2 + 20.6f64.powi(2))
+                        * ((f2 + 107.7f64.powi(2)) * (f2 + 737.9f64.powi(2))).sqrt()
+                        * (f2 + 12194f64.powi(2)));
+                20. * r.log10() + 2.
+            }
+            Weighting::C => {
+                let r = 12194f64.powi(2) * f2 / ((f2 + 20.6f64.powi(2)) * (f2 + 12194f64.powi(2)));
+                20. * r.log10() + 0.06
+            }
+        };
+        10f64.powf(db / 10.) as f32
+    }
+
+    pub fn next(self) -> Weighting {
+        match self {
+            Weighting::Z => Weighting::A,
+            Weighting::A => Weighting::C,
+            Weighting::C => Weighting::Z,
+        }
+    }
+}
+
+impl FromStr for Weighting {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "z" | "none" => Ok(Weighting::Z),
+            "a" => Ok(Weighting::A),
+            "c" => Ok(Weighting::C),
+            _ => Err(format!("Unknown weighting {}, expected one of a, c, z", s)),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+/// how the spectrum is computed, `Settings::default()` is what the command line defaults to
+pub struct Settings {
+    pub window: WindowType,
+    pub fft_size: usize,
+    pub overlap: f32, // fraction of a frame shared with the previous one, 0..1
+    pub bins: Option<usize>, // None for the 31 third-octave bands, otherwise linear bands
+    pub weighting: Weighting,
+    pub smoothing: Option<f32>, // averaging factor of the levels over frames, 0..1
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            window: WindowType::Hann,
+            fft_size: 2048,
+            overlap: 0.5,
+            bins: None,
+            weighting: Weighting::Z,
+            smoothing: None,
+        }
+    }
+}
+
+impl Settings {
+    pub fn hop(&self) -> usize {
+        ((self.fft_size as f32 * (1. - self.overlap)).round() as usize).max(1)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.fft_size.is_power_of_two() || self.fft_size < 16 {
+            return Err(format!(
+                "The FFT size must be a power of two of at least 16, not {}",
+                self.fft_size
+            ));
+        }
+        if !(0. ..1.).contains(&self.overlap) {
+            return Err(format!("The overlap must be in 0..1, not {}", self.overlap));
+        }
+        if self.bins == Some(0) {
+            return Err(String::from("There must be at least one bin"));
+        }
+        if let Some(factor) = self.smoothing {
+            if !(0. ..1.).contains(&factor) {
+                return Err(format!("The smoothing must be in 0..1, not {}", factor));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// lower and upper edge of every band in Hz
+pub fn bands(sample_rate: u32, bins: Option<usize>) -> Vec<(f32, f32)> {
+    let nyquist = sample_rate as f32 / 2.;
+    match bins {
+        None => {
+            let half_step = 2f32.powf(1. / 6.);
+            THIRD_OCTAVE_CENTRES
+                .iter()
+                .map(|c| (c / half_step, (c * half_step).min(nyquist)))
+                .collect()
+        }
+        Some(bins) => {
+            let width = nyquist / bins as f32;
+            (0..bins)
+                .map(|i| (i as f32 * width, (i + 1) as f32 * width))
+                .collect()
+        }
+    }
+}
+
+/// edges and centre of a band in Hz
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Band {
+    pub lower: f32,
+    pub centre: f32,
+    pub upper: f32,
+}
+
+/// the centres of bands made by `bands`, the nominal ones for third octaves (the top band may be
+/// cut short at Nyquist) and the middle of linear bins
+pub fn centred(edges: &[(f32, f32)], bins: Option<usize>) -> Vec<Band> {
+    let half_step = 2f32.powf(1. / 6.);
+    edges
+        .iter()
+        .map(|(lower, upper)| Band {
+            lower: *lower,
+            centre: match bins {
+                None => lower * half_step,
+                Some(_) => (lower + upper) / 2.,
+            },
+            upper: *upper,
+        })
+        .collect()
+}
+
+/// short axis label for a band centre, in kHz with one decimal from 1 kHz up
+pub fn band_label(centre: f32) -> String {
+    if centre >= 1000. {
+        format!("{:.1}k", centre / 1000.)
+    } else {
+        format!("{:.0}", centre)
+    }
+}
+
+/// band levels in dB, time in seconds from the first sample fed
+pub struct Frame {
+    pub time: f32,        // of the first sample of the frame, in seconds since the start
+    pub levels: Vec<f32>, // dB per band
+}
+
+/// turns a stream of mono samples into frames of band levels
+pub struct Analyzer {
+    settings: Settings,
+    sample_rate: u32,
+    pipeline: Pipeline,
+    buffer: Vec<f32>,
+    consumed: usize, // samples dropped from the front of the buffer so far
+}
+
+impl Analyzer {
+    pub fn new(settings: Settings, sample_rate: u32) -> Analyzer {
+        let pipeline = Pipeline::from_settings(&settings, sample_rate);
+        Analyzer::build(settings, sample_rate, pipeline)
+    }
+
+    /// frames are cut as set, the pipeline has to be built for the same FFT size
+    pub fn with_pipeline(
+        settings: Settings,
+        sample_rate: u32,
+        pipeline: Pipeline,
+    ) -> Result<Analyzer, Error> {
+        if pipeline.fft_size() != settings.fft_size {
+            return Err(Error::Config(format!(
+                "The pipeline is built for an FFT size of {}, the frames are {} samples",
+                pipeline.fft_size(),
+                settings.fft_size
+            )));
+        }
+        Ok(Analyzer::build(settings, sample_rate, pipeline))
+    }
+
+    fn build(settings: Settings, sample_rate: u32, pipeline: Pipeline) -> Analyzer {
+        Analyzer {
+            settings,
+            sample_rate,
+            pipeline,
+            buffer: Vec::with_capacity(2 * settings.fft_size),
+            consumed: 0,
+        }
+    }
+
+    /// lower and upper edges of the bands the levels are in
+    pub fn bands(&self) -> Vec<(f32, f32)> {
+        self.pipeline
+            .bands()
+            .iter()
+            .map(|band| (band.lower, band.upper))
+            .collect()
+    }
+
+    pub fn centred_bands(&self) -> Vec<Band> {
+        self.pipeline.bands().to_vec()
+    }
+
+    /// takes new settings from the next frame on, the samples buffered so far are kept; the
+    /// stages that follow the settings are rebuilt and the others left alone
+    pub fn reconfigure(&mut self, settings: Settings) -> Result<(), Error> {
+        settings.validate().map_err(Error::Config)?;
+        self.pipeline.reconfigure(&settings)?;
+        self.settings = settings;
+        Ok(())
+    }
+
+    pub fn settings(&self) -> Settings {
+        self.settings
+    }
+
+    /// the stages can be rearranged between two calls to `feed`
+    pub fn pipeline_mut(&mut self) -> &mut Pipeline {
+        &mut self.pipeline
+    }
+
+    pub fn set_pipeline(&mut self, pipeline: Pipeline) -> Result<(), Error> {
+        if pipeline.fft_size() != self.settings.fft_size {
+            return Err(Error::Config(format!(
+                "The pipeline is built for {} samples, not {}",
+                pipeline.fft_size(),
+                self.settings.fft_size
+            )));
+        }
+        self.pipeline = pipeline;
+        Ok(())
+    }
+
+    /// returns every frame completed by the new samples
+    pub fn feed(&mut self, samples: &[f32]) -> Vec<Frame> {
+        self.buffer.extend_from_slice(samples);
+        let (size, hop) = (self.settings.fft_size, self.settings.hop());
+        let mut frames = Vec::new();
+        while self.buffer.len() >= size {
+            frames.push(Frame {
+                time: self.consumed as f32 / self.sample_rate as f32,
+                levels: self.pipeline.process(&self.buffer[..size]),
+            });
+            self.buffer.drain(..hop.min(self.buffer.len()));
+            self.consumed += hop;
+        }
+        frames
+    }
+}
+
+/// an analysed frame with what it takes to draw, label or store it, from the DSP worker to the
+/// front-ends
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpectrumFrame {
+    pub magnitudes: Vec<f32>,   // dB per band, MIN_DB for silence
+    pub bands: Arc<[Band]>,     // shared by all frames of a stream
+    pub channel: Option<usize>, // None for the downmix of all channels
+    pub sample_rate: u32,
+    pub fft_size: usize,
+    pub window: WindowType,
+    pub timestamp: f32, // of the first sample, in seconds since the capture started
+    pub sequence: u64,  // counts the frames of a stream from 0
+}
+
+impl SpectrumFrame {
+    pub fn new(
+        frame: Frame,
+        bands: Arc<[Band]>,
+        settings: &Settings,
+        sample_rate: u32,
+        sequence: u64,
+    ) -> SpectrumFrame {
+        SpectrumFrame {
+            magnitudes: frame.levels,
+            bands,
+            channel: None,
+            sample_rate,
+            fft_size: settings.fft_size,
+            window: settings.window,
+            timestamp: frame.time,
+            sequence,
+        }
+    }
+}
+
+// an empty frame, for the ring buffers of the front-ends
+impl Default for SpectrumFrame {
+    fn default() -> SpectrumFrame {
+        SpectrumFrame::new(
+            Frame {
+                time: 0.,
+                levels: Vec::new(),
+            },
+            Arc::from(Vec::new()),
+            &Settings::default(),
+            0,
+            0,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(freq: f32, amplitude: f32, len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|i| amplitude * (2. * std::f32::consts::PI * freq * i as f32 / 44100.).sin())
+            .collect()
+    }
+
+    fn loudest(levels: &[f32]) -> usize {
+        (0..levels.len())
+            .max_by(|a, b| levels[*a].partial_cmp(&levels[*b]).unwrap())
+            .unwrap()
+    }
+
+    #[test]
+    fn frames_follow_overlap() {
+        let settings = Settings {
+            fft_size: 1024,
+            overlap: 0.75,
+            ..Settings::default()
+        };
+        let mut analyzer = Analyzer::new(settings, 44100);
+        let frames = analyzer.feed(&vec![0.; 4096]);
+        // 1024 long frames every 256 samples
+        assert_eq!(frames.len(), 13);
+        assert!((frames[1].time - 256. / 44100.).abs() < 1e-6);
+        // the rest of the samples is kept for the next call
+        assert_eq!(analyzer.feed(&[0.; 256]).len(), 1);
+    }
+
+    #[test]
+    fn full_scale_sine_is_0_db() {
+        for &window in &[
+            WindowType::Hann,
+            WindowType::Hamming,
+            WindowType::Blackman,
+            WindowType::Rectangular,
+        ] {
+            let settings = Settings {
+                window,
+                ..Settings::default()
+            };
+            let mut analyzer = Analyzer::new(settings, 44100);
+            let frames = analyzer.feed(&sine(1000., 1., 2048));
+            let levels = &frames[0].levels;
+            let band = loudest(levels);
+            assert_eq!(THIRD_OCTAVE_CENTRES[band], 1000.);
+            assert!(levels[band].abs() < 0.5, "{:?}: {}", window, levels[band]);
+        }
+    }
+
+    #[test]
+    fn third_octave_bands_find_tones() {
+        let mut analyzer = Analyzer::new(Settings::default(), 44100);
+        for &(freq, centre) in &[(100., 100.), (440., 400.), (5000., 5000.)] {
+            let frames = analyzer.feed(&sine(freq, 0.5, 2048));
+            let band = loudest(&frames.last().unwrap().levels);
+            assert_eq!(THIRD_OCTAVE_CENTRES[band], centre);
+        }
+    }
+
+    #[test]
+    fn linear_bins() {
+        let settings = Settings {
+            bins: Some(10),
+            ..Settings::default()
+        };
+        let mut analyzer = Analyzer::new(settings, 44100);
+        assert_eq!(analyzer.bands().len(), 10);
+        // 2205 Hz wide bands, 5000 Hz is in the third one
+        let frames = analyzer.feed(&sine(5000., 0.5, 2048));
+        assert_eq!(loudest(&frames[0].levels), 2);
+        assert_eq!(analyzer.centred_bands()[2].centre, 5512.5);
+    }
+
+    #[test]
+    fn third_octaves_keep_nominal_centres() {
+        // the 20 kHz band is cut at 22.05 kHz
+        let bands = Analyzer::new(Settings::default(), 44100).centred_bands();
+        assert_eq!(bands.len(), 31);
+        assert!((bands[17].centre - 1000.).abs() < 0.01);
+        assert!((bands[30].centre - 20000.).abs() < 0.1);
+        assert_eq!(bands[30].upper, 22050.);
+    }
+
+    #[test]
+    fn a_weighting_attenuates_lows() {
+        assert!((10. * Weighting::A.gain(1000.).log10()).abs() < 0.1);
+        assert!((10. * Weighting::A.gain(100.).log10() + 19.1).abs() < 0.2);
+        assert!((10. * Weighting::C.gain(1000.).log10()).abs() < 0.1);
+        assert_eq!(Weighting::Z.gain(20.), 1.);
+    }
+
+    #[test]
+    fn silence_is_floored() {
+        let mut analyzer = Analyzer::new(Settings::default(), 44100);
+        let frames = analyzer.feed(&vec![0.; 2048]);
+        assert!(frames[0].levels.iter().all(|l| *l == MIN_DB));
+    }
+
+    #[test]
+    fn pipeline_must_match_the_frames() {
+        let settings = Settings::default();
+        let other = Settings {
+            fft_size: settings.fft_size * 2,
+            ..settings
+        };
+        let pipeline = Pipeline::from_settings(&other, 48000);
+        assert!(Analyzer::with_pipeline(settings, 48000, pipeline).is_err());
+        let pipeline = Pipeline::from_settings(&settings, 48000);
+        assert!(Analyzer::with_pipeline(settings, 48000, pipeline).is_ok());
+    }
+
+    #[test]
+    fn labels_band_centres() {
+        assert_eq!(band_label(1000.), "1.0k");
+        assert_eq!(band_label(99.6), "100");
+    }
+
+    #[test]
+    fn validates_settings() {
+        assert!(Settings::default().validate().is_ok());
+        let bad_size = Settings {
+            fft_size: 1000,
+            ..Settings::default()
+        };
+        assert!(bad_size.validate().is_err());
+        let bad_overlap = Settings {
+            overlap: 1.,
+            ..Settings::default()
+        };
+        assert!(bad_overlap.validate().is_err());
+    }
+}
@@ -0,0 +1,130 @@
+//! stereo image analysis: goniometer (M/S rotated Lissajous) and phase correlation
+
+const POINTS: usize = 2048; // how many L/R pairs are kept for drawing
+const CORRELATION_DECAY: f32 = 0.9; // per block, keeps the meter from jumping around
+const SILENCE: f32 = 1e-9;
+
+/// what the front-ends draw: x is the side (R - L), y the mid (L + R) component
+pub struct StereoImage {
+    pub points: Vec<(f32, f32)>,
+    pub correlation: f32,
+}
+
+pub struct Goniometer {
+    points: Vec<(f32, f32)>,
+    sum_lr: f32,
+    sum_ll: f32,
+    sum_rr: f32,
+}
+
+impl Goniometer {
+    pub fn new() -> Goniometer {
+        Goniometer {
+            points: Vec::with_capacity(2 * POINTS),
+            sum_lr: 0.,
+            sum_ll: 0.,
+            sum_rr: 0.,
+        }
+    }
+
+    /// takes interleaved frames, the first two channels are treated as L and R,
+    /// a mono stream is shown as if both channels carried the same signal
+    pub fn feed(&mut self, data: &[f32], channels: usize) {
+        let (mut lr, mut ll, mut rr) = (0., 0., 0.);
+        for frame in data.chunks_exact(channels.max(1)) {
+            let left = frame[0];
+            let right = *frame.get(1).unwrap_or(&left);
+            self.points.push(rotate(left, right));
+            lr += left * right;
+            ll += left * left;
+            rr += right * right;
+        }
+        if self.points.len() > POINTS {
+            let excess = self.points.len() - POINTS;
+            self.points.drain(..excess);
+        }
+
+        self.sum_lr = self.sum_lr * CORRELATION_DECAY + lr;
+        self.sum_ll = self.sum_ll * CORRELATION_DECAY + ll;
+        self.sum_rr = self.sum_rr * CORRELATION_DECAY + rr;
+    }
+
+    /// +1 is mono, 0 unrelated channels and -1 is one channel phase inverted against the other
+    pub fn correlation(&self) -> f32 {
+        let energy = (self.sum_ll * self.sum_rr).sqrt();
+        if energy < SILENCE {
+            return 0.;
+        }
+        (self.sum_lr / energy).clamp(-1., 1.)
+    }
+
+    pub fn snapshot(&self) -> StereoImage {
+        StereoImage {
+            points: self.points.clone(),
+            correlation: self.correlation(),
+        }
+    }
+}
+
+impl Default for Goniometer {
+    fn default() -> Self {
+        Goniometer::new()
+    }
+}
+
+// rotate by 45 degrees so that a mono signal is a vertical line, left-only leans to the left
+fn rotate(left: f32, right: f32) -> (f32, f32) {
+    let side = (right - left) * std::f32::consts::FRAC_1_SQRT_2;
+    let mid = (left + right) * std::f32::consts::FRAC_1_SQRT_2;
+    (side, mid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stereo(left: impl Fn(f32) -> f32, right: impl Fn(f32) -> f32, len: usize) -> Vec<f32> {
+        let mut data = Vec::with_capacity(2 * len);
+        for i in 0..len {
+            let t = i as f32 / 44100.;
+            data.push(left(t));
+            data.push(right(t));
+        }
+        data
+    }
+
+    fn tone(freq: f32) -> impl Fn(f32) -> f32 {
+        move |t| (2. * std::f32::consts::PI * freq * t).sin()
+    }
+
+    #[test]
+    fn mono_is_fully_correlated() {
+        let mut gonio = Goniometer::new();
+        gonio.feed(&stereo(tone(440.), tone(440.), 4096), 2);
+        let image = gonio.snapshot();
+        assert!((image.correlation - 1.).abs() < 1e-3);
+        assert!(image.points.iter().all(|(side, _)| side.abs() < 1e-6));
+    }
+
+    #[test]
+    fn inverted_is_anti_correlated() {
+        let mut gonio = Goniometer::new();
+        let inverted = tone(440.);
+        gonio.feed(&stereo(tone(440.), move |t| -inverted(t), 4096), 2);
+        assert!((gonio.correlation() + 1.).abs() < 1e-3);
+    }
+
+    #[test]
+    fn unrelated_is_uncorrelated() {
+        let mut gonio = Goniometer::new();
+        gonio.feed(&stereo(tone(440.), tone(1013.), 44100), 2);
+        assert!(gonio.correlation().abs() < 0.05);
+    }
+
+    #[test]
+    fn silence_reads_zero() {
+        let mut gonio = Goniometer::new();
+        gonio.feed(&[0.; 512], 2);
+        assert_eq!(gonio.correlation(), 0.);
+    }
+}
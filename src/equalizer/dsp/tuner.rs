@@ -0,0 +1,242 @@
+//! musical tuner: runs the pitch detector over the incoming audio and names the nearest note
+
+use super::pitch;
+use std::str::FromStr;
+
+const FRAME: usize = 4096; // ~93 ms at 44.1 kHz, enough for two periods of a low E
+const HOP: usize = 2048;
+const GATE: f32 = 0.01; // RMS under which the input is considered silent
+
+pub const NOTE_NAMES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Temperament {
+    Equal,
+    Just,
+    Pythagorean,
+    Meantone,
+}
+
+impl Temperament {
+    // deviation of every pitch class from equal temperament in cents, with C as the tonic
+    fn offsets(self) -> [f32; 12] {
+        match self {
+            Temperament::Equal => [0.; 12],
+            // 5-limit ratios 1, 16/15, 9/8, 6/5, 5/4, 4/3, 45/32, 3/2, 8/5, 5/3, 9/5, 15/8
+            Temperament::Just => [
+                0., 11.73, 3.91, 15.64, -13.69, -1.96, -9.78, 1.96, 13.69, -15.64, 17.6, -11.73,
+            ],
+            // stacked pure fifths from Eb to G#
+            Temperament::Pythagorean => [
+                0., 13.69, 3.91, -5.87, 7.82, -1.96, 11.73, 1.96, 15.64, 5.87, -3.91, 9.78,
+            ],
+            // quarter-comma meantone, Eb to G#
+            Temperament::Meantone => [
+                0., -23.95, -6.84, 10.26, -13.69, 3.42, -20.53, -3.42, -27.37, -10.26, 6.84, -17.11,
+            ],
+        }
+    }
+
+    pub fn next(self) -> Temperament {
+        match self {
+            Temperament::Equal => Temperament::Just,
+            Temperament::Just => Temperament::Pythagorean,
+            Temperament::Pythagorean => Temperament::Meantone,
+            Temperament::Meantone => Temperament::Equal,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Temperament::Equal => "equal",
+            Temperament::Just => "just",
+            Temperament::Pythagorean => "pythagorean",
+            Temperament::Meantone => "meantone",
+        }
+    }
+}
+
+impl FromStr for Temperament {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "equal" => Ok(Temperament::Equal),
+            "just" => Ok(Temperament::Just),
+            "pythagorean" => Ok(Temperament::Pythagorean),
+            "meantone" => Ok(Temperament::Meantone),
+            _ => Err(format!(
+                "Unknown temperament {}, expected one of equal, just, pythagorean, meantone",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Reading {
+    pub frequency: f32,
+    pub note: &'static str,
+    pub octave: i32,
+    pub cents: f32, // deviation from the nearest note, -50..50
+}
+
+pub struct Tuner {
+    buffer: Vec<f32>,
+    sample_rate: u32,
+    reference: f32,
+    temperament: Temperament,
+    reading: Option<Reading>,
+}
+
+impl Tuner {
+    pub fn new(sample_rate: u32) -> Tuner {
+        Tuner {
+            buffer: Vec::with_capacity(FRAME + HOP),
+            sample_rate,
+            reference: 440.,
+            temperament: Temperament::Equal,
+            reading: None,
+        }
+    }
+
+    /// collect samples and run the detector once per hop
+    pub fn feed(&mut self, samples: &[f32]) {
+        self.buffer.extend_from_slice(samples);
+        while self.buffer.len() >= FRAME {
+            self.reading = self.detect(&self.buffer[..FRAME]);
+            self.buffer.drain(..HOP);
+        }
+    }
+
+    fn detect(&self, frame: &[f32]) -> Option<Reading> {
+        let rms = (frame.iter().map(|s| s * s).sum::<f32>() / frame.len() as f32).sqrt();
+        if rms < GATE {
+            return None;
+        }
+        pitch::yin(frame, self.sample_rate).map(|freq| self.name(freq))
+    }
+
+    /// finds the nearest note of the current temperament, tuned so that A4 sits at the reference
+    pub fn name(&self, frequency: f32) -> Reading {
+        let offsets = self.temperament.offsets();
+        let cents_from_a4 = 1200. * (frequency / self.reference).log2();
+        let nearest = (cents_from_a4 / 100.).round() as i32 + 69; // MIDI numbering, A4 is 69
+
+        let mut best = (nearest, f32::MAX);
+        for midi in nearest - 1..=nearest + 1 {
+            let class = midi.rem_euclid(12) as usize;
+            let target = (midi - 69) as f32 * 100. + offsets[class] - offsets[9];
+            let deviation = cents_from_a4 - target;
+            if deviation.abs() < best.1.abs() {
+                best = (midi, deviation);
+            }
+        }
+
+        let (midi, cents) = best;
+        Reading {
+            frequency,
+            note: NOTE_NAMES[midi.rem_euclid(12) as usize],
+            octave: midi.div_euclid(12) - 1,
+            cents,
+        }
+    }
+
+    pub fn reading(&self) -> Option<Reading> {
+        self.reading.clone()
+    }
+
+    pub fn reference(&self) -> f32 {
+        self.reference
+    }
+
+    pub fn set_reference(&mut self, reference: f32) {
+        self.reference = reference;
+    }
+
+    pub fn temperament(&self) -> Temperament {
+        self.temperament
+    }
+
+    pub fn set_temperament(&mut self, temperament: Temperament) {
+        self.temperament = temperament;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn harmonic_tone(freq: f32, len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|i| {
+                let t = i as f32 / 44100.;
+                (1..=6)
+                    .map(|h| (2. * std::f32::consts::PI * freq * h as f32 * t).sin() / h as f32)
+                    .sum::<f32>()
+                    * 0.3
+            })
+            .collect()
+    }
+
+    #[test]
+    fn names_tones_in_tune() {
+        let mut tuner = Tuner::new(44100);
+        for &(freq, note, octave) in &[
+            (82.41, "E", 2),
+            (110., "A", 2),
+            (261.63, "C", 4),
+            (440., "A", 4),
+            (987.77, "B", 5),
+        ] {
+            tuner.feed(&harmonic_tone(freq, 2 * FRAME));
+            let reading = tuner.reading().unwrap();
+            assert_eq!(reading.note, note);
+            assert_eq!(reading.octave, octave);
+            assert!(
+                reading.cents.abs() < 2.,
+                "{} is off by {}",
+                note,
+                reading.cents
+            );
+        }
+    }
+
+    #[test]
+    fn reports_cents_deviation() {
+        let mut tuner = Tuner::new(44100);
+        // 445 Hz is 19.56 cents above A4
+        tuner.feed(&harmonic_tone(445., 2 * FRAME));
+        let reading = tuner.reading().unwrap();
+        assert_eq!(reading.note, "A");
+        assert!((reading.cents - 19.56).abs() < 1.);
+    }
+
+    #[test]
+    fn follows_reference() {
+        let mut tuner = Tuner::new(44100);
+        tuner.set_reference(432.);
+        let reading = tuner.name(432.);
+        assert_eq!((reading.note, reading.octave), ("A", 4));
+        assert!(reading.cents.abs() < 1e-3);
+    }
+
+    #[test]
+    fn follows_temperament() {
+        let mut tuner = Tuner::new(44100);
+        tuner.set_temperament(Temperament::Just);
+        // the just E is 1.96 cents above the equal tempered one when A is kept at 440
+        let reading = tuner.name(329.63);
+        assert_eq!(reading.note, "E");
+        assert!((reading.cents + 1.96).abs() < 0.1);
+    }
+
+    #[test]
+    fn quiet_input_has_no_reading() {
+        let mut tuner = Tuner::new(44100);
+        tuner.feed(&[0.001; 2 * FRAME]);
+        assert!(tuner.reading().is_none());
+    }
+}
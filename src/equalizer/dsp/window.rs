@@ -1,18 +1,56 @@
-#[derive(Copy, Clone)]
+use std::str::FromStr;
+
+/// the window applied to every frame before the FFT
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum WindowType {
     Hann,
+    Hamming,
+    Blackman,
+    Rectangular,
+}
+
+impl WindowType {
+    pub fn coefficient(self, idx: usize, size: usize) -> f32 {
+        let phase = 2. * std::f32::consts::PI * idx as f32 / size as f32;
+        match self {
+            WindowType::Hann => 0.5 * (1. - phase.cos()),
+            WindowType::Hamming => 0.54 - 0.46 * phase.cos(),
+            WindowType::Blackman => 0.42 - 0.5 * phase.cos() + 0.08 * (2. * phase).cos(),
+            WindowType::Rectangular => 1.,
+        }
+    }
+
+    pub fn next(self) -> WindowType {
+        match self {
+            WindowType::Hann => WindowType::Hamming,
+            WindowType::Hamming => WindowType::Blackman,
+            WindowType::Blackman => WindowType::Rectangular,
+            WindowType::Rectangular => WindowType::Hann,
+        }
+    }
 }
 
-// per-sample window function
+impl FromStr for WindowType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "hann" => Ok(WindowType::Hann),
+            "hamming" => Ok(WindowType::Hamming),
+            "blackman" => Ok(WindowType::Blackman),
+            "rectangular" | "none" => Ok(WindowType::Rectangular),
+            _ => Err(format!(
+                "Unknown window {}, expected one of hann, hamming, blackman, rectangular",
+                s
+            )),
+        }
+    }
+}
+
+/// per-sample window function
 pub fn choose_window<T>(window: WindowType) -> impl Fn(T, usize, usize) -> T
 where
     T: std::ops::Mul<f32, Output = T>,
 {
-    match window {
-        WindowType::Hann => move |sample, idx, size| {
-            let mult: f32 =
-                0.5 * (1. - ((2. * std::f32::consts::PI * idx as f32 / size as f32) as f32).cos());
-            sample * mult
-        },
-    }
+    move |sample, idx, size| sample * window.coefficient(idx, size)
 }
@@ -0,0 +1,361 @@
+//! synthetic test signals, a stand-in for the capture device in demos and a known input for tests
+
+use std::f64::consts::PI;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+const BLOCK: usize = 512; // frames per callback of the generator stream
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Signal {
+    Sine(f32),
+    MultiTone(Vec<f32>), // equal amplitudes, summed
+    Square(f32),
+    Sawtooth(f32),
+    WhiteNoise,
+    PinkNoise,
+    BrownNoise,
+    LinearSweep { from: f32, to: f32, period: f32 }, // period in seconds, then it starts over
+    LogSweep { from: f32, to: f32, period: f32 },
+    ImpulseTrain(f32), // impulses per second
+}
+
+impl FromStr for Signal {
+    type Err = String;
+
+    // sine:1000, multi:100,1000,5000, square:440, saw:440, white, pink, brown,
+    // sweep:20:20000[:seconds], logsweep:20:20000[:seconds], impulse[:per second]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split(':').collect();
+        let number = |idx: usize, default: Option<f32>| -> Result<f32, String> {
+            match parts.get(idx) {
+                Some(part) => match part.parse::<f32>() {
+                    Ok(value) if value > 0. => Ok(value),
+                    _ => Err(format!("Invalid number {} in signal {}", part, s)),
+                },
+                None => default.ok_or_else(|| format!("Missing a number in signal {}", s)),
+            }
+        };
+        match parts[0].to_lowercase().as_str() {
+            "sine" => Ok(Signal::Sine(number(1, None)?)),
+            "multi" => {
+                let freqs = parts
+                    .get(1)
+                    .ok_or_else(|| format!("Missing the frequencies in signal {}", s))?
+                    .split(',')
+                    .map(|f| match f.parse::<f32>() {
+                        Ok(freq) if freq > 0. => Ok(freq),
+                        _ => Err(format!("Invalid frequency {} in signal {}", f, s)),
+                    })
+                    .collect::<Result<Vec<f32>, String>>()?;
+                Ok(Signal::MultiTone(freqs))
+            }
+            "square" => Ok(Signal::Square(number(1, None)?)),
+            "saw" | "sawtooth" => Ok(Signal::Sawtooth(number(1, None)?)),
+            "white" => Ok(Signal::WhiteNoise),
+            "pink" => Ok(Signal::PinkNoise),
+            "brown" => Ok(Signal::BrownNoise),
+            "sweep" | "logsweep" => {
+                let (from, to, period) = (
+                    number(1, Some(20.))?,
+                    number(2, Some(20000.))?,
+                    number(3, Some(10.))?,
+                );
+                if parts[0] == "sweep" {
+                    Ok(Signal::LinearSweep { from, to, period })
+                } else {
+                    Ok(Signal::LogSweep { from, to, period })
+                }
+            }
+            "impulse" => Ok(Signal::ImpulseTrain(number(1, Some(1.))?)),
+            _ => Err(format!(
+                "Unknown signal {}, expected one of sine, multi, square, saw, white, pink, brown, \
+                 sweep, logsweep, impulse",
+                s
+            )),
+        }
+    }
+}
+
+pub const DEFAULT_LEVEL: f32 = -12.; // dBFS
+
+pub struct Generator {
+    signal: Signal,
+    sample_rate: f64,
+    amplitude: f32,
+    time: u64,      // samples generated so far
+    phase: f64,     // of the sweeps, in cycles
+    seed: u32,      // of the noise
+    pink: [f32; 7], // filter state
+    brown: f32,     // integrator state
+}
+
+impl Generator {
+    /// level is the peak level in dBFS
+    pub fn new(signal: Signal, sample_rate: u32, level: f32) -> Generator {
+        Generator {
+            signal,
+            sample_rate: f64::from(sample_rate),
+            amplitude: 10f32.powf(level / 20.),
+            time: 0,
+            phase: 0.,
+            seed: 22222,
+            pink: [0.; 7],
+            brown: 0.,
+        }
+    }
+
+    /// the same signal on every channel of interleaved frames
+    pub fn fill(&mut self, data: &mut [f32], channels: usize) {
+        for frame in data.chunks_mut(channels.max(1)) {
+            let sample = self.next_sample() * self.amplitude;
+            for s in frame.iter_mut() {
+                *s = sample;
+            }
+        }
+    }
+
+    fn next_sample(&mut self) -> f32 {
+        let n = self.time;
+        let t = n as f64 / self.sample_rate;
+        self.time += 1;
+        let cycle = |freq: f32| (t * f64::from(freq)).fract();
+        match &self.signal {
+            Signal::Sine(freq) => (2. * PI * cycle(*freq)).sin() as f32,
+            Signal::MultiTone(freqs) => {
+                let sum: f64 = freqs.iter().map(|f| (2. * PI * cycle(*f)).sin()).sum();
+                (sum / freqs.len().max(1) as f64) as f32
+            }
+            Signal::Square(freq) => {
+                if cycle(*freq) < 0.5 {
+                    1.
+                } else {
+                    -1.
+                }
+            }
+            Signal::Sawtooth(freq) => (2. * cycle(*freq) - 1.) as f32,
+            Signal::WhiteNoise => white(&mut self.seed),
+            Signal::PinkNoise => {
+                // Paul Kellet's refined filter, about -3 dB per octave
+                let white = white(&mut self.seed);
+                let b = &mut self.pink;
+                b[0] = 0.99886 * b[0] + white * 0.0555179;
+                b[1] = 0.99332 * b[1] + white * 0.0750759;
+                b[2] = 0.96900 * b[2] + white * 0.153852;
+                b[3] = 0.86650 * b[3] + white * 0.3104856;
+                b[4] = 0.55000 * b[4] + white * 0.5329522;
+                b[5] = -0.7616 * b[5] - white * 0.0168980;
+                let pink = b[..6].iter().sum::<f32>() + b[6] + white * 0.5362;
+                b[6] = white * 0.115926;
+                (pink * 0.2).clamp(-1., 1.)
+            }
+            Signal::BrownNoise => {
+                // leaky integration of white noise, -6 dB per octave above a few Hz
+                let white = white(&mut self.seed);
+                self.brown = (self.brown * 0.998 + white * 0.05).clamp(-1., 1.);
+                self.brown
+            }
+            Signal::LinearSweep { from, to, period } => {
+                let progress = (t / f64::from(*period)).fract();
+                let freq = f64::from(*from) + f64::from(to - from) * progress;
+                sweep(&mut self.phase, freq / self.sample_rate)
+            }
+            Signal::LogSweep { from, to, period } => {
+                let progress = (t / f64::from(*period)).fract();
+                let freq = f64::from(*from) * f64::from(to / from).powf(progress);
+                sweep(&mut self.phase, freq / self.sample_rate)
+            }
+            Signal::ImpulseTrain(rate) => {
+                let interval = (self.sample_rate / f64::from(*rate)).round().max(1.) as u64;
+                match n % interval {
+                    0 => 1.,
+                    _ => 0.,
+                }
+            }
+        }
+    }
+}
+
+// sweeps integrate the frequency, given in cycles per sample, there are no jumps in the phase
+fn sweep(phase: &mut f64, step: f64) -> f32 {
+    let sample = (2. * PI * *phase).sin() as f32;
+    *phase = (*phase + step).fract();
+    sample
+}
+
+// xorshift, uniform in -1..1
+fn white(seed: &mut u32) -> f32 {
+    *seed ^= *seed << 13;
+    *seed ^= *seed >> 17;
+    *seed ^= *seed << 5;
+    *seed as f32 / u32::MAX as f32 * 2. - 1.
+}
+
+/// plays a generator in real time on a thread of its own, in blocks like an audio callback
+pub struct GeneratorStream {
+    playing: Arc<AtomicBool>,
+    running: Arc<AtomicBool>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl GeneratorStream {
+    pub fn new<F>(mut generator: Generator, channels: usize, mut callback: F) -> GeneratorStream
+    where
+        F: FnMut(&[f32]) + Send + 'static,
+    {
+        let playing = Arc::new(AtomicBool::new(false));
+        let running = Arc::new(AtomicBool::new(true));
+        let (is_playing, is_running) = (playing.clone(), running.clone());
+        let worker = thread::spawn(move || {
+            let mut block = vec![0.; BLOCK * channels.max(1)];
+            let mut start = Instant::now();
+            let mut produced = 0f64; // seconds since start
+            while is_running.load(Ordering::Relaxed) {
+                if !is_playing.load(Ordering::Relaxed) {
+                    thread::sleep(Duration::from_millis(10));
+                    start = Instant::now();
+                    produced = 0.;
+                    continue;
+                }
+                generator.fill(&mut block, channels);
+                callback(&block);
+                produced += BLOCK as f64 / generator.sample_rate;
+                // keep to the clock of a device, a block ahead at most
+                let due = start + Duration::from_secs_f64(produced);
+                if let Some(wait) = due.checked_duration_since(Instant::now()) {
+                    thread::sleep(wait);
+                }
+            }
+        });
+        GeneratorStream {
+            playing,
+            running,
+            worker: Some(worker),
+        }
+    }
+
+    pub fn play(&self) {
+        self.playing.store(true, Ordering::Relaxed);
+    }
+
+    pub fn pause(&self) {
+        self.playing.store(false, Ordering::Relaxed);
+    }
+}
+
+impl Drop for GeneratorStream {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(worker) = self.worker.take() {
+            if worker.join().is_err() {
+                error!("The generator thread panicked");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::equalizer::dsp::spectrum::{Analyzer, Settings};
+    use std::f32::consts::FRAC_1_SQRT_2;
+
+    fn generate(signal: &str, len: usize) -> Vec<f32> {
+        let mut generator = Generator::new(signal.parse().unwrap(), 48000, 0.);
+        let mut data = vec![0.; len];
+        generator.fill(&mut data, 1);
+        data
+    }
+
+    fn rms(data: &[f32]) -> f32 {
+        (data.iter().map(|s| s * s).sum::<f32>() / data.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn parses_signals() {
+        assert_eq!("sine:1000".parse(), Ok(Signal::Sine(1000.)));
+        assert_eq!(
+            "multi:100,1000".parse(),
+            Ok(Signal::MultiTone(vec![100., 1000.]))
+        );
+        assert_eq!(
+            "logsweep:20:20000".parse(),
+            Ok(Signal::LogSweep {
+                from: 20.,
+                to: 20000.,
+                period: 10.
+            })
+        );
+        assert!("sine".parse::<Signal>().is_err());
+        assert!("sine:-5".parse::<Signal>().is_err());
+        assert!("multi:100,0".parse::<Signal>().is_err());
+        assert!("multi:100,-1000".parse::<Signal>().is_err());
+    }
+
+    #[test]
+    fn levels() {
+        // a full scale sine has an RMS of -3 dB
+        assert!((rms(&generate("sine:1000", 48000)) - FRAC_1_SQRT_2).abs() < 1e-3);
+        assert!((rms(&generate("square:100", 48000)) - 1.).abs() < 1e-3);
+        assert!((rms(&generate("saw:100", 48000)) - 0.5774).abs() < 1e-2);
+        assert!((rms(&generate("white", 48000)) - 0.5774).abs() < 1e-2);
+        let mut quiet = Generator::new(Signal::Square(100.), 48000, -20.);
+        let mut data = vec![0.; 100];
+        quiet.fill(&mut data, 1);
+        assert!((data[0] - 0.1).abs() < 1e-6);
+    }
+
+    // edges of the loudest bands of the last frame, loudest first
+    fn peaks(signal: &str, count: usize) -> Vec<(f32, f32)> {
+        let mut analyzer = Analyzer::new(Settings::default(), 48000);
+        let frame = analyzer.feed(&generate(signal, 48000)).pop().unwrap();
+        let mut bands: Vec<usize> = (0..frame.levels.len()).collect();
+        bands.sort_by(|a, b| frame.levels[*b].total_cmp(&frame.levels[*a]));
+        let edges = analyzer.bands();
+        bands[..count].iter().map(|band| edges[*band]).collect()
+    }
+
+    fn contains(edges: &[(f32, f32)], freq: f32) -> bool {
+        edges
+            .iter()
+            .any(|(lower, upper)| (*lower..*upper).contains(&freq))
+    }
+
+    #[test]
+    fn sine_peaks_in_its_band() {
+        assert!(contains(&peaks("sine:1000", 1), 1000.));
+        assert!(contains(&peaks("sine:250", 1), 250.));
+    }
+
+    #[test]
+    fn multi_tone_peaks_in_every_band() {
+        let loudest = peaks("multi:100,1000,5000", 3);
+        for freq in [100., 1000., 5000.].iter() {
+            assert!(
+                contains(&loudest, *freq),
+                "{} Hz not in {:?}",
+                freq,
+                loudest
+            );
+        }
+    }
+
+    #[test]
+    fn impulses_are_evenly_spaced() {
+        let data = generate("impulse:100", 48000);
+        let impulses: Vec<usize> = (0..data.len()).filter(|i| data[*i] == 1.).collect();
+        assert_eq!(impulses.len(), 100);
+        assert_eq!(impulses[1] - impulses[0], 480);
+    }
+
+    #[test]
+    fn channels_get_the_same_signal() {
+        let mut generator = Generator::new(Signal::WhiteNoise, 48000, 0.);
+        let mut data = vec![0.; 64];
+        generator.fill(&mut data, 2);
+        assert!(data.chunks(2).all(|frame| frame[0] == frame[1]));
+    }
+}
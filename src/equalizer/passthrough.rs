@@ -0,0 +1,93 @@
+//! plays the (equalized) captured input on an output device
+
+use crate::errors::{Error, StreamOp};
+use crate::ring_buffer::{Consumer, Producer, RingBuffer};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::Stream;
+
+// ~185 ms of stereo at 44.1 kHz between the input and the output callback
+const BUFFER_LEN: usize = 16384;
+
+pub struct PassThrough {
+    device: cpal::Device,
+    buffer: Option<Consumer<f32>>, // handed to the output callback on connect
+    stream: Option<Stream>,
+}
+
+impl PassThrough {
+    /// "default" picks the default output device of the host
+    pub fn new(host: &cpal::Host, device_name: &str) -> Result<PassThrough, Error> {
+        let device = if device_name == "default" {
+            host.default_output_device()
+        } else {
+            host.output_devices()
+                .map_err(|_| Error::NoOutputDevice)?
+                .find(|dev| matches!(dev.name(), Ok(name) if name == device_name))
+        };
+
+        match device {
+            Some(device) => {
+                info!("output device {}", device.name().unwrap_or_default());
+                Ok(PassThrough {
+                    device,
+                    buffer: None,
+                    stream: None,
+                })
+            }
+            None => Err(Error::NoOutputDevice),
+        }
+    }
+
+    /// the input callback pushes the processed samples here, every call starts a new buffer
+    /// that the next connect plays from
+    pub fn buffer(&mut self) -> Producer<f32> {
+        let (producer, consumer) = RingBuffer::spsc(BUFFER_LEN);
+        self.buffer = Some(consumer);
+        producer
+    }
+
+    pub fn connect(&mut self, config: &cpal::StreamConfig) -> Result<(), Error> {
+        let err_fn = move |err| {
+            error!("An error ocurred on output stream: {}", err);
+        };
+        // without an input there is nothing but silence to play
+        let mut buffer = match self.buffer.take() {
+            Some(buffer) => buffer,
+            None => RingBuffer::spsc(BUFFER_LEN).1,
+        };
+        let stream = self.device.build_output_stream(
+            config,
+            move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
+                // play silence on underrun rather than waiting for the input
+                let count = buffer.pop_slice(data);
+                for sample in data[count..].iter_mut() {
+                    *sample = 0.;
+                }
+            },
+            err_fn,
+        )?;
+
+        self.stream = Some(stream);
+        Ok(())
+    }
+
+    pub fn play(&self) -> Result<(), Error> {
+        match &self.stream {
+            Some(stream) => {
+                stream.play()?;
+                Ok(())
+            }
+            None => Err(Error::StreamOperation(StreamOp::Play)),
+        }
+    }
+
+    pub fn pause(&self) -> Result<(), Error> {
+        match &self.stream {
+            Some(stream) => {
+                stream.pause()?;
+                Ok(())
+            }
+            None => Err(Error::StreamOperation(StreamOp::Pause)),
+        }
+    }
+}
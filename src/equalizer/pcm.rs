@@ -0,0 +1,426 @@
+//! raw interleaved PCM from stdin or a named pipe, for audio piped out of sox, ffmpeg or parec
+
+use crate::errors::Error;
+use std::fs::File;
+use std::io::{self, ErrorKind, Read};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+const BLOCK: usize = 512; // frames per callback, like the generator stream
+pub const POLL: Duration = Duration::from_millis(100); // how soon the stream notices it is dropped
+
+/// all little endian
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SampleFormat {
+    S16,
+    S24, // packed, three bytes per sample
+    F32,
+}
+
+impl SampleFormat {
+    /// bytes per sample
+    pub fn width(self) -> usize {
+        match self {
+            SampleFormat::S16 => 2,
+            SampleFormat::S24 => 3,
+            SampleFormat::F32 => 4,
+        }
+    }
+
+    fn decode(self, bytes: &[u8]) -> f32 {
+        match self {
+            SampleFormat::S16 => i16::from_le_bytes([bytes[0], bytes[1]]) as f32 / 32768.,
+            // shifted up into an i32 to get the sign right
+            SampleFormat::S24 => {
+                (i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) >> 8) as f32 / 8_388_608.
+            }
+            SampleFormat::F32 => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        }
+    }
+}
+
+impl FromStr for SampleFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "s16le" => Ok(SampleFormat::S16),
+            "s24le" => Ok(SampleFormat::S24),
+            "f32le" => Ok(SampleFormat::F32),
+            _ => Err(format!(
+                "Unknown sample format {}, expected s16le, s24le or f32le",
+                s
+            )),
+        }
+    }
+}
+
+/// stdin without a path; a named pipe is opened without waiting for its writer, read it with
+/// `PcmReader::poll`, which waits for the writer, a plain read can end before it comes
+pub fn open(path: Option<&Path>) -> Result<File, Error> {
+    match path {
+        Some(path) => open_path(path),
+        None => stdin(),
+    }
+}
+
+// a blocking open of a pipe cannot be interrupted, the signal handler restarts it
+#[cfg(unix)]
+fn open_path(path: &Path) -> Result<File, Error> {
+    use std::fs::OpenOptions;
+    use std::os::unix::fs::OpenOptionsExt;
+    Ok(OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(path)?)
+}
+
+#[cfg(not(unix))]
+fn open_path(path: &Path) -> Result<File, Error> {
+    Ok(File::open(path)?)
+}
+
+// a handle of its own on stdin, unbuffered so that waiting on it sees every byte
+#[cfg(unix)]
+fn stdin() -> Result<File, Error> {
+    use std::os::unix::io::AsFd;
+    Ok(File::from(io::stdin().as_fd().try_clone_to_owned()?))
+}
+
+#[cfg(windows)]
+fn stdin() -> Result<File, Error> {
+    use std::os::windows::io::AsHandle;
+    Ok(File::from(io::stdin().as_handle().try_clone_to_owned()?))
+}
+
+/// an input that can be waited on, so that a reader thread is never stuck in a read
+pub trait Ready {
+    /// false when nothing arrived within the timeout; the end of the input counts as readable
+    fn ready(&self, timeout: Duration) -> io::Result<bool>;
+}
+
+impl Ready for File {
+    #[cfg(unix)]
+    fn ready(&self, timeout: Duration) -> io::Result<bool> {
+        use std::os::unix::io::AsRawFd;
+        let mut fd = libc::pollfd {
+            fd: self.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        match unsafe { libc::poll(&mut fd, 1, timeout.as_millis() as _) } {
+            -1 => match io::Error::last_os_error() {
+                err if err.kind() == ErrorKind::Interrupted => Ok(false),
+                err => Err(err),
+            },
+            0 => Ok(false),
+            _ => Ok(true),
+        }
+    }
+
+    // no waiting there, the read blocks as it always did
+    #[cfg(not(unix))]
+    fn ready(&self, _timeout: Duration) -> io::Result<bool> {
+        Ok(true)
+    }
+}
+
+/// decodes whole frames, however the writer splits its writes
+pub struct PcmReader<R: Read> {
+    input: R,
+    format: SampleFormat,
+    frame: usize, // bytes per frame
+    buffer: Vec<u8>,
+    filled: usize,
+}
+
+impl<R: Read> PcmReader<R> {
+    pub fn new(input: R, format: SampleFormat, channels: usize) -> PcmReader<R> {
+        let frame = format.width() * channels.max(1);
+        PcmReader {
+            input,
+            format,
+            frame,
+            buffer: vec![0; BLOCK * frame],
+            filled: 0,
+        }
+    }
+
+    /// the next block of interleaved samples, at most BLOCK frames, None at the end of the
+    /// stream; a frame cut short by the end is dropped
+    pub fn read(&mut self) -> Result<Option<Vec<f32>>, Error> {
+        self.next(|_| Ok(true))
+    }
+
+    /// like read, but an empty block when nothing can be read within the timeout
+    pub fn poll(&mut self, timeout: Duration) -> Result<Option<Vec<f32>>, Error>
+    where
+        R: Ready,
+    {
+        self.next(|input| input.ready(timeout))
+    }
+
+    fn next<W>(&mut self, mut wait: W) -> Result<Option<Vec<f32>>, Error>
+    where
+        W: FnMut(&R) -> io::Result<bool>,
+    {
+        loop {
+            if !wait(&self.input)? {
+                return Ok(Some(Vec::new()));
+            }
+            match self.input.read(&mut self.buffer[self.filled..]) {
+                Ok(0) => {
+                    if self.filled > 0 {
+                        warn!("Dropped {} bytes of a partial frame", self.filled);
+                        self.filled = 0;
+                    }
+                    return Ok(None);
+                }
+                Ok(read) => self.filled += read,
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                // a pipe opened without blocking
+                Err(err) if err.kind() == ErrorKind::WouldBlock => return Ok(Some(Vec::new())),
+                Err(err) => return Err(err.into()),
+            }
+            let whole = self.filled - self.filled % self.frame;
+            if whole == 0 {
+                continue;
+            }
+            let format = self.format;
+            let samples = self.buffer[..whole]
+                .chunks_exact(format.width())
+                .map(|bytes| format.decode(bytes))
+                .collect();
+            // keep the start of a frame for the next read
+            self.buffer.copy_within(whole..self.filled, 0);
+            self.filled -= whole;
+            return Ok(Some(samples));
+        }
+    }
+}
+
+/// feeds the blocks read from a pipe to the callback on a thread of its own, paced by the writer
+pub struct PcmStream {
+    playing: Arc<AtomicBool>,
+    running: Arc<AtomicBool>,
+    ended: Arc<AtomicBool>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl PcmStream {
+    pub fn new<R, F>(mut reader: PcmReader<R>, mut callback: F) -> PcmStream
+    where
+        R: Read + Ready + Send + 'static,
+        F: FnMut(&[f32]) + Send + 'static,
+    {
+        let playing = Arc::new(AtomicBool::new(false));
+        let running = Arc::new(AtomicBool::new(true));
+        let ended = Arc::new(AtomicBool::new(false));
+        let (is_playing, is_running, has_ended) = (playing.clone(), running.clone(), ended.clone());
+        let worker = thread::spawn(move || {
+            while is_running.load(Ordering::Relaxed) {
+                // paused, the writer blocks once the pipe is full
+                if !is_playing.load(Ordering::Relaxed) {
+                    thread::sleep(Duration::from_millis(10));
+                    continue;
+                }
+                // bounded, so that a silent writer does not keep the thread from stopping
+                match reader.poll(POLL) {
+                    Ok(Some(samples)) if samples.is_empty() => continue,
+                    Ok(Some(samples)) => callback(&samples),
+                    Ok(None) => {
+                        info!("End of the PCM stream");
+                        break;
+                    }
+                    Err(err) => {
+                        error!("Could not read the PCM stream: {}", err);
+                        break;
+                    }
+                }
+            }
+            has_ended.store(true, Ordering::Relaxed);
+        });
+        PcmStream {
+            playing,
+            running,
+            ended,
+            worker: Some(worker),
+        }
+    }
+
+    pub fn play(&self) {
+        self.playing.store(true, Ordering::Relaxed);
+    }
+
+    pub fn pause(&self) {
+        self.playing.store(false, Ordering::Relaxed);
+    }
+
+    /// the writer closed the pipe, or reading it failed
+    pub fn ended(&self) -> bool {
+        self.ended.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for PcmStream {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(worker) = self.worker.take() {
+            if worker.join().is_err() {
+                error!("The PCM thread panicked");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // hands out a few bytes at a time, like a pipe
+    struct Trickle<'a> {
+        data: &'a [u8],
+        step: usize,
+    }
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.step.min(buf.len()).min(self.data.len());
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            Ok(n)
+        }
+    }
+
+    impl Ready for io::Cursor<Vec<u8>> {
+        fn ready(&self, _timeout: Duration) -> io::Result<bool> {
+            Ok(true)
+        }
+    }
+
+    fn read_all<R: Read>(mut reader: PcmReader<R>) -> Vec<f32> {
+        let mut samples = Vec::new();
+        while let Some(block) = reader.read().unwrap() {
+            samples.extend(block);
+        }
+        samples
+    }
+
+    #[test]
+    fn decodes_formats() {
+        let s16: Vec<u8> = [0i16, 16384, -32768]
+            .iter()
+            .flat_map(|s| s.to_le_bytes().to_vec())
+            .collect();
+        let reader = PcmReader::new(s16.as_slice(), SampleFormat::S16, 1);
+        assert_eq!(read_all(reader), vec![0., 0.5, -1.]);
+
+        let s24 = [0x00, 0x00, 0x40, 0x00, 0x00, 0x80, 0xff, 0xff, 0xff];
+        let reader = PcmReader::new(&s24[..], SampleFormat::S24, 1);
+        assert_eq!(read_all(reader), vec![0.5, -1., -1. / 8_388_608.]);
+
+        let f32: Vec<u8> = [0.25f32, -0.75]
+            .iter()
+            .flat_map(|s| s.to_le_bytes().to_vec())
+            .collect();
+        let reader = PcmReader::new(f32.as_slice(), SampleFormat::F32, 2);
+        assert_eq!(read_all(reader), vec![0.25, -0.75]);
+
+        assert_eq!("F32LE".parse(), Ok(SampleFormat::F32));
+        assert!("u8".parse::<SampleFormat>().is_err());
+    }
+
+    #[test]
+    fn keeps_frames_whole() {
+        // stereo s16, 1000 frames trickling in 3 bytes at a time, then half a frame
+        let mut data: Vec<u8> = (0..2000i16)
+            .flat_map(|s| s.to_le_bytes().to_vec())
+            .collect();
+        data.extend(&[1, 2]);
+        let mut reader = PcmReader::new(
+            Trickle {
+                data: &data,
+                step: 3,
+            },
+            SampleFormat::S16,
+            2,
+        );
+        let mut samples = Vec::new();
+        while let Some(block) = reader.read().unwrap() {
+            assert!(!block.is_empty());
+            assert_eq!(block.len() % 2, 0);
+            samples.extend(block);
+        }
+        assert_eq!(samples.len(), 2000);
+        assert_eq!(samples[1999], 1999. / 32768.);
+        // nothing more after the end
+        assert!(reader.read().unwrap().is_none());
+    }
+
+    #[test]
+    fn stream_ends_with_the_input() {
+        let data: Vec<u8> = vec![0; 4 * 3000];
+        let reader = PcmReader::new(std::io::Cursor::new(data), SampleFormat::F32, 1);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let stream = PcmStream::new(reader, move |block: &[f32]| {
+            sender.send(block.len()).unwrap();
+        });
+        stream.play();
+        let mut total = 0;
+        while let Ok(len) = receiver.recv_timeout(Duration::from_secs(5)) {
+            total += len;
+            if total == 3000 {
+                break;
+            }
+        }
+        assert_eq!(total, 3000);
+        for _ in 0..500 {
+            if stream.ended() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(stream.ended());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn drop_stops_a_stream_waiting_for_its_writer() {
+        use std::os::unix::io::FromRawFd;
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        let (output, input) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+        let stream = PcmStream::new(PcmReader::new(output, SampleFormat::F32, 1), |_: &[f32]| {});
+        stream.play();
+        thread::sleep(Duration::from_millis(50));
+        // the writer is still open and silent, the drop joins the reader all the same
+        drop(stream);
+        drop(input);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn opening_a_pipe_does_not_wait_for_its_writer() {
+        use std::io::Write;
+        let fifo = std::env::temp_dir().join(format!("rustalizer-open-{}", std::process::id()));
+        let path = std::ffi::CString::new(fifo.to_str().unwrap()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(path.as_ptr(), 0o600) }, 0);
+
+        let mut reader = PcmReader::new(open(Some(&fifo)).unwrap(), SampleFormat::F32, 1);
+        // no writer yet, which is not the end of the stream
+        assert_eq!(
+            reader.poll(Duration::from_millis(10)).unwrap(),
+            Some(Vec::new())
+        );
+        let mut writer = std::fs::OpenOptions::new().write(true).open(&fifo).unwrap();
+        writer.write_all(&0.5f32.to_le_bytes()).unwrap();
+        assert_eq!(reader.poll(POLL).unwrap(), Some(vec![0.5]));
+        drop(writer);
+        assert_eq!(reader.poll(POLL).unwrap(), None);
+        std::fs::remove_file(&fifo).unwrap();
+    }
+}
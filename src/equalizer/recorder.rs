@@ -0,0 +1,358 @@
+//! records the captured input to WAV files next to the visualization: the input callback pushes
+//! into a bounded buffer without waiting, a background thread empties it into the current file
+
+use crate::errors::Error;
+use crate::ring_buffer::{Consumer, Producer, RingBuffer};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// ~1.5 s of stereo at 44.1 kHz, plenty for a writer waking up every POLL
+const BUFFER_LEN: usize = 131_072;
+const POLL: Duration = Duration::from_millis(20);
+
+/// when to start a new file, whichever comes first
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Rotation {
+    pub max_bytes: Option<u64>, // of samples, the header adds a few dozen bytes
+    pub max_duration: Option<Duration>,
+}
+
+#[derive(Clone, Debug)]
+pub struct RecordingStatus {
+    pub file: Option<PathBuf>, // being written to, None when not recording
+    pub dropped: usize,        // samples lost because the writer fell behind
+}
+
+/// the part of the recorder that lives in the input callback
+pub struct Tap {
+    buffer: Producer<f32>,
+    recording: Arc<AtomicBool>,
+    dropped: Arc<AtomicUsize>,
+}
+
+impl Tap {
+    /// never blocks, what does not fit is counted and dropped
+    pub fn write(&mut self, data: &[f32]) {
+        if !self.recording.load(Ordering::Relaxed) {
+            return;
+        }
+        let written = self.buffer.push_slice(data);
+        if written < data.len() {
+            self.dropped
+                .fetch_add(data.len() - written, Ordering::Relaxed);
+        }
+    }
+}
+
+pub struct Recorder {
+    // the writer's end of the current tap, only locked by the writer and by tap
+    buffer: Arc<Mutex<Consumer<f32>>>,
+    recording: Arc<AtomicBool>,
+    dropped: Arc<AtomicUsize>,
+    file: Arc<Mutex<Option<PathBuf>>>,
+    running: Arc<AtomicBool>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl Recorder {
+    pub fn new(directory: &Path, channels: u16, sample_rate: u32, rotation: Rotation) -> Recorder {
+        let buffer = Arc::new(Mutex::new(RingBuffer::spsc(BUFFER_LEN).1));
+        let recording = Arc::new(AtomicBool::new(false));
+        let file = Arc::new(Mutex::new(None));
+        let running = Arc::new(AtomicBool::new(true));
+
+        let mut writer = Writer::new(directory, channels, sample_rate, rotation);
+        let (samples_in, recording_in) = (buffer.clone(), recording.clone());
+        let (current, alive) = (file.clone(), running.clone());
+        let worker = thread::spawn(move || {
+            let mut samples = vec![0.; BUFFER_LEN];
+            let (buffer, recording) = (samples_in, recording_in);
+            loop {
+                // one more round after the stop, for what came in before it
+                let last = !alive.load(Ordering::Relaxed);
+                if !last {
+                    thread::sleep(POLL);
+                }
+                let count = match buffer.lock() {
+                    Ok(mut buffer) => buffer.pop_slice(&mut samples),
+                    Err(_) => 0,
+                };
+                let samples = &samples[..count];
+                let result = if recording.load(Ordering::Relaxed) {
+                    writer.write(samples)
+                } else {
+                    // whatever came in before the toggle still belongs to the file
+                    writer.write(samples).and_then(|_| writer.close())
+                };
+                if let Err(err) = result {
+                    error!("Recording stopped: {}", err);
+                    recording.store(false, Ordering::Relaxed);
+                    writer.abandon();
+                }
+                if let Ok(mut current) = current.lock() {
+                    *current = writer.file().map(Path::to_path_buf);
+                }
+                if last {
+                    break;
+                }
+            }
+            if let Err(err) = writer.close() {
+                error!("Could not finish the recording: {}", err);
+            }
+        });
+
+        Recorder {
+            buffer,
+            recording,
+            dropped: Arc::new(AtomicUsize::new(0)),
+            file,
+            running,
+            worker: Some(worker),
+        }
+    }
+
+    /// what the input callback writes through, a new tap replaces the previous one and drops
+    /// what it left unwritten
+    pub fn tap(&self) -> Tap {
+        let (producer, consumer) = RingBuffer::spsc(BUFFER_LEN);
+        if let Ok(mut buffer) = self.buffer.lock() {
+            *buffer = consumer;
+        }
+        Tap {
+            buffer: producer,
+            recording: self.recording.clone(),
+            dropped: self.dropped.clone(),
+        }
+    }
+
+    pub fn recording(&self) -> bool {
+        self.recording.load(Ordering::Relaxed)
+    }
+
+    pub fn set_recording(&self, recording: bool) {
+        self.recording.store(recording, Ordering::Relaxed);
+    }
+
+    pub fn status(&self) -> RecordingStatus {
+        RecordingStatus {
+            file: self.file.lock().ok().and_then(|file| file.clone()),
+            dropped: self.dropped.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Drop for Recorder {
+    // finishes the current file, its header is only complete after that
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(worker) = self.worker.take() {
+            if worker.join().is_err() {
+                error!("The recorder thread panicked");
+            }
+        }
+    }
+}
+
+struct Output {
+    writer: hound::WavWriter<BufWriter<File>>,
+    path: PathBuf,
+    frames: u64,
+}
+
+// writes 32 bit float files and starts a new one when the rotation says so
+struct Writer {
+    directory: PathBuf,
+    spec: hound::WavSpec,
+    rotation: Rotation,
+    session: u64, // start of the program in seconds since the epoch, keeps the names unique
+    index: usize,
+    output: Option<Output>,
+    pending: usize, // samples of an incomplete frame written so far
+}
+
+impl Writer {
+    fn new(directory: &Path, channels: u16, sample_rate: u32, rotation: Rotation) -> Writer {
+        Writer {
+            directory: directory.to_path_buf(),
+            spec: hound::WavSpec {
+                channels,
+                sample_rate,
+                bits_per_sample: 32,
+                sample_format: hound::SampleFormat::Float,
+            },
+            rotation,
+            session: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_secs()),
+            index: 0,
+            output: None,
+            pending: 0,
+        }
+    }
+
+    fn file(&self) -> Option<&Path> {
+        self.output.as_ref().map(|output| output.path.as_path())
+    }
+
+    fn write(&mut self, samples: &[f32]) -> Result<(), Error> {
+        for sample in samples {
+            // files are only split between frames
+            if self.pending == 0 && self.output.as_ref().map(|o| self.full(o)).unwrap_or(true) {
+                self.close()?;
+                self.open()?;
+            }
+            if let Some(output) = self.output.as_mut() {
+                output.writer.write_sample(*sample)?;
+                self.pending += 1;
+                if self.pending == self.spec.channels as usize {
+                    self.pending = 0;
+                    output.frames += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn full(&self, output: &Output) -> bool {
+        let frame_bytes = 4 * u64::from(self.spec.channels);
+        let by_size = matches!(self.rotation.max_bytes,
+            Some(max) if (output.frames + 1) * frame_bytes > max);
+        let by_duration = matches!(self.rotation.max_duration,
+            Some(max) if output.frames as f64 >= max.as_secs_f64() * f64::from(self.spec.sample_rate));
+        by_size || by_duration
+    }
+
+    fn open(&mut self) -> Result<(), Error> {
+        let path = self
+            .directory
+            .join(format!("rustalizer-{}-{:03}.wav", self.session, self.index));
+        self.index += 1;
+        info!("recording to {}", path.display());
+        self.output = Some(Output {
+            writer: hound::WavWriter::create(&path, self.spec)?,
+            path,
+            frames: 0,
+        });
+        self.pending = 0;
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), Error> {
+        if let Some(output) = self.output.take() {
+            output.writer.finalize()?;
+        }
+        self.pending = 0;
+        Ok(())
+    }
+
+    // after an error the file is left as it is
+    fn abandon(&mut self) {
+        self.output = None;
+        self.pending = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!(
+            "rustalizer-recorder-{}-{}",
+            name,
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    fn files(directory: &Path) -> Vec<(PathBuf, u32)> {
+        let mut files: Vec<PathBuf> = std::fs::read_dir(directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        files.sort();
+        files
+            .into_iter()
+            .map(|path| {
+                let frames = hound::WavReader::open(&path).unwrap().duration();
+                (path, frames)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn rotates_by_duration() {
+        let directory = directory("duration");
+        let rotation = Rotation {
+            max_duration: Some(Duration::from_secs(1)),
+            ..Rotation::default()
+        };
+        let mut writer = Writer::new(&directory, 2, 1000, rotation);
+        // uneven chunks, frames must not be split between files
+        for chunk in vec![0.25; 5000].chunks(333) {
+            writer.write(chunk).unwrap();
+        }
+        writer.close().unwrap();
+        let frames: Vec<u32> = files(&directory).iter().map(|(_, f)| *f).collect();
+        assert_eq!(frames, vec![1000, 1000, 500]);
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn rotates_by_size() {
+        let directory = directory("size");
+        let rotation = Rotation {
+            max_bytes: Some(400),
+            ..Rotation::default()
+        };
+        let mut writer = Writer::new(&directory, 1, 1000, rotation);
+        writer.write(&[0.5; 250]).unwrap();
+        writer.close().unwrap();
+        let files = files(&directory);
+        assert_eq!(files.len(), 3);
+        assert_eq!(files[0].1, 100);
+        assert_eq!(files[2].1, 50);
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn records_only_while_enabled() {
+        let directory = directory("toggle");
+        let recorder = Recorder::new(&directory, 1, 1000, Rotation::default());
+        let mut tap = recorder.tap();
+        tap.write(&[1.; 100]);
+        recorder.set_recording(true);
+        tap.write(&[0.5; 300]);
+        thread::sleep(POLL * 5);
+        assert!(recorder.status().file.is_some());
+        recorder.set_recording(false);
+        tap.write(&[1.; 100]);
+        drop(recorder);
+
+        let files = files(&directory);
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].1, 300);
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn writes_the_rest_when_dropped() {
+        let directory = directory("drain");
+        let recorder = Recorder::new(&directory, 1, 1000, Rotation::default());
+        let mut tap = recorder.tap();
+        recorder.set_recording(true);
+        tap.write(&[0.5; 700]);
+        drop(recorder);
+
+        let files = files(&directory);
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].1, 700);
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,578 @@
+//! network audio over RTP (RFC 3550) with the uncompressed L16 and L24 payloads of RFC 3551,
+//! big endian and interleaved; the clock rate and the channels are not negotiated, both ends
+//! are told on the command line
+
+use crate::errors::Error;
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const HEADER: usize = 12; // bytes without CSRCs or extensions
+const MAX_PAYLOAD: usize = 1200; // bytes, stays below the usual MTU
+const DYNAMIC_TYPE: u8 = 96;
+pub const DEFAULT_PORT: u16 = 5004;
+pub const DEFAULT_JITTER: u32 = 40; // ms
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Payload {
+    L16,
+    L24,
+}
+
+impl Payload {
+    /// bytes per sample
+    pub fn width(self) -> usize {
+        match self {
+            Payload::L16 => 2,
+            Payload::L24 => 3,
+        }
+    }
+
+    fn decode(self, bytes: &[u8]) -> f32 {
+        match self {
+            Payload::L16 => i16::from_be_bytes([bytes[0], bytes[1]]) as f32 / 32768.,
+            Payload::L24 => {
+                (i32::from_be_bytes([bytes[0], bytes[1], bytes[2], 0]) >> 8) as f32 / 8_388_608.
+            }
+        }
+    }
+
+    fn encode(self, sample: f32, out: &mut Vec<u8>) {
+        let sample = sample.clamp(-1., 1.);
+        match self {
+            Payload::L16 => out.extend(&((sample * 32767.) as i16).to_be_bytes()),
+            Payload::L24 => out.extend(&((sample * 8_388_607.) as i32).to_be_bytes()[1..]),
+        }
+    }
+
+    // the static types of RFC 3551 only cover L16 at 44.1 kHz
+    fn payload_type(self, sample_rate: u32, channels: usize) -> u8 {
+        match (self, sample_rate, channels) {
+            (Payload::L16, 44100, 2) => 10,
+            (Payload::L16, 44100, 1) => 11,
+            _ => DYNAMIC_TYPE,
+        }
+    }
+}
+
+impl FromStr for Payload {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "l16" => Ok(Payload::L16),
+            "l24" => Ok(Payload::L24),
+            _ => Err(format!("Unknown RTP payload {}, expected l16 or l24", s)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Packet {
+    pub sequence: u16,
+    pub timestamp: u32, // in frames
+    pub ssrc: u32,
+    pub samples: Vec<f32>,
+}
+
+impl Packet {
+    pub fn parse(bytes: &[u8], payload: Payload) -> Result<Packet, String> {
+        if bytes.len() < HEADER {
+            return Err(format!("A packet of {} bytes is too short", bytes.len()));
+        }
+        if bytes[0] >> 6 != 2 {
+            return Err(format!("Unknown RTP version {}", bytes[0] >> 6));
+        }
+        let padding = bytes[0] & 0x20 != 0;
+        let extension = bytes[0] & 0x10 != 0;
+        let csrcs = (bytes[0] & 0x0f) as usize;
+        let sequence = u16::from_be_bytes([bytes[2], bytes[3]]);
+        let timestamp = u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+        let ssrc = u32::from_be_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]);
+
+        let mut start = HEADER + 4 * csrcs;
+        if extension {
+            let words = bytes
+                .get(start + 2..start + 4)
+                .map(|len| u16::from_be_bytes([len[0], len[1]]) as usize)
+                .ok_or_else(|| String::from("Truncated header extension"))?;
+            start += 4 + 4 * words;
+        }
+        let mut end = bytes.len();
+        if padding {
+            end = end.saturating_sub(bytes[end - 1] as usize);
+        }
+        if start > end {
+            return Err(String::from("Truncated packet"));
+        }
+        let samples = bytes[start..end]
+            .chunks_exact(payload.width())
+            .map(|sample| payload.decode(sample))
+            .collect();
+        Ok(Packet {
+            sequence,
+            timestamp,
+            ssrc,
+            samples,
+        })
+    }
+}
+
+/// packet counts, for the status line
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RtpStats {
+    pub received: u64,
+    pub lost: u64,    // never arrived in time, replaced by silence
+    pub late: u64,    // arrived after their place was played, or twice
+    pub invalid: u64, // could not be parsed, or ended in part of a frame
+}
+
+/// puts packets back in order; a missing packet is waited for until depth frames arrived after
+/// it, then counted lost and played as silence
+pub struct JitterBuffer {
+    channels: usize,
+    depth: usize,                  // frames
+    packets: HashMap<u16, Packet>, // by sequence number
+    buffered: usize,               // frames
+    next: Option<u16>,             // sequence number
+    timestamp: u32,                // expected of the next packet
+    stats: RtpStats,
+}
+
+impl JitterBuffer {
+    pub fn new(channels: usize, depth: usize) -> JitterBuffer {
+        JitterBuffer {
+            channels: channels.max(1),
+            depth,
+            packets: HashMap::new(),
+            buffered: 0,
+            next: None,
+            timestamp: 0,
+            stats: RtpStats::default(),
+        }
+    }
+
+    pub fn push(&mut self, mut packet: Packet) {
+        self.stats.received += 1;
+        let next = match self.next {
+            Some(next) => next,
+            None => {
+                self.timestamp = packet.timestamp;
+                *self.next.insert(packet.sequence)
+            }
+        };
+        // sequence numbers wrap, anything up to half the range behind is old
+        let behind = (packet.sequence.wrapping_sub(next) as i16) < 0;
+        if behind || self.packets.contains_key(&packet.sequence) {
+            self.stats.late += 1;
+            return;
+        }
+        // a part of a frame would put every later sample on the wrong channel
+        let partial = packet.samples.len() % self.channels;
+        if partial > 0 {
+            self.stats.invalid += 1;
+            packet.samples.truncate(packet.samples.len() - partial);
+        }
+        self.buffered += packet.samples.len() / self.channels;
+        self.packets.insert(packet.sequence, packet);
+    }
+
+    /// the next block of samples in order, if there is one to play yet
+    pub fn pop(&mut self) -> Option<Vec<f32>> {
+        self.take(false)
+    }
+
+    /// like pop, but gives up on a missing packet right away; for when the sender went quiet
+    /// and nothing more will push the buffer past its depth
+    pub fn flush(&mut self) -> Option<Vec<f32>> {
+        self.take(true)
+    }
+
+    fn take(&mut self, flush: bool) -> Option<Vec<f32>> {
+        let next = self.next?;
+        if let Some(packet) = self.packets.remove(&next) {
+            let frames = packet.samples.len() / self.channels;
+            self.buffered -= frames;
+            self.next = Some(next.wrapping_add(1));
+            self.timestamp = packet.timestamp.wrapping_add(frames as u32);
+            return Some(packet.samples);
+        }
+        if self.buffered <= self.depth && !flush {
+            return None;
+        }
+        // waited long enough, skip to the oldest packet there is
+        let (&sequence, packet) = self
+            .packets
+            .iter()
+            .min_by_key(|(sequence, _)| sequence.wrapping_sub(next))?;
+        self.stats.lost += u64::from(sequence.wrapping_sub(next));
+        // the timestamps tell how much is missing, a restarted sender could make that anything
+        let missing = (packet.timestamp.wrapping_sub(self.timestamp) as usize).min(self.depth);
+        self.next = Some(sequence);
+        self.timestamp = packet.timestamp;
+        Some(vec![0.; missing * self.channels])
+    }
+
+    pub fn stats(&self) -> RtpStats {
+        self.stats
+    }
+
+    fn invalid(&mut self) {
+        self.stats.invalid += 1;
+    }
+
+    // a new sender, the sequence numbers start over
+    fn reset(&mut self) {
+        self.packets.clear();
+        self.buffered = 0;
+        self.next = None;
+    }
+}
+
+/// receives packets on a thread of its own and feeds the ordered samples to the callback
+pub struct RtpStream {
+    playing: Arc<AtomicBool>,
+    running: Arc<AtomicBool>,
+    stats: Arc<Mutex<RtpStats>>,
+    address: SocketAddr,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl RtpStream {
+    /// jitter is how long a missing packet is waited for, in ms
+    pub fn new<F>(
+        address: &str,
+        payload: Payload,
+        channels: usize,
+        sample_rate: u32,
+        jitter: u32,
+        mut callback: F,
+    ) -> Result<RtpStream, Error>
+    where
+        F: FnMut(&[f32]) + Send + 'static,
+    {
+        let socket = UdpSocket::bind(address)?;
+        // wakes up now and then to notice it should stop
+        socket.set_read_timeout(Some(Duration::from_millis(50)))?;
+        let address = socket.local_addr()?;
+        info!("Receiving RTP on {}", address);
+
+        let playing = Arc::new(AtomicBool::new(false));
+        let running = Arc::new(AtomicBool::new(true));
+        let stats = Arc::new(Mutex::new(RtpStats::default()));
+        let (is_playing, is_running, shared_stats) =
+            (playing.clone(), running.clone(), stats.clone());
+        let depth = (u64::from(sample_rate) * u64::from(jitter) / 1000) as usize;
+        let quiet = Duration::from_millis(u64::from(jitter));
+        let worker = thread::spawn(move || {
+            let mut jitter = JitterBuffer::new(channels, depth);
+            let mut ssrc = None;
+            let mut buffer = [0u8; 65536];
+            let mut arrived = Instant::now();
+            while is_running.load(Ordering::Relaxed) {
+                let len = match socket.recv(&mut buffer) {
+                    Ok(len) => len,
+                    Err(err)
+                        if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+                    {
+                        // nothing came for as long as a gap is waited for, whatever is still
+                        // buffered behind one would otherwise never be played
+                        if arrived.elapsed() >= quiet {
+                            while let Some(samples) = jitter.flush() {
+                                callback(&samples);
+                            }
+                        }
+                        continue;
+                    }
+                    Err(err) => {
+                        error!("Could not receive RTP: {}", err);
+                        break;
+                    }
+                };
+                // paused, the packets are dropped
+                if !is_playing.load(Ordering::Relaxed) {
+                    continue;
+                }
+                arrived = Instant::now();
+                match Packet::parse(&buffer[..len], payload) {
+                    Ok(packet) => {
+                        if matches!(ssrc.replace(packet.ssrc), Some(old) if old != packet.ssrc) {
+                            info!("New RTP sender {:08x}", packet.ssrc);
+                            jitter.reset();
+                        }
+                        jitter.push(packet);
+                    }
+                    Err(err) => {
+                        debug!("Dropped an RTP packet: {}", err);
+                        jitter.invalid();
+                    }
+                }
+                while let Some(samples) = jitter.pop() {
+                    callback(&samples);
+                }
+                if let Ok(mut stats) = shared_stats.try_lock() {
+                    *stats = jitter.stats();
+                }
+            }
+        });
+        Ok(RtpStream {
+            playing,
+            running,
+            stats,
+            address,
+            worker: Some(worker),
+        })
+    }
+
+    pub fn play(&self) {
+        self.playing.store(true, Ordering::Relaxed);
+    }
+
+    pub fn pause(&self) {
+        self.playing.store(false, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> RtpStats {
+        self.stats.lock().map(|stats| *stats).unwrap_or_default()
+    }
+
+    /// where it listens, with the port filled in when it was 0
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+}
+
+impl Drop for RtpStream {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(worker) = self.worker.take() {
+            if worker.join().is_err() {
+                error!("The RTP thread panicked");
+            }
+        }
+    }
+}
+
+/// packs interleaved samples into RTP packets for one destination
+pub struct Sender {
+    socket: UdpSocket,
+    payload: Payload,
+    payload_type: u8,
+    channels: usize,
+    sequence: u16,
+    timestamp: u32,
+    ssrc: u32,
+}
+
+impl Sender {
+    pub fn new<A: ToSocketAddrs>(
+        destination: A,
+        payload: Payload,
+        channels: usize,
+        sample_rate: u32,
+    ) -> Result<Sender, Error> {
+        let destination = destination
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| Error::Config(String::from("The destination resolves to no address")))?;
+        // the unspecified address of the same family, an IPv4 socket cannot reach IPv6
+        let local = if destination.is_ipv6() {
+            "[::]:0"
+        } else {
+            "0.0.0.0:0"
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(destination)?;
+        // random enough to tell two runs apart
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(1, |time| time.subsec_nanos() | 1);
+        let channels = channels.max(1);
+        Ok(Sender {
+            socket,
+            payload,
+            payload_type: payload.payload_type(sample_rate, channels),
+            channels,
+            sequence: seed as u16,
+            timestamp: seed.rotate_left(16),
+            ssrc: seed.wrapping_mul(0x9e37_79b9),
+        })
+    }
+
+    pub fn send(&mut self, samples: &[f32]) -> Result<(), Error> {
+        for packet in self.packets(samples) {
+            self.socket.send(&packet)?;
+        }
+        Ok(())
+    }
+
+    fn packets(&mut self, samples: &[f32]) -> Vec<Vec<u8>> {
+        let frames = MAX_PAYLOAD / (self.payload.width() * self.channels);
+        samples
+            .chunks(frames * self.channels)
+            .map(|chunk| {
+                let mut packet = Vec::with_capacity(HEADER + chunk.len() * self.payload.width());
+                packet.push(2 << 6);
+                packet.push(self.payload_type);
+                packet.extend(&self.sequence.to_be_bytes());
+                packet.extend(&self.timestamp.to_be_bytes());
+                packet.extend(&self.ssrc.to_be_bytes());
+                for sample in chunk {
+                    self.payload.encode(*sample, &mut packet);
+                }
+                self.sequence = self.sequence.wrapping_add(1);
+                self.timestamp = self
+                    .timestamp
+                    .wrapping_add((chunk.len() / self.channels) as u32);
+                packet
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    fn packet(sequence: u16, timestamp: u32, value: f32) -> Packet {
+        Packet {
+            sequence,
+            timestamp,
+            ssrc: 1,
+            samples: vec![value; 10],
+        }
+    }
+
+    #[test]
+    fn packets_round_trip() {
+        let samples: Vec<f32> = (0..1000).map(|i| (i as f32 / 500.) - 1.).collect();
+        for payload in [Payload::L16, Payload::L24].iter() {
+            let mut sender = Sender::new("127.0.0.1:9", *payload, 2, 48000).unwrap();
+            let packets = sender.packets(&samples);
+            assert!(packets.iter().all(|p| p.len() <= HEADER + MAX_PAYLOAD));
+            let parsed: Vec<Packet> = packets
+                .iter()
+                .map(|p| Packet::parse(p, *payload).unwrap())
+                .collect();
+            let decoded: Vec<f32> = parsed.iter().flat_map(|p| p.samples.clone()).collect();
+            assert_eq!(decoded.len(), samples.len());
+            let precision = match payload {
+                Payload::L16 => 1e-4,
+                Payload::L24 => 1e-6,
+            };
+            assert!(decoded
+                .iter()
+                .zip(samples.iter())
+                .all(|(a, b)| (a - b).abs() < precision));
+            // one frame on the clock per frame of samples
+            assert_eq!(parsed[1].sequence, parsed[0].sequence.wrapping_add(1));
+            assert_eq!(
+                parsed[1].timestamp.wrapping_sub(parsed[0].timestamp) as usize,
+                parsed[0].samples.len() / 2
+            );
+        }
+        assert!(Packet::parse(&[0x80, 0, 0], Payload::L16).is_err());
+        assert!(Packet::parse(&[0x40; 20], Payload::L16).is_err());
+    }
+
+    #[test]
+    fn jitter_buffer_reorders_and_fills_gaps() {
+        // mono packets of 10 frames, waits 20 frames for a missing one
+        let mut jitter = JitterBuffer::new(1, 20);
+        let sequences = [65534u16, 0, 65535, 2, 3, 4];
+        for (i, sequence) in sequences.iter().enumerate() {
+            let timestamp = u32::from(sequence.wrapping_sub(65534)) * 10;
+            jitter.push(packet(*sequence, timestamp, i as f32));
+        }
+        let mut played = Vec::new();
+        while let Some(samples) = jitter.pop() {
+            played.push(samples[0]);
+            assert_eq!(samples.len(), 10);
+        }
+        // 65535 came late but in time, 1 never came
+        assert_eq!(played, vec![0., 2., 1., 0., 3., 4., 5.]);
+        assert_eq!(jitter.stats().lost, 1);
+
+        // behind what was played already, or twice
+        jitter.push(packet(1, 30, 9.));
+        jitter.push(packet(5, 70, 6.));
+        jitter.push(packet(5, 70, 6.));
+        assert_eq!(jitter.stats().late, 2);
+        assert_eq!(jitter.pop(), Some(vec![6.; 10]));
+        assert_eq!(jitter.pop(), None);
+    }
+
+    #[test]
+    fn partial_frames_are_cut_off() {
+        let mut jitter = JitterBuffer::new(2, 0);
+        let mut odd = packet(0, 0, 1.);
+        odd.samples.push(1.);
+        jitter.push(odd);
+        jitter.push(packet(1, 5, 2.));
+        // the left channel stays on the left
+        assert_eq!(jitter.pop(), Some(vec![1.; 10]));
+        assert_eq!(jitter.pop(), Some(vec![2.; 10]));
+        assert_eq!(jitter.stats().invalid, 1);
+        assert_eq!(jitter.stats().lost, 0);
+    }
+
+    #[test]
+    fn flush_plays_what_waits_behind_a_final_gap() {
+        let mut jitter = JitterBuffer::new(1, 20);
+        jitter.push(packet(0, 0, 1.));
+        jitter.push(packet(2, 20, 3.));
+        assert_eq!(jitter.pop(), Some(vec![1.; 10]));
+        // 10 frames buffered do not reach the depth, the sender stopped
+        assert_eq!(jitter.pop(), None);
+        assert_eq!(jitter.flush(), Some(vec![0.; 10]));
+        assert_eq!(jitter.flush(), Some(vec![3.; 10]));
+        assert_eq!(jitter.flush(), None);
+        assert_eq!(jitter.stats().lost, 1);
+    }
+
+    #[test]
+    fn localhost_round_trip() {
+        let (sender, receiver) = mpsc::channel();
+        let stream = RtpStream::new("127.0.0.1:0", Payload::L24, 1, 48000, 10, move |data| {
+            sender.send(data.to_vec()).unwrap();
+        })
+        .unwrap();
+        stream.play();
+        let samples: Vec<f32> = (0..4800).map(|i| (i as f32 * 0.01).sin() * 0.5).collect();
+        let mut rtp = Sender::new(stream.address(), Payload::L24, 1, 48000).unwrap();
+        for block in samples.chunks(480) {
+            rtp.send(block).unwrap();
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        let mut received = Vec::new();
+        while received.len() < samples.len() {
+            match receiver.recv_timeout(Duration::from_secs(5)) {
+                Ok(block) => received.extend(block),
+                Err(_) => break,
+            }
+        }
+        assert_eq!(received.len(), samples.len());
+        assert!((received[1000] - samples[1000]).abs() < 1e-6);
+        assert_eq!(stream.stats().lost, 0);
+    }
+
+    #[test]
+    fn sends_to_ipv6() {
+        let (sender, receiver) = mpsc::channel();
+        let stream = RtpStream::new("[::1]:0", Payload::L16, 1, 48000, 10, move |data| {
+            sender.send(data.len()).unwrap();
+        })
+        .unwrap();
+        stream.play();
+        let mut rtp = Sender::new(stream.address(), Payload::L16, 1, 48000).unwrap();
+        rtp.send(&[0.25; 480]).unwrap();
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)), Ok(480));
+    }
+}
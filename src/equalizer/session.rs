@@ -0,0 +1,414 @@
+//! spectrum sessions: the band levels of every analysed frame, with the bands and the stream
+//! parameters they came from, saved to a compact binary file and played back without a device
+//!
+//! layout, all little endian: the magic, a version, the header (sample rate u32, channels u16,
+//! FFT size u32, overlap f32, window u8, weighting u8, linear bins u32 or 0 for third octaves,
+//! band count u32, lower and upper edge f32 per band), then per frame its time f32 and one f32
+//! level per band
+
+use crate::equalizer::dsp::spectrum::{centred, Band, Frame, Settings, SpectrumFrame, Weighting};
+use crate::equalizer::dsp::window::WindowType;
+use crate::errors::Error;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+const MAGIC: &[u8; 6] = b"RZSESS";
+const VERSION: u16 = 1;
+const MIN_SPEED: f64 = 0.125;
+const MAX_SPEED: f64 = 8.;
+const MAX_BANDS: usize = 1 << 16; // a corrupt count must not size the buffers
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SessionHeader {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub settings: Settings,
+    pub bands: Vec<(f32, f32)>, // lower and upper edge in Hz
+}
+
+impl SessionHeader {
+    /// centre of every band, see `spectrum::centred`
+    pub fn centres(&self) -> Vec<f32> {
+        centred(&self.bands, self.settings.bins)
+            .iter()
+            .map(|band| band.centre)
+            .collect()
+    }
+}
+
+pub struct SessionWriter<W: Write> {
+    out: W,
+    bands: usize,
+    start: Option<f32>, // analysis time of the first frame, the session starts at 0
+}
+
+impl SessionWriter<BufWriter<File>> {
+    pub fn create(path: &Path, header: &SessionHeader) -> Result<Self, Error> {
+        SessionWriter::new(BufWriter::new(File::create(path)?), header)
+    }
+}
+
+impl<W: Write> SessionWriter<W> {
+    pub fn new(mut out: W, header: &SessionHeader) -> Result<SessionWriter<W>, Error> {
+        let settings = &header.settings;
+        out.write_all(MAGIC)?;
+        out.write_all(&VERSION.to_le_bytes())?;
+        out.write_all(&header.sample_rate.to_le_bytes())?;
+        out.write_all(&header.channels.to_le_bytes())?;
+        out.write_all(&(settings.fft_size as u32).to_le_bytes())?;
+        out.write_all(&settings.overlap.to_le_bytes())?;
+        out.write_all(&[
+            window_code(settings.window),
+            weighting_code(settings.weighting),
+        ])?;
+        out.write_all(&(settings.bins.unwrap_or(0) as u32).to_le_bytes())?;
+        out.write_all(&(header.bands.len() as u32).to_le_bytes())?;
+        for (lower, upper) in header.bands.iter() {
+            out.write_all(&lower.to_le_bytes())?;
+            out.write_all(&upper.to_le_bytes())?;
+        }
+        Ok(SessionWriter {
+            out,
+            bands: header.bands.len(),
+            start: None,
+        })
+    }
+
+    pub fn write(&mut self, frame: &Frame) -> Result<(), Error> {
+        if frame.levels.len() != self.bands {
+            return Err(Error::Session(format!(
+                "A frame has {} bands, the session {}",
+                frame.levels.len(),
+                self.bands
+            )));
+        }
+        let start = *self.start.get_or_insert(frame.time);
+        self.out.write_all(&(frame.time - start).to_le_bytes())?;
+        for level in frame.levels.iter() {
+            self.out.write_all(&level.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<W, Error> {
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+pub struct Session {
+    pub header: SessionHeader,
+    pub frames: Vec<Frame>,
+}
+
+impl Session {
+    pub fn load(path: &Path) -> Result<Session, Error> {
+        Session::read(BufReader::new(File::open(path)?))
+    }
+
+    /// a frame cut short, by a crash while recording, ends the session
+    pub fn read<R: Read>(mut input: R) -> Result<Session, Error> {
+        let mut magic = [0u8; 6];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(Error::Session(String::from("Not a session file")));
+        }
+        let version = u16::from_le_bytes(bytes(&mut input)?);
+        if version != VERSION {
+            return Err(Error::Session(format!("Unknown version {}", version)));
+        }
+        let sample_rate = u32::from_le_bytes(bytes(&mut input)?);
+        let channels = u16::from_le_bytes(bytes(&mut input)?);
+        let fft_size = u32::from_le_bytes(bytes(&mut input)?) as usize;
+        let overlap = f32::from_le_bytes(bytes(&mut input)?);
+        let [window, weighting] = bytes(&mut input)?;
+        let bins = u32::from_le_bytes(bytes(&mut input)?) as usize;
+        let count = u32::from_le_bytes(bytes(&mut input)?) as usize;
+        if count > MAX_BANDS {
+            return Err(Error::Session(format!("Too many bands: {}", count)));
+        }
+        let mut bands = Vec::with_capacity(count);
+        for _ in 0..count {
+            let lower = f32::from_le_bytes(bytes(&mut input)?);
+            let upper = f32::from_le_bytes(bytes(&mut input)?);
+            bands.push((lower, upper));
+        }
+        let header = SessionHeader {
+            sample_rate,
+            channels,
+            settings: Settings {
+                window: window_from_code(window)?,
+                fft_size,
+                overlap,
+                bins: if bins == 0 { None } else { Some(bins) },
+                weighting: weighting_from_code(weighting)?,
+                smoothing: None, // does not change what the bands mean, not stored
+            },
+            bands,
+        };
+
+        let mut frames = Vec::new();
+        let mut record = vec![0u8; 4 * (count + 1)];
+        loop {
+            match input.read_exact(&mut record) {
+                Ok(()) => {}
+                Err(err) if err.kind() == ErrorKind::UnexpectedEof => break,
+                Err(err) => return Err(err.into()),
+            }
+            let mut values = record
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+            let time = values.next().unwrap_or(0.);
+            frames.push(Frame {
+                time,
+                levels: values.collect(),
+            });
+        }
+        Ok(Session { header, frames })
+    }
+
+    /// time of the last frame in seconds
+    pub fn duration(&self) -> f32 {
+        self.frames.last().map_or(0., |frame| frame.time)
+    }
+}
+
+fn bytes<R: Read, const N: usize>(input: &mut R) -> Result<[u8; N], Error> {
+    let mut buffer = [0u8; N];
+    input.read_exact(&mut buffer)?;
+    Ok(buffer)
+}
+
+fn window_code(window: WindowType) -> u8 {
+    match window {
+        WindowType::Hann => 0,
+        WindowType::Hamming => 1,
+        WindowType::Blackman => 2,
+        WindowType::Rectangular => 3,
+    }
+}
+
+fn window_from_code(code: u8) -> Result<WindowType, Error> {
+    match code {
+        0 => Ok(WindowType::Hann),
+        1 => Ok(WindowType::Hamming),
+        2 => Ok(WindowType::Blackman),
+        3 => Ok(WindowType::Rectangular),
+        _ => Err(Error::Session(format!("Unknown window {}", code))),
+    }
+}
+
+fn weighting_code(weighting: Weighting) -> u8 {
+    match weighting {
+        Weighting::Z => 0,
+        Weighting::A => 1,
+        Weighting::C => 2,
+    }
+}
+
+fn weighting_from_code(code: u8) -> Result<Weighting, Error> {
+    match code {
+        0 => Ok(Weighting::Z),
+        1 => Ok(Weighting::A),
+        2 => Ok(Weighting::C),
+        _ => Err(Error::Session(format!("Unknown weighting {}", code))),
+    }
+}
+
+/// plays a session back in real time, or faster or slower; the front-ends call advance with the
+/// time passed since the previous call and draw frame()
+pub struct Player {
+    session: Session,
+    bands: Arc<[Band]>,
+    position: f32, // seconds into the session
+    speed: f64,
+    playing: bool,
+}
+
+impl Player {
+    pub fn new(session: Session) -> Player {
+        let header = &session.header;
+        let bands = centred(&header.bands, header.settings.bins).into();
+        Player {
+            session,
+            bands,
+            position: 0.,
+            speed: 1.,
+            playing: true,
+        }
+    }
+
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    pub fn position(&self) -> f32 {
+        self.position
+    }
+
+    pub fn playing(&self) -> bool {
+        self.playing
+    }
+
+    /// playing again at the end starts over
+    pub fn set_playing(&mut self, playing: bool) {
+        if playing && self.position >= self.session.duration() {
+            self.position = 0.;
+        }
+        self.playing = playing;
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    pub fn set_speed(&mut self, speed: f64) {
+        self.speed = speed.clamp(MIN_SPEED, MAX_SPEED);
+    }
+
+    pub fn seek(&mut self, position: f32) {
+        self.position = position.max(0.).min(self.session.duration());
+    }
+
+    /// pauses at the end
+    pub fn advance(&mut self, elapsed: Duration) {
+        if !self.playing {
+            return;
+        }
+        let position = self.position + (elapsed.as_secs_f64() * self.speed) as f32;
+        self.seek(position);
+        if self.position >= self.session.duration() {
+            self.playing = false;
+        }
+    }
+
+    /// the last frame at or before the position
+    pub fn frame(&self) -> Option<&Frame> {
+        self.session.frames.get(self.index()?)
+    }
+
+    fn index(&self) -> Option<usize> {
+        let after = self
+            .session
+            .frames
+            .partition_point(|frame| frame.time <= self.position);
+        after.checked_sub(1)
+    }
+
+    /// frame() as the live front-ends get it, the sequence is the index in the session
+    pub fn spectrum(&self) -> Option<SpectrumFrame> {
+        let sequence = self.index()?;
+        let frame = &self.session.frames[sequence];
+        let header = &self.session.header;
+        Some(SpectrumFrame::new(
+            Frame {
+                time: frame.time,
+                levels: frame.levels.clone(),
+            },
+            self.bands.clone(),
+            &header.settings,
+            header.sample_rate,
+            sequence as u64,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header() -> SessionHeader {
+        SessionHeader {
+            sample_rate: 48000,
+            channels: 2,
+            settings: Settings {
+                window: WindowType::Blackman,
+                weighting: Weighting::A,
+                bins: Some(2),
+                ..Settings::default()
+            },
+            bands: vec![(0., 12000.), (12000., 24000.)],
+        }
+    }
+
+    fn session(frames: usize) -> Vec<u8> {
+        let mut writer = SessionWriter::new(Vec::new(), &header()).unwrap();
+        for i in 0..frames {
+            writer
+                .write(&Frame {
+                    time: 10. + i as f32 * 0.5,
+                    levels: vec![-(i as f32), -60.],
+                })
+                .unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let bytes = session(3);
+        // 6 + 2 + 4 + 2 + 4 + 4 + 2 + 4 + 4 header bytes, 2 bands, 3 frames
+        assert_eq!(bytes.len(), 32 + 2 * 8 + 3 * 12);
+        let session = Session::read(bytes.as_slice()).unwrap();
+        assert_eq!(session.header, header());
+        assert_eq!(session.frames.len(), 3);
+        // times start at the first frame
+        assert_eq!(session.frames[0].time, 0.);
+        assert_eq!(session.frames[2].time, 1.);
+        assert_eq!(session.frames[2].levels, vec![-2., -60.]);
+        assert_eq!(session.header.centres(), vec![6000., 18000.]);
+    }
+
+    #[test]
+    fn truncated_frame_ends_the_session() {
+        let mut bytes = session(3);
+        bytes.truncate(bytes.len() - 5);
+        assert_eq!(Session::read(bytes.as_slice()).unwrap().frames.len(), 2);
+        assert!(Session::read(&b"RIFF...."[..]).is_err());
+    }
+
+    #[test]
+    fn rejects_a_corrupt_band_count() {
+        let mut bytes = session(1);
+        // the band count is the last header field
+        bytes[28..32].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(Session::read(bytes.as_slice()).is_err());
+    }
+
+    #[test]
+    fn player_follows_the_clock() {
+        let mut player = Player::new(Session::read(session(5).as_slice()).unwrap());
+        assert_eq!(player.frame().unwrap().levels[0], 0.);
+        player.advance(Duration::from_millis(600));
+        assert_eq!(player.frame().unwrap().levels[0], -1.);
+
+        player.set_speed(2.);
+        player.advance(Duration::from_millis(500));
+        assert_eq!(player.frame().unwrap().levels[0], -3.);
+
+        player.set_playing(false);
+        player.advance(Duration::from_secs(1));
+        assert!((player.position() - 1.6).abs() < 1e-6);
+
+        player.seek(0.2);
+        assert_eq!(player.frame().unwrap().levels[0], 0.);
+        player.seek(1.2);
+        let spectrum = player.spectrum().unwrap();
+        assert_eq!(spectrum.sequence, 2);
+        assert_eq!(spectrum.magnitudes, vec![-2., -60.]);
+        assert_eq!(spectrum.bands[1].centre, 18000.);
+        assert_eq!(spectrum.sample_rate, 48000);
+        player.seek(0.2);
+
+        // stops at the end and starts over when played again
+        player.set_playing(true);
+        player.advance(Duration::from_secs(10));
+        assert_eq!(player.position(), 2.);
+        assert!(!player.playing());
+        player.set_playing(true);
+        assert_eq!(player.position(), 0.);
+    }
+}
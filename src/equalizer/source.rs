@@ -0,0 +1,106 @@
+use crate::equalizer::generator::{Signal, DEFAULT_LEVEL};
+use crate::equalizer::pcm::SampleFormat;
+use crate::equalizer::rtp::{Payload, DEFAULT_JITTER, DEFAULT_PORT};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// where the samples come from
+#[derive(Clone, Debug, PartialEq)]
+pub enum Source {
+    Device,
+    Generator {
+        signal: Signal,
+        level: f32, // dBFS
+    },
+    Pipe {
+        path: Option<PathBuf>, // stdin without one
+        format: SampleFormat,
+    },
+    Rtp {
+        address: String, // to listen on
+        payload: Payload,
+        jitter: u32, // ms
+    },
+}
+
+impl FromStr for Source {
+    type Err = String;
+
+    // device, gen:<signal>, stdin (or -), pipe:<path> and rtp[:[address:]port]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "device" => return Ok(Source::Device),
+            "stdin" | "-" => {
+                return Ok(Source::Pipe {
+                    path: None,
+                    format: SampleFormat::S16,
+                })
+            }
+            "rtp" => return Ok(rtp(format!("0.0.0.0:{}", DEFAULT_PORT))),
+            _ => {}
+        }
+        if let Some(address) = s.strip_prefix("rtp:") {
+            // just a port listens on all interfaces
+            return match address.parse::<u16>() {
+                Ok(port) => Ok(rtp(format!("0.0.0.0:{}", port))),
+                Err(_) => Ok(rtp(address.to_string())),
+            };
+        }
+        if let Some(path) = s.strip_prefix("pipe:") {
+            return Ok(Source::Pipe {
+                path: Some(PathBuf::from(path)),
+                format: SampleFormat::S16,
+            });
+        }
+        match s.strip_prefix("gen:") {
+            Some(signal) => Ok(Source::Generator {
+                signal: signal.parse()?,
+                level: DEFAULT_LEVEL,
+            }),
+            None => Err(format!(
+                "Unknown source {}, expected device, gen:<signal>, stdin, pipe:<path> or rtp:<port>",
+                s
+            )),
+        }
+    }
+}
+
+fn rtp(address: String) -> Source {
+    Source::Rtp {
+        address,
+        payload: Payload::L16,
+        jitter: DEFAULT_JITTER,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sources() {
+        assert_eq!("device".parse(), Ok(Source::Device));
+        assert_eq!(
+            "gen:pink".parse(),
+            Ok(Source::Generator {
+                signal: Signal::PinkNoise,
+                level: DEFAULT_LEVEL
+            })
+        );
+        assert_eq!(
+            "pipe:/tmp/audio".parse(),
+            Ok(Source::Pipe {
+                path: Some(PathBuf::from("/tmp/audio")),
+                format: SampleFormat::S16
+            })
+        );
+        assert!(matches!("-".parse(), Ok(Source::Pipe { path: None, .. })));
+        assert!(
+            matches!("rtp:6000".parse(), Ok(Source::Rtp { address, .. }) if address == "0.0.0.0:6000")
+        );
+        assert!(
+            matches!("rtp:[::1]:6000".parse(), Ok(Source::Rtp { address, .. }) if address == "[::1]:6000")
+        );
+        assert!("pink".parse::<Source>().is_err());
+    }
+}
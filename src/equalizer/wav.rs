@@ -0,0 +1,115 @@
+//! reading and writing of WAV files, shared by the impulse response loader and the offline analysis
+use crate::errors::Error;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+/// reads all channels of a WAV file as floats in -1..1, one vector per channel
+pub fn read(path: &Path) -> Result<(Vec<Vec<f32>>, u32), Error> {
+    let (interleaved, channels, sample_rate) = read_interleaved(path)?;
+    Ok((deinterleave(&interleaved, channels), sample_rate))
+}
+
+/// reads a WAV file as floats in -1..1 the way it is stored, returns the samples, the number
+/// of channels and the sample rate
+pub fn read_interleaved(path: &Path) -> Result<(Vec<f32>, usize, u32), Error> {
+    let mut blocks = Blocks::open(path)?;
+    let mut interleaved = Vec::new();
+    blocks.read(&mut interleaved, usize::MAX)?;
+    Ok((interleaved, blocks.channels(), blocks.sample_rate()))
+}
+
+/// reads a WAV file a block at a time, for files that do not need to be in memory at once
+pub struct Blocks {
+    reader: hound::WavReader<BufReader<File>>,
+    scale: Option<f32>, // of integer samples, None for floats
+}
+
+impl Blocks {
+    pub fn open(path: &Path) -> Result<Blocks, Error> {
+        let reader = hound::WavReader::open(path)?;
+        let spec = reader.spec();
+        let scale = match spec.sample_format {
+            hound::SampleFormat::Float => None,
+            hound::SampleFormat::Int => Some((1i64 << (spec.bits_per_sample - 1)) as f32),
+        };
+        Ok(Blocks { reader, scale })
+    }
+
+    pub fn channels(&self) -> usize {
+        self.reader.spec().channels as usize
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.reader.spec().sample_rate
+    }
+
+    /// replaces the contents of block with up to len interleaved samples in -1..1, returns how
+    /// many there were; 0 at the end of the file
+    pub fn read(&mut self, block: &mut Vec<f32>, len: usize) -> Result<usize, Error> {
+        block.clear();
+        match self.scale {
+            None => {
+                for sample in self.reader.samples::<f32>().take(len) {
+                    block.push(sample?);
+                }
+            }
+            Some(scale) => {
+                for sample in self.reader.samples::<i32>().take(len) {
+                    block.push(sample? as f32 / scale);
+                }
+            }
+        }
+        Ok(block.len())
+    }
+}
+
+/// splits interleaved samples into one vector per channel, an incomplete last frame is dropped
+pub fn deinterleave(interleaved: &[f32], channels: usize) -> Vec<Vec<f32>> {
+    let mut data = vec![Vec::with_capacity(interleaved.len() / channels); channels];
+    for frame in interleaved.chunks_exact(channels) {
+        for (channel, sample) in data.iter_mut().zip(frame) {
+            channel.push(*sample);
+        }
+    }
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_blocks() {
+        let path =
+            std::env::temp_dir().join(format!("rustalizer-blocks-{}.wav", std::process::id()));
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 8000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for i in 0..10 {
+            writer.write_sample((i * 1024) as i16).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let mut blocks = Blocks::open(&path).unwrap();
+        assert_eq!((blocks.channels(), blocks.sample_rate()), (2, 8000));
+        let mut block = Vec::new();
+        assert_eq!(blocks.read(&mut block, 4).unwrap(), 4);
+        assert_eq!(block[1], 1024. / 32768.);
+        assert_eq!(blocks.read(&mut block, 4).unwrap(), 4);
+        assert_eq!(blocks.read(&mut block, 4).unwrap(), 2);
+        assert_eq!(blocks.read(&mut block, 4).unwrap(), 0);
+        assert_eq!(read_interleaved(&path).unwrap().0.len(), 10);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn splits_channels() {
+        let data = deinterleave(&[1., 0., 0.5, 0.5, 1.], 2);
+        assert_eq!(data, vec![vec![1., 0.5], vec![0., 0.5]]);
+    }
+}
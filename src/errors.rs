@@ -15,14 +15,25 @@ pub enum BufferOp {
 #[derive(Debug)]
 pub enum Error {
     NoCpalDevice,
+    NoOutputDevice,
+    #[cfg(feature = "capture")]
     BuildStream(cpal::BuildStreamError),
+    #[cfg(feature = "capture")]
     PlayStream(cpal::PlayStreamError),
+    #[cfg(feature = "capture")]
     PauseStream(cpal::PauseStreamError),
     StreamOperation(StreamOp),
     BufferOperation(BufferOp),
-    FFTOperation,
+    Config(String),
     IO(std::io::Error),
+    Wav(hound::Error),
+    Session(String),
     MPSCRecv(std::sync::mpsc::RecvError),
+    NoInput(std::time::Duration), // nothing was captured for that long
+    Worker(String),
+    Stopped, // Ctrl-C or SIGTERM while waiting for the input
+    Signal(ctrlc::Error),
+    #[cfg(feature = "terminal")]
     Crossterm(crossterm::ErrorKind),
 }
 
@@ -30,14 +41,25 @@ impl std::error::Error for Error {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
             Error::NoCpalDevice => None,
+            Error::NoOutputDevice => None,
+            #[cfg(feature = "capture")]
             Error::BuildStream(err) => Some(err),
+            #[cfg(feature = "capture")]
             Error::PlayStream(err) => Some(err),
+            #[cfg(feature = "capture")]
             Error::PauseStream(err) => Some(err),
             Error::StreamOperation(_) => None,
             Error::BufferOperation(_) => None,
-            Error::FFTOperation => None,
+            Error::Config(_) => None,
             Error::IO(err) => Some(err),
+            Error::Wav(err) => Some(err),
+            Error::Session(_) => None,
             Error::MPSCRecv(err) => Some(err),
+            Error::NoInput(_) => None,
+            Error::Worker(_) => None,
+            Error::Stopped => None,
+            Error::Signal(err) => Some(err),
+            #[cfg(feature = "terminal")]
             Error::Crossterm(err) => Some(err),
         }
     }
@@ -47,8 +69,12 @@ impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
             Error::NoCpalDevice => write!(f, "No cpal device available"),
+            Error::NoOutputDevice => write!(f, "No such cpal output device available"),
+            #[cfg(feature = "capture")]
             Error::BuildStream(_) => write!(f, "Could not create build stream"),
+            #[cfg(feature = "capture")]
             Error::PlayStream(_) => write!(f, "Could not play stream"),
+            #[cfg(feature = "capture")]
             Error::PauseStream(_) => write!(f, "Could not pause stream"),
             Error::StreamOperation(op) => match op {
                 StreamOp::Play => {
@@ -62,26 +88,40 @@ impl std::fmt::Display for Error {
                 BufferOp::Push => write!(f, "Push failed! The RingBuffer is full!"),
                 BufferOp::Pop => write!(f, "Pop failed! The RingBuffer is empty!"),
             },
-            Error::FFTOperation=> write!(f, "The input data was greater than the sampling rate, probably CPAL hiccup - ignoring"),
-            Error::IO(_) => write!(f, "Could not create terminal backend!"),
+            Error::Config(msg) => write!(f, "Invalid configuration: {}", msg),
+            Error::IO(err) => write!(f, "I/O error: {}", err),
+            Error::Wav(err) => write!(f, "Could not read the WAV file: {}", err),
+            Error::Session(msg) => write!(f, "Invalid session file: {}", msg),
             Error::MPSCRecv(_) => write!(f, "The receiver queue is empty!"),
+            Error::NoInput(waited) => write!(
+                f,
+                "No audio arrived from the input for {:.1} s",
+                waited.as_secs_f32()
+            ),
+            Error::Worker(msg) => write!(f, "The analysis thread failed: {}", msg),
+            Error::Stopped => write!(f, "Stopped while waiting for the input"),
+            Error::Signal(_) => write!(f, "Could not install the signal handler"),
+            #[cfg(feature = "terminal")]
             Error::Crossterm(_) => write!(f, "Could not create TUI"),
         }
     }
 }
 
+#[cfg(feature = "capture")]
 impl From<cpal::BuildStreamError> for Error {
     fn from(err: cpal::BuildStreamError) -> Error {
         Error::BuildStream(err)
     }
 }
 
+#[cfg(feature = "capture")]
 impl From<cpal::PlayStreamError> for Error {
     fn from(err: cpal::PlayStreamError) -> Error {
         Error::PlayStream(err)
     }
 }
 
+#[cfg(feature = "capture")]
 impl From<cpal::PauseStreamError> for Error {
     fn from(err: cpal::PauseStreamError) -> Error {
         Error::PauseStream(err)
@@ -94,12 +134,25 @@ impl From<std::io::Error> for Error {
     }
 }
 
+impl From<hound::Error> for Error {
+    fn from(err: hound::Error) -> Error {
+        Error::Wav(err)
+    }
+}
+
 impl From<std::sync::mpsc::RecvError> for Error {
     fn from(err: std::sync::mpsc::RecvError) -> Error {
         Error::MPSCRecv(err)
     }
 }
 
+impl From<ctrlc::Error> for Error {
+    fn from(err: ctrlc::Error) -> Error {
+        Error::Signal(err)
+    }
+}
+
+#[cfg(feature = "terminal")]
 impl From<crossterm::ErrorKind> for Error {
     fn from(err: crossterm::ErrorKind) -> Error {
         Error::Crossterm(err)
@@ -0,0 +1,66 @@
+//! Audio analysis for live streams and files: a spectrum analyser with third-octave or linear
+//! bands, a scope, a vectorscope, a tuner, a chromagram and beat tracking, plus an equalizer
+//! whose result can be listened to or analysed.
+//!
+//! The headless parts need no front-end:
+//!
+//! - [`equalizer::Equalizer`] captures an input (a device, a generator, a pipe or RTP), runs
+//!   the equalizer and feeds the analysis thread, [`equalizer::dsp::DSP`].
+//! - [`equalizer::dsp::spectrum`] is the spectrum pipeline on its own: framing, windowing
+//!   ([`equalizer::dsp::window`]), the FFT ([`equalizer::dsp::fft`]), weighting and binning.
+//! - [`equalizer::dsp::queue`] carries the samples from the audio callback to the worker without
+//!   locks or allocations, and the spectrum frames out of it to any number of subscribers, each
+//!   with its own bounded queue and policy for when it lags.
+//! - [`ring_buffer::RingBuffer`] is the fixed size queue the front-ends buffer frames in, its
+//!   [`spsc`](ring_buffer::RingBuffer::spsc) halves are what the audio callbacks share with the
+//!   other threads.
+//! - [`analyze`] and [`report`] work on WAV files.
+//! - [`shutdown`] turns Ctrl-C and SIGTERM into the same orderly exit as `q`.
+//!
+//! The GTK window lives in `app` behind the `gui` feature and the terminal front-end in `tui`
+//! behind the `terminal` feature. Audio devices go through cpal behind the `capture` feature;
+//! without it an [`equalizer::Equalizer`] still takes a generator, a pipe or RTP. All three are
+//! on by default, without them:
+//!
+//! ```toml
+//! rustalizer = { version = "0.1", default-features = false }
+//! ```
+//!
+//! Band levels of a 1 kHz tone:
+//!
+//! ```
+//! use rustalizer::equalizer::dsp::spectrum::{Analyzer, Settings};
+//! use rustalizer::equalizer::generator::{Generator, Signal};
+//!
+//! let mut tone = vec![0.; 48000];
+//! Generator::new(Signal::Sine(1000.), 48000, -6.).fill(&mut tone, 1);
+//! let mut analyzer = Analyzer::new(Settings::default(), 48000);
+//! let frames = analyzer.feed(&tone);
+//! let levels = &frames.last().unwrap().levels;
+//! let loudest = (0..levels.len())
+//!     .max_by(|a, b| levels[*a].partial_cmp(&levels[*b]).unwrap())
+//!     .unwrap();
+//! let (lower, upper) = analyzer.bands()[loudest];
+//! assert!(lower < 1000. && 1000. < upper);
+//! ```
+
+#[cfg(feature = "gui")]
+extern crate gio;
+#[cfg(feature = "gui")]
+#[macro_use]
+extern crate glib;
+#[cfg(feature = "gui")]
+extern crate gtk;
+#[macro_use]
+extern crate log;
+
+pub mod analyze;
+#[cfg(feature = "gui")]
+pub mod app;
+pub mod equalizer;
+pub mod errors;
+pub mod report;
+pub mod ring_buffer;
+pub mod shutdown;
+#[cfg(feature = "terminal")]
+pub mod tui;
@@ -1,23 +1,34 @@
-extern crate gio;
-#[macro_use]
-extern crate glib;
-extern crate gtk;
 #[macro_use]
 extern crate log;
 extern crate simplelog;
 
-mod app;
-mod equalizer;
-mod errors;
-mod ring_buffer;
-mod tui;
+mod send;
 
 use anyhow::{Context, Result};
-use equalizer::Equalizer;
+use rustalizer::analyze::{self, Format};
+#[cfg(feature = "gui")]
+use rustalizer::app;
+use rustalizer::equalizer::dsp::biquad::FilterSpec;
+use rustalizer::equalizer::dsp::pipeline::PipelineSpec;
+use rustalizer::equalizer::dsp::spectrum::{Settings, Weighting};
+use rustalizer::equalizer::dsp::tuner::Temperament;
+use rustalizer::equalizer::dsp::window::WindowType;
+use rustalizer::equalizer::pcm::SampleFormat;
+use rustalizer::equalizer::recorder::Rotation;
+use rustalizer::equalizer::rtp::Payload;
+use rustalizer::equalizer::session::{Player, Session};
+use rustalizer::equalizer::source::Source;
+use rustalizer::equalizer::{self, Equalizer};
+#[cfg(feature = "terminal")]
+use rustalizer::tui;
+use rustalizer::{errors, report, shutdown};
 use simplelog::*;
 use std::cell::RefCell;
+use std::path::PathBuf;
+#[cfg(feature = "capture")]
 use std::process;
 use std::rc::Rc;
+use std::time::Duration;
 use structopt::StructOpt;
 
 #[derive(StructOpt, Debug)]
@@ -27,7 +38,7 @@ use structopt::StructOpt;
 struct Cli {
     /// Specify display type, GUI for a GTK window based rendering, or TUI for a terminal based
     /// interface
-    #[structopt(name = "mode", long, short)]
+    #[structopt(name = "mode", long, short, default_value = "TUI")]
     app_mode: String,
     /// Pass the name of monitored device, obtained by running "rustalizer -q"
     #[structopt(name = "device", long, short)]
@@ -38,9 +49,186 @@ struct Cli {
     /// Display available devices and hosts
     #[structopt(short, long)]
     query: bool,
+    /// Analyse "device", a synthetic signal "gen:<signal>", raw PCM from "stdin" or
+    /// "pipe:<path>", or RTP received on "rtp[:[address:]port]" (port 5004 by default); signal
+    /// is one of sine:F, multi:F1,F2,.., square:F, saw:F, white, pink,
+    /// brown, sweep:FROM:TO[:SECONDS], logsweep:FROM:TO[:SECONDS] or impulse[:PER_SECOND],
+    /// e.g. --source gen:sine:1000
+    #[structopt(long, default_value = "device")]
+    source: Source,
+    /// Sample rate in Hz
+    #[structopt(long, default_value = "44100")]
+    rate: u32,
+    /// Level of the generated signal in dBFS
+    #[structopt(long, default_value = "-12", allow_hyphen_values = true)]
+    level: f32,
+    /// Sample format of piped PCM: s16le, s24le or f32le, interleaved
+    #[structopt(long, default_value = "s16le")]
+    format: SampleFormat,
+    /// RTP payload: l16 or l24, at --rate with --channels
+    #[structopt(long, default_value = "l16")]
+    payload: Payload,
+    /// How long a missing RTP packet is waited for before it is counted lost, in ms
+    #[structopt(long, default_value = "40")]
+    jitter: u32,
+    #[structopt(flatten)]
+    spectrum: SpectrumOpts,
+    /// Number of captured channels, the first two are used as L/R by the vectorscope
+    #[structopt(short, long, default_value = "1")]
+    channels: u16,
+    /// Reference frequency of A4 in Hz, used by the tuner and the chromagram
+    #[structopt(long, default_value = "440")]
+    a4: f32,
+    /// Tuner temperament: equal, just, pythagorean or meantone
+    #[structopt(long, default_value = "equal")]
+    temperament: Temperament,
+    /// Play the equalized input on this output device, "default" picks the default one
+    #[structopt(long)]
+    output: Option<String>,
+    /// Add a filter to the equalizer as type:frequency[:q[:gain]], where type is one of peak,
+    /// lowshelf, highshelf, lowpass, highpass, notch or bandpass, e.g. --eq peak:1000:1.4:-6
+    #[structopt(long = "eq")]
+    filters: Vec<FilterSpec>,
+    /// Add a graphic equalizer with 10 octave or 31 third-octave bands
+    #[structopt(long, possible_values = &["10", "31"])]
+    graphic_eq: Option<usize>,
+    /// Convolve the equalizer output with the impulse response in this WAV file
+    #[structopt(long, parse(from_os_str))]
+    ir: Option<PathBuf>,
+    /// Partition size of the convolution in samples, a power of two; it is also the added latency
+    #[structopt(long, default_value = "512")]
+    ir_block: usize,
+    /// Also show the spectrum after the equalizer, next to the captured one
+    #[structopt(long)]
+    post_eq: bool,
+    /// Start recording the captured input right away, "w" in the TUI or the Record button toggle it
+    #[structopt(long)]
+    record: bool,
+    /// Directory the recordings are written to
+    #[structopt(long, default_value = ".", parse(from_os_str))]
+    record_dir: PathBuf,
+    /// Start a new recording file after this many megabytes of audio
+    #[structopt(long, parse(try_from_str = megabytes))]
+    record_max_size: Option<u64>,
+    /// Start a new recording file after this many seconds
+    #[structopt(long, parse(try_from_str = seconds))]
+    record_max_duration: Option<f32>,
+    /// Start recording the spectrum to a session file right away, "s" in the TUI or the Record
+    /// session button toggle it
+    #[structopt(long)]
+    session: bool,
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(StructOpt, Debug)]
+struct SpectrumOpts {
     /// Number of frequency bins displayed, if empty then a common frequency binning is applied
     #[structopt(short, long)]
     bins: Option<usize>,
+    /// Window applied before the FFT: hann, hamming, blackman or rectangular
+    #[structopt(long, default_value = "hann")]
+    window: WindowType,
+    /// FFT size in samples, a power of two
+    #[structopt(long, default_value = "2048")]
+    fft_size: usize,
+    /// Fraction of a frame shared with the previous one, in 0..1
+    #[structopt(long, default_value = "0.5")]
+    overlap: f32,
+    /// Frequency weighting of the levels: a, c or z for none
+    #[structopt(long, default_value = "z")]
+    weighting: Weighting,
+    /// Average the levels over frames, the closer to 1 the slower they follow; off by default
+    #[structopt(long)]
+    smoothing: Option<f32>,
+    /// Stages of the spectrum in order, e.g. "dc,window,fft,weighting,bins,smoothing=0.6,db";
+    /// window, weighting and bins without a value follow the options above
+    #[structopt(long)]
+    pipeline: Option<PipelineSpec>,
+}
+
+impl SpectrumOpts {
+    fn settings(&self) -> Settings {
+        let settings = Settings {
+            window: self.window,
+            fft_size: self.fft_size,
+            overlap: self.overlap,
+            bins: self.bins,
+            weighting: self.weighting,
+            smoothing: self.smoothing,
+        };
+        match self.pipeline.as_ref() {
+            Some(spec) => spec.settings(settings),
+            None => settings,
+        }
+    }
+}
+
+#[derive(StructOpt, Debug)]
+enum Command {
+    /// Write the band levels of every frame of a WAV file, without opening an audio device
+    Analyze {
+        /// WAV file to analyze, all channels are mixed down
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+        /// Output format: csv, or jsonl / ndjson for one JSON object per line
+        #[structopt(short, long, default_value = "csv")]
+        format: Format,
+        /// Write to this file instead of stdout
+        #[structopt(short, long = "output", parse(from_os_str))]
+        output_file: Option<PathBuf>,
+        #[structopt(flatten)]
+        spectrum: SpectrumOpts,
+    },
+    /// Write the long-term power spectral density of a WAV file or a capture as CSV and SVG
+    Report {
+        /// WAV file to analyze, all channels are mixed down; without it the device is captured
+        #[structopt(parse(from_os_str))]
+        input: Option<PathBuf>,
+        /// Seconds to capture from the device when there is no input file
+        #[structopt(long, default_value = "10", parse(try_from_str = seconds))]
+        duration: f32,
+        /// Segment length in samples, a power of two; longer segments resolve finer details but
+        /// average fewer segments
+        #[structopt(long, default_value = "4096")]
+        segment: usize,
+        /// Fraction of a segment shared with the previous one, in 0..1
+        #[structopt(long, default_value = "0.5")]
+        overlap: f32,
+        /// Window applied to every segment: hann, hamming, blackman or rectangular
+        #[structopt(long, default_value = "hann")]
+        window: WindowType,
+        /// Writes <output>.csv and <output>.svg
+        #[structopt(short, long, default_value = "report", parse(from_os_str))]
+        output: PathBuf,
+    },
+    /// Stream --source (a generator, stdin or a pipe) over RTP to another rustalizer
+    Send {
+        /// Where to send to as address:port, e.g. 127.0.0.1:5004
+        destination: String,
+    },
+    /// Play back a recorded spectrum session in the TUI or the GUI, without an audio device
+    Replay {
+        /// Session file, recorded with --session or the "s" key
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+    },
+}
+
+// a duration that Duration::from_secs_f32 takes
+fn seconds(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(seconds) if seconds > 0. && seconds.is_finite() => Ok(seconds),
+        _ => Err(format!("{} is not a positive number of seconds", s)),
+    }
+}
+
+// a rotation size of at least one megabyte
+fn megabytes(s: &str) -> Result<u64, String> {
+    match s.parse::<u64>() {
+        Ok(megabytes) if megabytes > 0 => Ok(megabytes),
+        _ => Err(format!("{} is not a positive number of megabytes", s)),
+    }
 }
 
 fn main() -> Result<()> {
@@ -52,17 +240,108 @@ fn main() -> Result<()> {
         .with_context(|| format!("Cannot set up SimpleLogger"))?;
 
     if args.query {
-        Equalizer::query();
-        process::exit(1);
+        #[cfg(feature = "capture")]
+        {
+            Equalizer::query();
+            process::exit(1);
+        }
+        #[cfg(not(feature = "capture"))]
+        return Err(anyhow::anyhow!(
+            "There are no devices to list, rustalizer was built without the capture feature"
+        ));
+    }
+
+    if let Some(Command::Analyze {
+        input,
+        format,
+        output_file,
+        spectrum,
+    }) = args.command.as_ref()
+    {
+        return analyze::run(
+            input,
+            output_file.as_deref(),
+            *format,
+            spectrum.settings(),
+            spectrum.pipeline.as_ref(),
+        )
+        .with_context(|| format!("Cannot analyze {}", input.display()));
+    }
+
+    // the source options only given on their own
+    let source = match args.source.clone() {
+        Source::Generator { signal, .. } => Source::Generator {
+            signal,
+            level: args.level,
+        },
+        Source::Pipe { path, .. } => Source::Pipe {
+            path,
+            format: args.format,
+        },
+        Source::Rtp { address, .. } => Source::Rtp {
+            address,
+            payload: args.payload,
+            jitter: args.jitter,
+        },
+        Source::Device => Source::Device,
+    };
+
+    if let Some(Command::Send { destination }) = args.command.as_ref() {
+        return send::run(
+            destination,
+            &source,
+            args.payload,
+            args.channels as usize,
+            args.rate,
+        )
+        .with_context(|| format!("Cannot send to {}", destination));
+    }
+
+    if let Some(Command::Replay { input }) = args.command.as_ref() {
+        let session = Session::load(input)
+            .with_context(|| format!("Cannot load the session {}", input.display()))?;
+        return replay(&args.app_mode, Player::new(session));
     }
 
+    if let Some(Command::Report {
+        input: Some(input),
+        segment,
+        overlap,
+        window,
+        output,
+        ..
+    }) = args.command.as_ref()
+    {
+        let options = report::Options {
+            segment: *segment,
+            overlap: *overlap,
+            window: *window,
+        };
+        let (interleaved, channels, sample_rate) = equalizer::wav::read_interleaved(input)
+            .with_context(|| format!("Cannot read {}", input.display()))?;
+        return report::run(&interleaved, channels, sample_rate, options, output)
+            .context("Cannot write the report");
+    }
+
+    let settings = args.spectrum.settings();
+    settings
+        .validate()
+        .map_err(errors::Error::Config)
+        .context("Invalid spectrum settings")?;
+
     // start processing backend here
-    // TODO: depending on the option, change the source
     let host_name_copy = args.host_name.clone(); // TODO: must I do this dance?
     let device_name_copy = args.device_name.clone();
-    let bins_copy = args.bins.clone();
     let equalizer = Rc::new(RefCell::new(
-        Equalizer::new(&device_name_copy, &host_name_copy, bins_copy).with_context(|| {
+        Equalizer::new(
+            &device_name_copy,
+            &host_name_copy,
+            &source,
+            settings,
+            args.channels,
+            args.rate,
+        )
+        .with_context(|| {
             format!(
                 "Cannot create Equalizer backend for host: {} and device: {}",
                 device_name_copy.unwrap_or("Default".to_string()),
@@ -71,10 +350,126 @@ fn main() -> Result<()> {
         })?,
     ));
 
-    match args.app_mode.as_str() {
+    if let Ok(mut tuner) = equalizer.borrow().tuner().lock() {
+        tuner.set_reference(args.a4);
+        tuner.set_temperament(args.temperament);
+    }
+    if let Ok(mut chroma) = equalizer.borrow().chroma().lock() {
+        chroma.set_reference(args.a4);
+    }
+    for filter in args.filters.iter() {
+        equalizer.borrow().add_filter(*filter);
+    }
+    if let Some(bands) = args.graphic_eq {
+        equalizer
+            .borrow()
+            .set_graphic_eq(bands)
+            .context("Cannot set up the graphic equalizer")?;
+    }
+    if let Some(ir) = args.ir.as_ref() {
+        equalizer
+            .borrow()
+            .set_impulse_response(ir, args.ir_block)
+            .with_context(|| format!("Cannot load the impulse response: {}", ir.display()))?;
+    }
+    equalizer.borrow().set_post_eq(args.post_eq);
+    if let Some(spec) = args.spectrum.pipeline.as_ref() {
+        let equalizer = equalizer.borrow();
+        equalizer
+            .pipeline(spec)
+            .and_then(|pipeline| equalizer.set_pipeline(pipeline))
+            .context("Invalid spectrum pipeline")?;
+    }
+    if let Some(output) = args.output.as_ref() {
+        equalizer
+            .borrow_mut()
+            .set_output(output)
+            .with_context(|| format!("Cannot open output device: {}", output))?;
+    }
+
+    // only the front-ends record, a report capture has no use for the writer thread
+    if args.command.is_none() {
+        let rotation = Rotation {
+            max_bytes: args.record_max_size.map(|mb| mb.saturating_mul(1_000_000)),
+            max_duration: args.record_max_duration.map(Duration::from_secs_f32),
+        };
+        equalizer
+            .borrow_mut()
+            .set_recorder(&args.record_dir, rotation);
+        equalizer.borrow().set_recording(args.record);
+        if args.session {
+            equalizer
+                .borrow()
+                .start_session()
+                .context("Cannot start the session recording")?;
+        }
+    }
+
+    if let Some(Command::Report {
+        duration,
+        segment,
+        overlap,
+        window,
+        output,
+        ..
+    }) = args.command.as_ref()
+    {
+        let options = report::Options {
+            segment: *segment,
+            overlap: *overlap,
+            window: *window,
+        };
+        let (interleaved, sample_rate) = equalizer
+            .borrow()
+            .record(Duration::from_secs_f32(*duration))
+            .context("Cannot capture the input")?;
+        return report::run(
+            &interleaved,
+            args.channels as usize,
+            sample_rate,
+            options,
+            output,
+        )
+        .context("Cannot write the report");
+    }
+
+    live(&args.app_mode, equalizer)
+}
+
+// the front-ends are cargo features, a build without them still analyzes, reports and sends
+#[cfg_attr(not(feature = "terminal"), allow(unused_variables))]
+fn replay(mode: &str, player: Player) -> Result<()> {
+    shutdown::install().context("Cannot handle Ctrl-C and SIGTERM")?;
+    match mode {
+        #[cfg(feature = "gui")]
+        "GUI" => {
+            let application = app::GuiApp::new("MyApp");
+            application.build_replay(player);
+            application.run();
+            Ok(())
+        }
+        #[cfg(not(feature = "gui"))]
+        "GUI" => Err(no_front_end(mode)),
+        #[cfg(feature = "terminal")]
+        _ => {
+            let mut application = tui::ReplayApp::new(player)?;
+            application.run()?;
+            Ok(())
+        }
+        #[cfg(not(feature = "terminal"))]
+        _ => Err(no_front_end(mode)),
+    }
+}
+
+#[cfg_attr(not(feature = "terminal"), allow(unused_variables))]
+fn live(mode: &str, equalizer: Rc<RefCell<Equalizer>>) -> Result<()> {
+    // the front-ends leave on Ctrl-C and SIGTERM as on q, the capture is then stopped below
+    shutdown::install().context("Cannot handle Ctrl-C and SIGTERM")?;
+    match mode {
+        #[cfg(feature = "gui")]
         "GUI" => {
             let application = app::GuiApp::new("MyApp");
-            application.build_ui(equalizer.clone(), args.bins); // move the cloned rc to app closure -> now it also owns it
+            application.build_ui(equalizer.clone()); // move the cloned rc to app closure -> now it also owns it
             equalizer
                 .borrow_mut()
                 .connect()
@@ -84,8 +479,13 @@ fn main() -> Result<()> {
                 .play()
                 .with_context(|| format!("cannot play the audio stream!"))?;
             application.run();
+            drop(application);
+            equalizer.borrow_mut().shutdown()?;
             Ok(())
         }
+        #[cfg(not(feature = "gui"))]
+        "GUI" => Err(no_front_end(mode)),
+        #[cfg(feature = "terminal")]
         "TUI" | _ => {
             equalizer
                 .borrow_mut()
@@ -95,10 +495,22 @@ fn main() -> Result<()> {
                 .borrow()
                 .play()
                 .with_context(|| format!("cannot play the audio stream!"))?;
-            let mut application = tui::TerminalApp::new(equalizer)?;
-            application.run(args.bins)?;
-            // handle TUI stuff
+            let shown = tui::TerminalApp::new(equalizer.clone())
+                .and_then(|mut application| application.run());
+            // stops the capture and joins the threads, also when the front-end failed
+            let stopped = equalizer.borrow_mut().shutdown();
+            shown.and(stopped)?;
             Ok(())
         }
+        #[cfg(not(feature = "terminal"))]
+        _ => Err(no_front_end(mode)),
     }
 }
+
+#[cfg(not(all(feature = "gui", feature = "terminal")))]
+fn no_front_end(mode: &str) -> anyhow::Error {
+    anyhow::anyhow!(
+        "Mode {} is not available, rustalizer was built without its front-end feature",
+        mode
+    )
+}
@@ -0,0 +1,227 @@
+//! long-term spectrum report of a whole file or capture: the Welch density per FFT bin and per
+//! octave band as a CSV table and as an SVG plot
+use crate::equalizer::dsp::downmix;
+use crate::equalizer::dsp::psd::{octave_bands, Band, Welch};
+use crate::equalizer::dsp::window::WindowType;
+use crate::errors::Error;
+use std::fs;
+use std::path::Path;
+
+const FLOOR_DB: f32 = -200.; // for empty bins, keeps the output finite
+const WIDTH: f32 = 800.;
+const HEIGHT: f32 = 400.;
+const MARGIN: f32 = 50.;
+const MIN_FREQ: f32 = 20.;
+
+#[derive(Copy, Clone, Debug)]
+pub struct Options {
+    pub segment: usize,
+    pub overlap: f32,
+    pub window: WindowType,
+}
+
+/// writes `<output>.csv` and `<output>.svg` for the downmix of all channels of the interleaved
+/// samples
+pub fn run(
+    interleaved: &[f32],
+    channels: usize,
+    sample_rate: u32,
+    options: Options,
+    output: &Path,
+) -> Result<(), Error> {
+    let mut welch = Welch::new(
+        options.segment,
+        options.overlap,
+        options.window,
+        sample_rate,
+    )
+    .map_err(Error::Config)?;
+    welch.feed(&downmix(interleaved, channels));
+    if welch.segments() == 0 {
+        return Err(Error::Config(format!(
+            "The input is shorter than one segment of {} samples",
+            options.segment
+        )));
+    }
+    let density = welch.density();
+    let bands = octave_bands(&density, welch.resolution());
+
+    let title = format!(
+        "Welch PSD, {} segments of {} samples, {:?} window, {:.0}% overlap",
+        welch.segments(),
+        options.segment,
+        options.window,
+        options.overlap * 100.
+    );
+    fs::write(
+        output.with_extension("csv"),
+        csv(&density, welch.resolution(), &bands),
+    )?;
+    fs::write(
+        output.with_extension("svg"),
+        svg(&density, welch.resolution(), &bands, sample_rate, &title),
+    )?;
+    Ok(())
+}
+
+fn db(density: f32) -> f32 {
+    (10. * density.log10()).max(FLOOR_DB)
+}
+
+// one row per FFT bin and per octave band, told apart by the first column
+fn csv(density: &[f32], resolution: f32, bands: &[Band]) -> String {
+    let mut csv = String::from("kind,lower,centre,upper,db_per_hz\n");
+    for (k, d) in density.iter().enumerate() {
+        let centre = k as f32 * resolution;
+        csv.push_str(&format!(
+            "bin,{:.2},{:.2},{:.2},{:.2}\n",
+            (centre - resolution / 2.).max(0.),
+            centre,
+            centre + resolution / 2.,
+            db(*d)
+        ));
+    }
+    for band in bands {
+        csv.push_str(&format!(
+            "octave,{:.2},{:.2},{:.2},{:.2}\n",
+            band.lower,
+            band.centre,
+            band.upper,
+            db(band.density)
+        ));
+    }
+    csv
+}
+
+// log frequency axis from 20 Hz to Nyquist, the bins as a line and the octave bands as steps
+fn svg(density: &[f32], resolution: f32, bands: &[Band], sample_rate: u32, title: &str) -> String {
+    let nyquist = sample_rate as f32 / 2.;
+    let visible: Vec<(f32, f32)> = density
+        .iter()
+        .enumerate()
+        .map(|(k, d)| (k as f32 * resolution, db(*d)))
+        .filter(|(freq, _)| *freq >= MIN_FREQ)
+        .collect();
+    // whole 10 dB steps around the data, at most 120 dB deep
+    let top = visible
+        .iter()
+        .map(|(_, l)| *l)
+        .fold(FLOOR_DB, f32::max)
+        .max(FLOOR_DB + 10.);
+    let top = (top / 10.).ceil() * 10.;
+    let bottom = visible
+        .iter()
+        .map(|(_, l)| *l)
+        .fold(top, f32::min)
+        .max(top - 120.);
+    let bottom = ((bottom / 10.).floor() * 10.).min(top - 10.);
+
+    let x = |freq: f32| {
+        MARGIN + (WIDTH - 2. * MARGIN) * (freq / MIN_FREQ).log10() / (nyquist / MIN_FREQ).log10()
+    };
+    let y = |level: f32| {
+        let level = level.max(bottom).min(top);
+        MARGIN + (HEIGHT - 2. * MARGIN) * (top - level) / (top - bottom)
+    };
+
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" \
+         font-family=\"sans-serif\" font-size=\"11\">\n",
+        WIDTH, HEIGHT
+    );
+    svg.push_str("<rect width=\"100%\" height=\"100%\" fill=\"white\"/>\n");
+    svg.push_str(&format!(
+        "<text x=\"{}\" y=\"{}\" text-anchor=\"middle\" font-size=\"13\">{}</text>\n",
+        WIDTH / 2.,
+        MARGIN / 2.,
+        title
+    ));
+
+    let mut level = bottom;
+    while level <= top {
+        svg.push_str(&format!(
+            "<line x1=\"{0}\" y1=\"{1:.1}\" x2=\"{2}\" y2=\"{1:.1}\" stroke=\"#ddd\"/>\n\
+             <text x=\"{3}\" y=\"{4:.1}\" text-anchor=\"end\">{5}</text>\n",
+            MARGIN,
+            y(level),
+            WIDTH - MARGIN,
+            MARGIN - 5.,
+            y(level) + 4.,
+            level
+        ));
+        level += 10.;
+    }
+    for band in bands {
+        let px = x(band.centre);
+        svg.push_str(&format!(
+            "<line x1=\"{0:.1}\" y1=\"{1}\" x2=\"{0:.1}\" y2=\"{2}\" stroke=\"#ddd\"/>\n\
+             <text x=\"{0:.1}\" y=\"{3}\" text-anchor=\"middle\">{4}</text>\n",
+            px,
+            MARGIN,
+            HEIGHT - MARGIN,
+            HEIGHT - MARGIN + 15.,
+            band.centre
+        ));
+    }
+    svg.push_str(&format!(
+        "<text x=\"{}\" y=\"{}\" text-anchor=\"middle\">Hz</text>\n\
+         <text x=\"15\" y=\"{}\" transform=\"rotate(-90 15 {2})\" text-anchor=\"middle\">dB/Hz</text>\n",
+        WIDTH / 2.,
+        HEIGHT - 10.,
+        HEIGHT / 2.
+    ));
+
+    let points: Vec<String> = visible
+        .iter()
+        .map(|(freq, level)| format!("{:.1},{:.1}", x(*freq), y(*level)))
+        .collect();
+    svg.push_str(&format!(
+        "<polyline fill=\"none\" stroke=\"steelblue\" points=\"{}\"/>\n",
+        points.join(" ")
+    ));
+    for band in bands {
+        svg.push_str(&format!(
+            "<line x1=\"{:.1}\" y1=\"{2:.1}\" x2=\"{:.1}\" y2=\"{2:.1}\" stroke=\"firebrick\" \
+             stroke-width=\"2\"/>\n",
+            x(band.lower.max(MIN_FREQ)),
+            x(band.upper.min(nyquist)),
+            y(db(band.density))
+        ));
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bands() -> Vec<Band> {
+        vec![Band {
+            lower: 707.1,
+            centre: 1000.,
+            upper: 1414.2,
+            density: 1e-3,
+        }]
+    }
+
+    #[test]
+    fn csv_has_bins_and_octaves() {
+        let csv = csv(&[1e-3, 0.], 10., &bands());
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[1], "bin,0.00,0.00,5.00,-30.00");
+        assert_eq!(lines[2], "bin,5.00,10.00,15.00,-200.00");
+        assert_eq!(lines[3], "octave,707.10,1000.00,1414.20,-30.00");
+    }
+
+    #[test]
+    fn svg_plots_both() {
+        let density: Vec<f32> = (0..1025).map(|k| 1e-4 / (1. + k as f32)).collect();
+        let svg = svg(&density, 21.5, &bands(), 44100, "test");
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.trim_end().ends_with("</svg>"));
+        assert_eq!(svg.matches("<polyline").count(), 1);
+        assert!(svg.contains("firebrick"));
+    }
+}
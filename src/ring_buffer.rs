@@ -1,5 +1,16 @@
+//! fixed capacity FIFO between the threads, push fails when it is full and pop when it is empty;
+//! an [`overwriting`](RingBuffer::overwriting) one drops its oldest element instead, which makes
+//! it a sliding window over the newest ones
+//!
+//! [`RingBuffer`] needs `&mut self` and so a lock when it is shared; [`RingBuffer::spsc`] splits
+//! one into a [`Producer`] and a [`Consumer`] that work from two threads without any, which is
+//! what the audio callbacks use
+
 use crate::errors::{BufferOp, Error};
+use std::cell::UnsafeCell;
 use std::num::Wrapping;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 
 // For now the ring buffer operates on a vec -> TODO: convert to a heap
 // contigous memory
@@ -7,9 +18,14 @@ pub struct RingBuffer<T> {
     data: Vec<T>,
     read: usize,
     write: usize,
+    overwrite: bool, // a push onto a full buffer drops the oldest element rather than failing
 }
 
+/// the elements from the oldest to the newest
+pub type Iter<'a, T> = std::iter::Chain<std::slice::Iter<'a, T>, std::slice::Iter<'a, T>>;
+
 impl<T: Default + Clone> RingBuffer<T> {
+    /// capacity must be a power of two
     pub fn new(capacity: usize) -> RingBuffer<T> {
         assert!(
             is_power_of_two(capacity),
@@ -19,11 +35,24 @@ impl<T: Default + Clone> RingBuffer<T> {
             data: vec![T::default(); capacity],
             read: 0,
             write: 0,
+            overwrite: false,
+        }
+    }
+
+    /// a buffer whose push never fails, when full it makes room by dropping the oldest element;
+    /// capacity must be a power of two
+    pub fn overwriting(capacity: usize) -> RingBuffer<T> {
+        RingBuffer {
+            overwrite: true,
+            ..RingBuffer::new(capacity)
         }
     }
 
     pub fn push(&mut self, val: T) -> Result<(), Error> {
-        if self.full() {
+        if self.overwrite {
+            self.push_overwrite(val);
+            Ok(())
+        } else if self.full() {
             Err(Error::BufferOperation(BufferOp::Push))
         } else {
             //self.write += 1;
@@ -38,6 +67,15 @@ impl<T: Default + Clone> RingBuffer<T> {
         }
     }
 
+    /// pushes whatever the mode, returns the oldest element when it had to make room for val
+    pub fn push_overwrite(&mut self, val: T) -> Option<T> {
+        let dropped = if self.full() { self.pop().ok() } else { None };
+        self.write = self.write.wrapping_add(1);
+        let idx = self.mask(self.write);
+        self.data[idx] = val;
+        dropped
+    }
+
     pub fn pop(&mut self) -> Result<T, Error> {
         if self.empty() {
             Err(Error::BufferOperation(BufferOp::Pop))
@@ -53,6 +91,7 @@ impl<T: Default + Clone> RingBuffer<T> {
         }
     }
 
+    /// the oldest element, without removing it
     pub fn top(&self) -> Result<T, Error> {
         if self.empty() {
             Err(Error::BufferOperation(BufferOp::Pop))
@@ -68,6 +107,45 @@ impl<T: Default + Clone> RingBuffer<T> {
         }
     }
 
+    /// the newest element, without removing it
+    pub fn last(&self) -> Option<&T> {
+        if self.empty() {
+            None
+        } else {
+            Some(&self.data[self.mask(self.write)])
+        }
+    }
+
+    pub fn iter(&self) -> Iter<'_, T> {
+        self.peek_last(self.size())
+    }
+
+    /// the newest n elements or all of them when there are fewer, from the oldest to the newest
+    pub fn peek_last(&self, n: usize) -> Iter<'_, T> {
+        let (first, second) = self.last_slices(n);
+        first.iter().chain(second.iter())
+    }
+
+    /// the contents from the oldest to the newest, in two parts when they wrap around the end of
+    /// the storage
+    pub fn as_slices(&self) -> (&[T], &[T]) {
+        self.last_slices(self.size())
+    }
+
+    /// moves the contents to the start of the storage so that one slice holds all of them
+    pub fn make_contiguous(&mut self) -> &[T] {
+        let (size, start) = (self.size(), self.mask(self.read.wrapping_add(1)));
+        self.data.rotate_left(start);
+        // the oldest element is at mask(read + 1), so index 0 now
+        self.read = self.data.len() - 1;
+        self.write = self.read.wrapping_add(size);
+        &self.data[..size]
+    }
+
+    pub fn clear(&mut self) {
+        while self.pop().is_ok() {}
+    }
+
     pub fn full(&self) -> bool {
         self.size() == self.data.capacity()
     }
@@ -83,9 +161,196 @@ impl<T: Default + Clone> RingBuffer<T> {
     fn mask(&self, val: usize) -> usize {
         val & (self.data.capacity() - 1)
     }
+
+    fn last_slices(&self, n: usize) -> (&[T], &[T]) {
+        let n = n.min(self.size());
+        let start = self.mask(self.write.wrapping_sub(n).wrapping_add(1));
+        if start + n <= self.data.len() {
+            (&self.data[start..start + n], &[])
+        } else {
+            let (front, back) = self.data.split_at(start);
+            (back, &front[..start + n - self.data.len()])
+        }
+    }
 }
 
-// cannot be 0 or non-power of two
+impl<'a, T: Default + Clone> IntoIterator for &'a RingBuffer<T> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+impl<T: Default + Send> RingBuffer<T> {
+    /// the lock-free variant for one producer and one consumer thread, capacity must be a power
+    /// of two
+    pub fn spsc(capacity: usize) -> (Producer<T>, Consumer<T>) {
+        assert!(
+            is_power_of_two(capacity),
+            "Capacity of the RingBuffer must be a power of two!"
+        );
+        let shared = Arc::new(Shared {
+            data: (0..capacity)
+                .map(|_| UnsafeCell::new(T::default()))
+                .collect(),
+            read: CachePadded(AtomicUsize::new(0)),
+            write: CachePadded(AtomicUsize::new(0)),
+        });
+        (
+            Producer {
+                shared: shared.clone(),
+                write: 0,
+                read: 0,
+            },
+            Consumer {
+                shared,
+                read: 0,
+                write: 0,
+            },
+        )
+    }
+}
+
+// keeps the two indices on their own cache lines, so that the producer and the consumer do not
+// invalidate each other's line on every update
+#[repr(align(64))]
+struct CachePadded<T>(T);
+
+struct Shared<T> {
+    data: Box<[UnsafeCell<T>]>,
+    read: CachePadded<AtomicUsize>, // elements popped so far, wrapping
+    write: CachePadded<AtomicUsize>, // elements pushed so far, wrapping
+}
+
+// SAFETY: a slot is only touched by the producer while it is free and by the consumer while it
+// is filled; the Release store of an index after the access and the Acquire load on the other
+// side hand each slot over between the two threads
+unsafe impl<T: Send> Sync for Shared<T> {}
+
+impl<T> Shared<T> {
+    fn slot(&self, index: usize) -> *mut T {
+        self.data[index & (self.data.len() - 1)].get()
+    }
+}
+
+/// the writing half of [`RingBuffer::spsc`], never blocks nor allocates
+pub struct Producer<T> {
+    shared: Arc<Shared<T>>,
+    write: usize, // own index, only this side changes it
+    read: usize,  // last seen index of the consumer, refreshed when it leaves too little room
+}
+
+/// the reading half of [`RingBuffer::spsc`], never blocks nor allocates
+pub struct Consumer<T> {
+    shared: Arc<Shared<T>>,
+    read: usize,
+    write: usize, // last seen index of the producer, refreshed when it shows too few elements
+}
+
+impl<T> Producer<T> {
+    pub fn push(&mut self, val: T) -> Result<(), Error> {
+        if self.room(1) == 0 {
+            return Err(Error::BufferOperation(BufferOp::Push));
+        }
+        // SAFETY: the slot is free, the consumer does not read it until write is published
+        unsafe { *self.shared.slot(self.write) = val };
+        self.publish(1);
+        Ok(())
+    }
+
+    /// room left, at least what the next pushes can rely on
+    pub fn free(&mut self) -> usize {
+        self.room(self.capacity())
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.shared.data.len()
+    }
+
+    // only asks the consumer when the last seen index leaves less than wanted
+    fn room(&mut self, wanted: usize) -> usize {
+        let capacity = self.capacity();
+        if capacity - self.write.wrapping_sub(self.read) < wanted {
+            self.read = self.shared.read.0.load(Ordering::Acquire);
+        }
+        capacity - self.write.wrapping_sub(self.read)
+    }
+
+    fn publish(&mut self, count: usize) {
+        self.write = self.write.wrapping_add(count);
+        self.shared.write.0.store(self.write, Ordering::Release);
+    }
+}
+
+impl<T: Copy> Producer<T> {
+    /// pushes as much of vals as fits, returns how much that was
+    pub fn push_slice(&mut self, vals: &[T]) -> usize {
+        let count = vals.len().min(self.room(vals.len()));
+        for (i, val) in vals[..count].iter().enumerate() {
+            // SAFETY: as in push, the count slots after write are free
+            unsafe { *self.shared.slot(self.write.wrapping_add(i)) = *val };
+        }
+        self.publish(count);
+        count
+    }
+}
+
+impl<T: Default> Consumer<T> {
+    pub fn pop(&mut self) -> Result<T, Error> {
+        if self.available(1) == 0 {
+            return Err(Error::BufferOperation(BufferOp::Pop));
+        }
+        // SAFETY: the slot is filled, the producer does not write it until read is published
+        let val = unsafe { std::mem::take(&mut *self.shared.slot(self.read)) };
+        self.release(1);
+        Ok(val)
+    }
+}
+
+impl<T: Copy> Consumer<T> {
+    /// fills the front of out with the oldest elements, returns how many there were
+    pub fn pop_slice(&mut self, out: &mut [T]) -> usize {
+        let count = out.len().min(self.available(out.len()));
+        for (i, val) in out[..count].iter_mut().enumerate() {
+            // SAFETY: as in pop, the count slots after read are filled
+            *val = unsafe { *self.shared.slot(self.read.wrapping_add(i)) };
+        }
+        self.release(count);
+        count
+    }
+}
+
+impl<T> Consumer<T> {
+    /// elements waiting, at least what the next pops can rely on
+    pub fn size(&mut self) -> usize {
+        self.available(self.capacity())
+    }
+
+    pub fn empty(&mut self) -> bool {
+        self.size() == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.shared.data.len()
+    }
+
+    // only asks the producer when the last seen index leaves less than wanted
+    fn available(&mut self, wanted: usize) -> usize {
+        if self.write.wrapping_sub(self.read) < wanted {
+            self.write = self.shared.write.0.load(Ordering::Acquire);
+        }
+        self.write.wrapping_sub(self.read)
+    }
+
+    fn release(&mut self, count: usize) {
+        self.read = self.read.wrapping_add(count);
+        self.shared.read.0.store(self.read, Ordering::Release);
+    }
+}
+
+/// cannot be 0 or non-power of two
 pub fn is_power_of_two(val: usize) -> bool {
     (val & (val - 1)) == 0
 }
@@ -120,4 +385,116 @@ mod tests {
             "The RingBuffer should not allow for pushing when it is full!"
         );
     }
+
+    #[test]
+    fn overwrite_drops_the_oldest() {
+        let mut buf = RingBuffer::<i32>::overwriting(4);
+        for val in 0..6 {
+            assert!(buf.push(val).is_ok());
+        }
+        assert_eq!(buf.push_overwrite(6), Some(2));
+        assert_eq!(buf.iter().copied().collect::<Vec<_>>(), [3, 4, 5, 6]);
+        assert_eq!(buf.last(), Some(&6));
+        assert_eq!(buf.pop().unwrap(), 3);
+    }
+
+    #[test]
+    fn windows_across_the_end() {
+        let mut buf = RingBuffer::<i32>::overwriting(8);
+        for val in 0..11 {
+            buf.push(val).unwrap();
+        }
+        assert_eq!(buf.peek_last(2).copied().collect::<Vec<_>>(), [9, 10]);
+        assert_eq!(buf.peek_last(20).count(), 8);
+        let (first, second) = buf.as_slices();
+        assert_eq!((first, second), (&[3, 4, 5, 6][..], &[7, 8, 9, 10][..]));
+        assert_eq!(buf.make_contiguous(), [3, 4, 5, 6, 7, 8, 9, 10]);
+        assert_eq!(buf.as_slices().1.len(), 0);
+        buf.push(11).unwrap();
+        assert_eq!((&buf).into_iter().next(), Some(&4));
+        buf.clear();
+        assert!(buf.empty() && buf.last().is_none());
+    }
+
+    #[test]
+    fn spsc_wraps_around() {
+        let (mut producer, mut consumer) = RingBuffer::<i32>::spsc(4);
+        assert!(consumer.pop().is_err());
+        assert_eq!(producer.push_slice(&[1, 2, 3]), 3);
+        assert_eq!(consumer.pop().unwrap(), 1);
+        assert_eq!(producer.push_slice(&[4, 5, 6]), 2);
+        assert!(producer.push(6).is_err());
+        let mut out = [0; 8];
+        assert_eq!(consumer.pop_slice(&mut out), 4);
+        assert_eq!(out[..4], [2, 3, 4, 5]);
+        assert!(consumer.empty());
+    }
+
+    // many rounds of a small buffer between two threads, any race shows up as a lost, repeated
+    // or reordered element
+    #[test]
+    fn spsc_stress() {
+        const COUNT: u64 = 200_000;
+        let (mut producer, mut consumer) = RingBuffer::<u64>::spsc(64);
+        let writer = std::thread::spawn(move || {
+            let mut next = 0;
+            let mut block = [0; 37];
+            while next < COUNT {
+                let len = (next % 37 + 1).min(COUNT - next) as usize;
+                for (i, val) in block[..len].iter_mut().enumerate() {
+                    *val = next + i as u64;
+                }
+                match producer.push_slice(&block[..len]) {
+                    0 => std::thread::yield_now(),
+                    count => next += count as u64,
+                }
+            }
+        });
+        let (mut next, mut bulk) = (0, false);
+        let mut out = [0; 23];
+        while next < COUNT {
+            bulk = !bulk;
+            let count = if bulk {
+                consumer.pop_slice(&mut out)
+            } else {
+                consumer.pop().map(|val| out[0] = val).map_or(0, |_| 1)
+            };
+            if count == 0 {
+                std::thread::yield_now();
+            }
+            for val in out[..count].iter() {
+                assert_eq!(*val, next);
+                next += 1;
+            }
+        }
+        writer.join().unwrap();
+        assert!(consumer.empty());
+    }
+
+    // values with heap storage make use-after-free and double drops visible to the allocator
+    #[test]
+    fn spsc_moves_owned_values() {
+        let (mut producer, mut consumer) = RingBuffer::<String>::spsc(8);
+        let writer = std::thread::spawn(move || {
+            for i in 0..100_000 {
+                while producer.push(i.to_string()).is_err() {
+                    std::thread::yield_now();
+                }
+            }
+            // left in the buffer, dropped with it
+            while producer.push("left".to_string()).is_err() {
+                std::thread::yield_now();
+            }
+        });
+        for i in 0..100_000 {
+            loop {
+                if let Ok(val) = consumer.pop() {
+                    assert_eq!(val, i.to_string());
+                    break;
+                }
+                std::thread::yield_now();
+            }
+        }
+        writer.join().unwrap();
+    }
 }
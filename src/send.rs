@@ -0,0 +1,66 @@
+//! the sending end of an RTP input, streams a generated signal or piped PCM to another
+//! rustalizer, or to itself over localhost
+use rustalizer::equalizer::generator::Generator;
+use rustalizer::equalizer::pcm::{self, PcmReader};
+use rustalizer::equalizer::rtp::{Payload, Sender};
+use rustalizer::equalizer::source::Source;
+use rustalizer::errors::Error;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const BLOCK: usize = 480; // frames per send, 10 ms at 48 kHz
+
+/// sends in real time until the piped input ends, a generator goes on until interrupted
+pub fn run(
+    destination: &str,
+    source: &Source,
+    payload: Payload,
+    channels: usize,
+    sample_rate: u32,
+) -> Result<(), Error> {
+    let channels = channels.max(1);
+    let blocks: Box<dyn Iterator<Item = Vec<f32>>> = match source {
+        Source::Generator { signal, level } => {
+            let mut generator = Generator::new(signal.clone(), sample_rate, *level);
+            Box::new(std::iter::repeat_with(move || {
+                let mut block = vec![0.; BLOCK * channels];
+                generator.fill(&mut block, channels);
+                block
+            }))
+        }
+        Source::Pipe { path, format } => {
+            let mut reader = PcmReader::new(pcm::open(path.as_deref())?, *format, channels);
+            Box::new(std::iter::from_fn(move || loop {
+                match reader.poll(pcm::POLL) {
+                    // waiting for the writer
+                    Ok(Some(samples)) if samples.is_empty() => continue,
+                    Ok(samples) => return samples,
+                    Err(err) => {
+                        error!("Could not read the PCM stream: {}", err);
+                        return None;
+                    }
+                }
+            }))
+        }
+        Source::Device | Source::Rtp { .. } => {
+            return Err(Error::Config(String::from(
+                "send needs a gen:<signal>, stdin or pipe:<path> source",
+            )))
+        }
+    };
+
+    let mut sender = Sender::new(destination, payload, channels, sample_rate)?;
+    info!("Sending RTP to {}", destination);
+    let start = Instant::now();
+    let mut sent = 0u64; // frames
+    for block in blocks {
+        sender.send(&block)?;
+        sent += (block.len() / channels) as u64;
+        // a pipe can deliver faster than real time, keep to the clock
+        let due = start + Duration::from_secs_f64(sent as f64 / f64::from(sample_rate));
+        if let Some(wait) = due.checked_duration_since(Instant::now()) {
+            thread::sleep(wait);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,22 @@
+//! orderly exit on Ctrl-C and SIGTERM: the handler only raises a flag, the front-ends check it
+//! between frames and leave the way `q` does, so that the streams are stopped, the threads joined
+//! and the terminal restored before the process ends
+
+use crate::errors::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// can only be installed once per process
+pub fn install() -> Result<(), Error> {
+    ctrlc::set_handler(request)?;
+    Ok(())
+}
+
+pub fn request() {
+    REQUESTED.store(true, Ordering::Relaxed);
+}
+
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::Relaxed)
+}
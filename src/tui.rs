@@ -1,52 +1,183 @@
+mod replay;
+
+pub use replay::ReplayApp;
+
+use crate::equalizer::device::DeviceStatus;
+use crate::equalizer::dsp::beat::{BeatEvent, Tempo};
+use crate::equalizer::dsp::chroma::Chromagram;
+use crate::equalizer::dsp::graphic::MAX_GAIN;
+use crate::equalizer::dsp::queue::LagPolicy;
+use crate::equalizer::dsp::scope::TriggerEdge;
+use crate::equalizer::dsp::spectrum::{band_label, Settings, SpectrumFrame};
+use crate::equalizer::dsp::stereo::StereoImage;
+use crate::equalizer::dsp::tuner::{Reading, Temperament, NOTE_NAMES};
+use crate::equalizer::dsp::{display_level, FlowStats};
+use crate::equalizer::recorder::RecordingStatus;
+use crate::equalizer::rtp::RtpStats;
 use crate::equalizer::Equalizer;
 use crate::errors::Error;
-use crate::ring_buffer::RingBuffer;
+use crate::shutdown;
 
 use crossterm::{
-    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode},
+    event::{
+        self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent, KeyModifiers,
+    },
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
 use std::{
     cell::RefCell,
-    io::{stdout, Write},
+    io::{stdout, Stdout, Write},
     rc::Rc,
-    sync::mpsc,
+    sync::atomic::{AtomicBool, Ordering},
+    sync::{mpsc, Arc},
     thread,
     time::{Duration, Instant},
 };
 use tui::{
     backend::CrosstermBackend,
-    layout::{Direction, Layout},
+    layout::{Alignment, Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style},
-    widgets::{BarChart, Block, Borders},
+    symbols,
+    text::{Span, Spans},
+    widgets::canvas::{Canvas, Line, Points},
+    widgets::{BarChart, Block, Borders, Paragraph},
     Frame, Terminal,
 };
 
 const TICK_RATE: u64 = 100;
+const SCOPE_POINTS: usize = 1024; // more is not visible even with braille
+const TRIGGER_STEP: f32 = 0.05;
+const EQ_STEP: f32 = 1.; // dB
 
-const NUMERIC_FREQS: [&str; 32] = [
-    "1", "2", "3", "4", "5", "6", "7", "8", "9", "10", "11", "12", "13", "14", "15", "16", "17",
-    "18", "19", "20", "21", "22", "23", "24", "25", "26", "27", "28", "29", "30", "31", "32",
-];
-
-const REGULAR_FREQS: [&str; 31] = [
-    "20 Hz", "25 Hz", "31 Hz", "40 Hz", "50 Hz", "63 Hz", "80 Hz", "100 Hz", "125 Hz", "160 Hz",
-    "200 Hz", "250 Hz", "315 Hz", "400 Hz", "500 Hz", "630 Hz", "800 Hz", "1 kHz", "1.2 kHz",
-    "1.6 kHz", "2 kHz", "2.5 kHz", "3.1 kHz", "4 kHz", "5 kHz", "6.3 kHz", "8 kHz", "10 kHz",
-    "12 kHz", "16 kHz", "20 kHz",
-];
+// what the spectrum controls step through, None for third octaves and no smoothing
+const BIN_STEPS: [Option<usize>; 4] = [None, Some(10), Some(20), Some(30)];
+const SMOOTHING_STEPS: [Option<f32>; 4] = [None, Some(0.5), Some(0.8), Some(0.9)];
+const FFT_SIZES: (usize, usize) = (256, 16384);
 
 enum IEvent<E> {
     Input(E),
     Tick,
+    Failed(Error), // the terminal could not be read, the key reader stopped
+}
+
+// reads the keys on its own thread and ticks every TICK_RATE; stopped and joined when dropped
+struct Events {
+    receiver: mpsc::Receiver<IEvent<KeyEvent>>,
+    running: Arc<AtomicBool>,
+    reader: Option<thread::JoinHandle<()>>,
+}
+
+impl Events {
+    fn start() -> Events {
+        let (sender, receiver) = mpsc::channel();
+        let running = Arc::new(AtomicBool::new(true));
+        let alive = running.clone();
+        let tick_rate = Duration::from_millis(TICK_RATE);
+        let reader = thread::spawn(move || {
+            let mut last_tick = Instant::now();
+            // poll gives up after a tick at the latest, so the flag is seen in time
+            while alive.load(Ordering::Relaxed) {
+                let timeout = tick_rate
+                    .checked_sub(last_tick.elapsed())
+                    .unwrap_or_default();
+                let event = event::poll(timeout).and_then(|ready| {
+                    if ready {
+                        event::read().map(Some)
+                    } else {
+                        Ok(None)
+                    }
+                });
+                let sent = match event {
+                    Ok(Some(Event::Key(key))) => sender.send(IEvent::Input(key)),
+                    Ok(_) => Ok(()),
+                    Err(err) => {
+                        let _ = sender.send(IEvent::Failed(err.into()));
+                        break;
+                    }
+                };
+                if sent.is_err() {
+                    break;
+                }
+                if last_tick.elapsed() >= tick_rate {
+                    if sender.send(IEvent::Tick).is_err() {
+                        break;
+                    }
+                    last_tick = Instant::now();
+                }
+            }
+        });
+        Events {
+            receiver,
+            running,
+            reader: Some(reader),
+        }
+    }
+
+    fn next(&self) -> Result<IEvent<KeyEvent>, Error> {
+        Ok(self.receiver.recv()?)
+    }
+}
+
+impl Drop for Events {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(reader) = self.reader.take() {
+            if reader.join().is_err() {
+                error!("The key reader thread panicked");
+            }
+        }
+    }
+}
+
+// q, or Ctrl-C which raw mode delivers as a key rather than as a signal
+fn quits(key: &KeyEvent) -> bool {
+    key.code == KeyCode::Char('q')
+        || (key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL))
+}
+
+// leaves the terminal the way it was found, after an error as well as after a quit
+fn restore(terminal: &mut Terminal<CrosstermBackend<Stdout>>) -> Result<(), Error> {
+    disable_raw_mode()?;
+    execute!(
+        terminal.backend_mut(),
+        LeaveAlternateScreen,
+        DisableMouseCapture
+    )?;
+    terminal.show_cursor()?;
+    Ok(())
+}
+
+// screens switched between with Tab
+#[derive(Copy, Clone, PartialEq)]
+enum View {
+    Spectrum,
+    Scope,
+    Vectorscope,
+    Tuner,
+    Chroma,
+    Equalizer,
+}
+
+impl View {
+    fn next(self) -> View {
+        match self {
+            View::Spectrum => View::Scope,
+            View::Scope => View::Vectorscope,
+            View::Vectorscope => View::Tuner,
+            View::Tuner => View::Chroma,
+            View::Chroma => View::Equalizer,
+            View::Equalizer => View::Spectrum,
+        }
+    }
 }
 
 pub struct TerminalApp {
     terminal: Terminal<tui::backend::CrosstermBackend<std::io::Stdout>>, // TODO: add crossplatform-ness, no function specializations in Rust so have to come up with something else
-    equalizer: Rc<RefCell<Equalizer>>,
-    data: RingBuffer<Vec<usize>>,
     // store the equalizer Rc for receiving data
+    equalizer: Rc<RefCell<Equalizer>>,
+    view: View,
+    eq_band: usize, // graphic EQ band moved with the arrow keys
 }
 
 impl TerminalApp {
@@ -57,93 +188,291 @@ impl TerminalApp {
 
         let backend = CrosstermBackend::new(stdout);
         let terminal = Terminal::new(backend)?;
-        let data = RingBuffer::new(16);
         Ok(TerminalApp {
             terminal,
             equalizer,
-            data,
+            view: View::Spectrum,
+            eq_band: 0,
         })
     }
 
-    pub fn run(&mut self, bins: Option<usize>) -> Result<(), Error> {
-        let mut custom_bins: bool = false;
-        if let Some(bins) = bins {
-            assert!(bins < 31, "There cannot be more than 31 custom bins");
-            custom_bins = true;
-        }
-        let (event_tx, event_rx) = mpsc::channel();
-
-        // spawn the event transmitting thread
-        let tick_rate = Duration::from_millis(TICK_RATE);
-
-        // poll for events, if none just send a tick event
-        thread::spawn(move || {
-            let mut last_tick = Instant::now();
-            loop {
-                let timeout = tick_rate
-                    .checked_sub(last_tick.elapsed())
-                    .unwrap_or_else(|| Duration::from_secs(0));
-                if event::poll(timeout).unwrap() {
-                    if let Event::Key(key) = event::read().unwrap() {
-                        event_tx.send(IEvent::Input(key)).unwrap();
-                    }
-                }
-
-                if last_tick.elapsed() >= tick_rate {
-                    event_tx.send(IEvent::Tick).unwrap();
-                    last_tick = Instant::now();
-                }
-            }
-        });
+    /// until q, Ctrl-C, SIGTERM or an error of the analysis; the terminal is restored either way
+    pub fn run(&mut self) -> Result<(), Error> {
+        let events = Events::start();
+        let result = self.show(&events);
+        drop(events);
+        result.and(restore(&mut self.terminal))
+    }
 
+    fn show(&mut self, events: &Events) -> Result<(), Error> {
         // prepare current batch to show
-        let mut last_batch = Vec::new();
+        let mut last_batch = None;
+        let mut last_equalized = None; // only while the post-EQ spectrum is shown
+        let mut last_waveform = Vec::new();
+        let mut last_image = StereoImage {
+            points: Vec::new(),
+            correlation: 0.,
+        };
+        let mut last_pitch = None;
+        let mut last_chromagram = None;
+        let mut last_tempo = None;
+        let mut last_eq_bands = None;
+        // set when a beat or an onset arrived since the previous tick, makes the status line blink
+        let (mut beat, mut onset) = (false, false);
+        // only whether something arrived since the previous tick matters, a few events do
+        let beats = self
+            .equalizer
+            .borrow()
+            .subscribe_beats(16, LagPolicy::DropOldest);
+        let scope = self.equalizer.borrow().scope();
+        let tuner = self.equalizer.borrow().tuner();
 
         loop {
             // paint last frame
-            let labels: Vec<(&str, u64)> = last_batch
-                .clone()
+            let bars = last_batch.as_ref().map(spectrum_bars).unwrap_or_default();
+            let labels: Vec<(&str, u64)> = bars
                 .iter()
-                .enumerate()
-                .map(|(index, val)| {
-                    let label_str;
-                    if custom_bins {
-                        label_str = NUMERIC_FREQS[index];
-                    } else {
-                        label_str = REGULAR_FREQS[index];
-                    }
-                    let label_val = *val as u64;
-                    (label_str, label_val)
-                })
+                .map(|(label, level)| (label.as_str(), *level))
                 .collect();
-            self.terminal.draw(|f| draw(f, &labels))?;
+            let equalized_bars = last_equalized.as_ref().map(spectrum_bars);
+            let equalized_labels: Option<Vec<(&str, u64)>> = equalized_bars.as_ref().map(|bars| {
+                bars.iter()
+                    .map(|(label, level)| (label.as_str(), *level))
+                    .collect()
+            });
+            let scope_title = match scope.lock() {
+                Ok(scope) => {
+                    scope_title(scope.timebase(), scope.edge(), scope.level(), scope.hold())
+                }
+                Err(_) => String::from("Scope"),
+            };
+            let (reference, temperament) = match tuner.lock() {
+                Ok(tuner) => (tuner.reference(), tuner.temperament()),
+                Err(_) => (440., Temperament::Equal),
+            };
+            let spectrum_title = format!(
+                "Rustalizer | {}{}{}",
+                if equalized_labels.is_some() {
+                    "pre-EQ | "
+                } else {
+                    ""
+                },
+                spectrum_settings(&self.equalizer.borrow().settings()),
+                if self.equalizer.borrow().session_recording() {
+                    " | recording session"
+                } else {
+                    ""
+                }
+            );
+            let recording = self.equalizer.borrow().get_recording_status();
+            let device = self.equalizer.borrow().get_device_status();
+            let status = Status {
+                tempo: last_tempo.as_ref(),
+                beat,
+                onset,
+                latency: self.equalizer.borrow().latency(),
+                recording: recording.as_ref(),
+                ended: self.equalizer.borrow().ended(),
+                network: self.equalizer.borrow().get_network_status(),
+                flow: self.equalizer.borrow().get_flow_stats(),
+                device: device.as_ref(),
+            };
+            let view = self.view;
+            let eq_band = self.eq_band;
+            self.terminal.draw(|f| {
+                // the view on top, one status line below it
+                let chunks = Layout::default()
+                    .direction(Direction::Vertical)
+                    .constraints([Constraint::Min(0), Constraint::Length(1)].as_ref())
+                    .split(f.size());
+                match view {
+                    View::Spectrum => match &equalized_labels {
+                        // the captured spectrum above the equalized one, band for band
+                        Some(equalized) => {
+                            let halves = Layout::default()
+                                .direction(Direction::Vertical)
+                                .constraints(
+                                    [Constraint::Percentage(50), Constraint::Percentage(50)]
+                                        .as_ref(),
+                                )
+                                .split(chunks[0]);
+                            draw(f, halves[0], &labels, &spectrum_title);
+                            draw(f, halves[1], equalized, "post-EQ");
+                        }
+                        None => draw(f, chunks[0], &labels, &spectrum_title),
+                    },
+                    View::Scope => draw_scope(f, chunks[0], &last_waveform, &scope_title),
+                    View::Vectorscope => draw_vectorscope(f, chunks[0], &last_image),
+                    View::Tuner => {
+                        draw_tuner(f, chunks[0], last_pitch.as_ref(), reference, temperament)
+                    }
+                    View::Chroma => draw_chroma(f, chunks[0], last_chromagram.as_ref()),
+                    View::Equalizer => {
+                        draw_equalizer(f, chunks[0], last_eq_bands.as_deref(), eq_band)
+                    }
+                }
+                draw_status(f, chunks[1], &status);
+            })?;
 
-            match event_rx.recv()? {
+            match events.next()? {
+                IEvent::Input(event) if quits(&event) => break,
                 IEvent::Input(event) => match event.code {
-                    KeyCode::Char('q') => {
-                        disable_raw_mode()?;
-                        execute!(
-                            self.terminal.backend_mut(),
-                            LeaveAlternateScreen,
-                            DisableMouseCapture
-                        )?;
-                        self.terminal.show_cursor()?;
-                        break;
+                    KeyCode::Tab => {
+                        self.view = self.view.next();
+                    }
+                    KeyCode::Char('w') => {
+                        let equalizer = self.equalizer.borrow();
+                        equalizer.set_recording(!equalizer.recording());
+                    }
+                    KeyCode::Char('s') => {
+                        let equalizer = self.equalizer.borrow();
+                        let result = if equalizer.session_recording() {
+                            equalizer.stop_session()
+                        } else {
+                            equalizer.start_session().map(|_| ())
+                        };
+                        if let Err(err) = result {
+                            error!("Session recording failed: {}", err);
+                        }
+                    }
+                    code if self.view == View::Spectrum => {
+                        let equalizer = self.equalizer.borrow();
+                        let settings = equalizer.settings();
+                        let result = match code {
+                            KeyCode::Char('p') => {
+                                equalizer.set_post_eq(!equalizer.post_eq());
+                                Ok(())
+                            }
+                            KeyCode::Char('b') => {
+                                equalizer.set_bins(next_step(&BIN_STEPS, settings.bins))
+                            }
+                            KeyCode::Char('n') => equalizer.set_window(settings.window.next()),
+                            KeyCode::Char('a') => {
+                                equalizer.set_weighting(settings.weighting.next())
+                            }
+                            KeyCode::Char('m') => equalizer
+                                .set_smoothing(next_step(&SMOOTHING_STEPS, settings.smoothing)),
+                            KeyCode::Char('+') if settings.fft_size < FFT_SIZES.1 => {
+                                equalizer.set_fft_size(settings.fft_size * 2)
+                            }
+                            KeyCode::Char('-') if settings.fft_size > FFT_SIZES.0 => {
+                                equalizer.set_fft_size(settings.fft_size / 2)
+                            }
+                            _ => Ok(()),
+                        };
+                        if let Err(err) = result {
+                            error!("Cannot change the spectrum: {}", err);
+                        }
+                    }
+                    code if self.view == View::Scope => {
+                        if let Ok(mut scope) = scope.lock() {
+                            match code {
+                                KeyCode::Char('e') => scope.toggle_edge(),
+                                KeyCode::Char('h') => {
+                                    let hold = scope.hold();
+                                    scope.set_hold(!hold);
+                                }
+                                KeyCode::Up => {
+                                    let level = scope.level();
+                                    scope.set_level(level + TRIGGER_STEP);
+                                }
+                                KeyCode::Down => {
+                                    let level = scope.level();
+                                    scope.set_level(level - TRIGGER_STEP);
+                                }
+                                KeyCode::Left => scope.zoom_in(),
+                                KeyCode::Right => scope.zoom_out(),
+                                _ => {}
+                            }
+                        }
+                    }
+                    code if self.view == View::Equalizer => {
+                        let equalizer = self.equalizer.borrow();
+                        if let Some(bands) = equalizer.get_eq_bands() {
+                            let (_, gain) = bands[self.eq_band.min(bands.len() - 1)];
+                            match code {
+                                KeyCode::Left => self.eq_band = self.eq_band.saturating_sub(1),
+                                KeyCode::Right => {
+                                    self.eq_band = (self.eq_band + 1).min(bands.len() - 1)
+                                }
+                                KeyCode::Up => equalizer.set_eq_gain(self.eq_band, gain + EQ_STEP),
+                                KeyCode::Down => {
+                                    equalizer.set_eq_gain(self.eq_band, gain - EQ_STEP)
+                                }
+                                KeyCode::Char('0') => equalizer.set_eq_gain(self.eq_band, 0.),
+                                KeyCode::Char('r') => equalizer.reset_eq(),
+                                _ => {}
+                            }
+                        }
+                    }
+                    code if self.view == View::Tuner => {
+                        if let Ok(mut tuner) = tuner.lock() {
+                            match code {
+                                KeyCode::Char('t') => {
+                                    let temperament = tuner.temperament();
+                                    tuner.set_temperament(temperament.next());
+                                }
+                                KeyCode::Up => {
+                                    let reference = tuner.reference();
+                                    tuner.set_reference(reference + 1.);
+                                }
+                                KeyCode::Down => {
+                                    let reference = tuner.reference();
+                                    tuner.set_reference(reference - 1.);
+                                }
+                                _ => {}
+                            }
+                        }
                     }
                     _ => {}
                 },
+                IEvent::Failed(err) => return Err(err),
                 IEvent::Tick => {
-                    // obtain new data and update last_batch
+                    if shutdown::requested() {
+                        break;
+                    }
+                    self.equalizer.borrow_mut().supervise();
+                    self.equalizer.borrow().check()?;
+
+                    // the newest spectrum, a lagging renderer skips the ones before it
                     if let Some(new_batch) = self.equalizer.borrow().get_processed_samples() {
-                        self.data.push(new_batch)?;
+                        last_batch = Some(new_batch);
                     }
+                    last_equalized = self.equalizer.borrow().get_equalized_spectrum();
 
-                    match self.data.pop() {
-                        Err(error) => {
-                            debug!("{:?}", error);
+                    if self.view == View::Scope {
+                        if let Some(waveform) = self.equalizer.borrow().get_waveform() {
+                            last_waveform = waveform;
                         }
-                        Ok(replacement) => {
-                            last_batch = replacement;
+                    }
+
+                    if self.view == View::Vectorscope {
+                        if let Some(image) = self.equalizer.borrow().get_stereo_image() {
+                            last_image = image;
+                        }
+                    }
+
+                    if self.view == View::Tuner {
+                        last_pitch = self.equalizer.borrow().get_pitch();
+                    }
+
+                    if self.view == View::Chroma {
+                        last_chromagram = self.equalizer.borrow().get_chromagram();
+                    }
+
+                    if self.view == View::Equalizer {
+                        last_eq_bands = self.equalizer.borrow().get_eq_bands();
+                    }
+
+                    last_tempo = self.equalizer.borrow().get_tempo();
+                    beat = false;
+                    onset = false;
+                    if let Some(beats) = &beats {
+                        while let Some(event) = beats.pop() {
+                            match event {
+                                BeatEvent::Beat { .. } => beat = true,
+                                BeatEvent::Onset { .. } => onset = true,
+                            }
                         }
                     }
                 }
@@ -153,16 +482,482 @@ impl TerminalApp {
     }
 }
 
-pub fn draw<B>(f: &mut Frame<B>, data: &[(&str, u64)])
+// bar heights of a spectrum, labelled with the band centres
+fn spectrum_bars(frame: &SpectrumFrame) -> Vec<(String, u64)> {
+    frame
+        .bands
+        .iter()
+        .zip(frame.magnitudes.iter())
+        .map(|(band, db)| (band_label(band.centre), display_level(*db) as u64))
+        .collect()
+}
+
+pub fn draw<B>(f: &mut Frame<B>, area: Rect, data: &[(&str, u64)], title: &str)
 where
     B: tui::backend::Backend,
 {
     let graph = BarChart::default()
-        .block(Block::default().title("Rustalizer").borders(Borders::ALL))
+        .block(Block::default().title(title).borders(Borders::ALL))
         .bar_width(3)
         .bar_gap(1)
         .bar_style(Style::default().fg(Color::Yellow).bg(Color::Red))
         .value_style(Style::default().fg(Color::Red).add_modifier(Modifier::BOLD))
         .data(data);
-    f.render_widget(graph, f.size()); // can add multiple parallel ones
+    f.render_widget(graph, area); // can add multiple parallel ones
+}
+
+fn spectrum_settings(settings: &Settings) -> String {
+    format!(
+        "{:?} {} | {:?} weighting | {}{}",
+        settings.window,
+        settings.fft_size,
+        settings.weighting,
+        match settings.bins {
+            Some(bins) => format!("{} bins", bins),
+            None => String::from("third octaves"),
+        },
+        match settings.smoothing {
+            Some(factor) => format!(" | smoothing {}", factor),
+            None => String::new(),
+        }
+    )
+}
+
+// the step after the current one, wrapping around; the first one if it is not a step
+fn next_step<T: Copy + PartialEq>(steps: &[T], current: T) -> T {
+    match steps.iter().position(|step| *step == current) {
+        Some(index) => steps[(index + 1) % steps.len()],
+        None => steps[0],
+    }
+}
+
+fn scope_title(timebase: f32, edge: TriggerEdge, level: f32, hold: bool) -> String {
+    let edge = match edge {
+        TriggerEdge::Rising => "rising",
+        TriggerEdge::Falling => "falling",
+    };
+    format!(
+        "Scope | {} ms | {} edge @ {:.2}{}",
+        timebase,
+        edge,
+        level,
+        if hold { " | HOLD" } else { "" }
+    )
+}
+
+pub fn draw_scope<B>(f: &mut Frame<B>, area: Rect, samples: &[f32], title: &str)
+where
+    B: tui::backend::Backend,
+{
+    // decimate so that long timebases do not draw thousands of overlapping lines
+    let step = (samples.len() / SCOPE_POINTS).max(1);
+    let points: Vec<(f64, f64)> = samples
+        .iter()
+        .enumerate()
+        .step_by(step)
+        .map(|(x, y)| (x as f64, f64::from(*y)))
+        .collect();
+    let width = samples.len().max(1) as f64;
+
+    let canvas = Canvas::default()
+        .block(Block::default().title(title).borders(Borders::ALL))
+        .marker(symbols::Marker::Braille)
+        .x_bounds([0., width])
+        .y_bounds([-1., 1.])
+        .paint(|ctx| {
+            ctx.draw(&Line {
+                x1: 0.,
+                y1: 0.,
+                x2: width,
+                y2: 0.,
+                color: Color::DarkGray,
+            });
+            ctx.layer();
+            for pair in points.windows(2) {
+                ctx.draw(&Line {
+                    x1: pair[0].0,
+                    y1: pair[0].1,
+                    x2: pair[1].0,
+                    y2: pair[1].1,
+                    color: Color::Yellow,
+                });
+            }
+        });
+    f.render_widget(canvas, area);
+}
+
+pub fn draw_vectorscope<B>(f: &mut Frame<B>, area: Rect, image: &StereoImage)
+where
+    B: tui::backend::Backend,
+{
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(5), Constraint::Length(3)].as_ref())
+        .split(area);
+
+    let points: Vec<(f64, f64)> = image
+        .points
+        .iter()
+        .map(|(side, mid)| (f64::from(*side), f64::from(*mid)))
+        .collect();
+    let goniometer = Canvas::default()
+        .block(Block::default().title("Vectorscope").borders(Borders::ALL))
+        .marker(symbols::Marker::Braille)
+        .x_bounds([-1., 1.])
+        .y_bounds([-1., 1.])
+        .paint(|ctx| {
+            // L and R axes, the vertical is mono
+            ctx.draw(&Line {
+                x1: -1.,
+                y1: -1.,
+                x2: 1.,
+                y2: 1.,
+                color: Color::DarkGray,
+            });
+            ctx.draw(&Line {
+                x1: -1.,
+                y1: 1.,
+                x2: 1.,
+                y2: -1.,
+                color: Color::DarkGray,
+            });
+            ctx.layer();
+            ctx.draw(&Points {
+                coords: &points,
+                color: Color::Yellow,
+            });
+        });
+    f.render_widget(goniometer, chunks[0]);
+
+    let correlation = f64::from(image.correlation);
+    let title = format!("Correlation {:+.2}", correlation);
+    let meter = Canvas::default()
+        .block(Block::default().title(title.as_str()).borders(Borders::ALL))
+        .marker(symbols::Marker::Braille)
+        .x_bounds([-1., 1.])
+        .y_bounds([0., 1.])
+        .paint(|ctx| {
+            ctx.draw(&Line {
+                x1: 0.,
+                y1: 0.5,
+                x2: correlation,
+                y2: 0.5,
+                color: if correlation < 0. {
+                    Color::Red
+                } else {
+                    Color::Green
+                },
+            });
+        });
+    f.render_widget(meter, chunks[1]);
+}
+
+pub fn draw_tuner<B>(
+    f: &mut Frame<B>,
+    area: Rect,
+    reading: Option<&Reading>,
+    reference: f32,
+    temperament: Temperament,
+) where
+    B: tui::backend::Backend,
+{
+    let block = Block::default()
+        .title(format!(
+            "Tuner | A4 = {} Hz | {}",
+            reference,
+            temperament.name()
+        ))
+        .borders(Borders::ALL);
+    let inner = block.inner(area);
+    f.render_widget(block, area);
+
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints(
+            [
+                Constraint::Percentage(40),
+                Constraint::Length(2),
+                Constraint::Min(3),
+            ]
+            .as_ref(),
+        )
+        .split(inner);
+
+    let (text, cents) = match reading {
+        Some(reading) => (
+            vec![
+                Spans::from(Span::styled(
+                    format!("{}{}", reading.note, reading.octave),
+                    Style::default()
+                        .fg(Color::Yellow)
+                        .add_modifier(Modifier::BOLD),
+                )),
+                Spans::from(format!(
+                    "{:+.1} cents  {:.2} Hz",
+                    reading.cents, reading.frequency
+                )),
+            ],
+            Some(f64::from(reading.cents)),
+        ),
+        None => (vec![Spans::from("--")], None),
+    };
+    let note = Paragraph::new(text).alignment(Alignment::Center);
+    f.render_widget(note, chunks[1]);
+
+    // needle over a -50..+50 cents scale, green when within 5 cents
+    let meter = Canvas::default()
+        .marker(symbols::Marker::Braille)
+        .x_bounds([-50., 50.])
+        .y_bounds([0., 1.])
+        .paint(|ctx| {
+            ctx.draw(&Line {
+                x1: -50.,
+                y1: 0.,
+                x2: 50.,
+                y2: 0.,
+                color: Color::DarkGray,
+            });
+            for tick in (-50..=50).step_by(10) {
+                ctx.draw(&Line {
+                    x1: f64::from(tick),
+                    y1: 0.,
+                    x2: f64::from(tick),
+                    y2: if tick == 0 { 0.6 } else { 0.3 },
+                    color: Color::DarkGray,
+                });
+            }
+            if let Some(cents) = cents {
+                ctx.layer();
+                ctx.draw(&Line {
+                    x1: cents,
+                    y1: 0.,
+                    x2: cents,
+                    y2: 1.,
+                    color: if cents.abs() < 5. {
+                        Color::Green
+                    } else {
+                        Color::Red
+                    },
+                });
+            }
+        });
+    f.render_widget(meter, chunks[2]);
+}
+
+pub fn draw_chroma<B>(f: &mut Frame<B>, area: Rect, chromagram: Option<&Chromagram>)
+where
+    B: tui::backend::Backend,
+{
+    let (data, title): (Vec<(&str, u64)>, String) = match chromagram {
+        Some(chromagram) => (
+            NOTE_NAMES
+                .iter()
+                .zip(chromagram.chroma.iter())
+                .map(|(name, value)| (*name, (value * 100.) as u64))
+                .collect(),
+            match &chromagram.key {
+                Some(key) => format!("Chroma | Key: {} ({:.2})", key.name(), key.confidence),
+                None => String::from("Chroma | Key: --"),
+            },
+        ),
+        None => (Vec::new(), String::from("Chroma")),
+    };
+    let graph = BarChart::default()
+        .block(Block::default().title(title).borders(Borders::ALL))
+        .bar_width(5)
+        .bar_gap(1)
+        .max(100)
+        .bar_style(Style::default().fg(Color::Yellow).bg(Color::Red))
+        .value_style(Style::default().fg(Color::Red).add_modifier(Modifier::BOLD))
+        .data(&data);
+    f.render_widget(graph, area);
+}
+
+/// bars going up or down from the 0 dB line, the selected band is highlighted
+pub fn draw_equalizer<B>(
+    f: &mut Frame<B>,
+    area: Rect,
+    bands: Option<&[(f32, f32)]>,
+    selected: usize,
+) where
+    B: tui::backend::Backend,
+{
+    let bands = match bands {
+        Some(bands) => bands,
+        None => {
+            let text =
+                Paragraph::new("No graphic EQ, start with --graphic-eq 10 or --graphic-eq 31")
+                    .block(Block::default().title("Graphic EQ").borders(Borders::ALL))
+                    .alignment(Alignment::Center);
+            f.render_widget(text, area);
+            return;
+        }
+    };
+    let selected = selected.min(bands.len() - 1);
+    let (centre, gain) = bands[selected];
+    let title = format!(
+        "Graphic EQ | {} | {:+.1} dB",
+        format_frequency(centre),
+        gain
+    );
+
+    let count = bands.len() as f64;
+    let canvas = Canvas::default()
+        .block(Block::default().title(title.as_str()).borders(Borders::ALL))
+        .marker(symbols::Marker::Block)
+        .x_bounds([0., count])
+        .y_bounds([-f64::from(MAX_GAIN), f64::from(MAX_GAIN)])
+        .paint(|ctx| {
+            ctx.draw(&Line {
+                x1: 0.,
+                y1: 0.,
+                x2: count,
+                y2: 0.,
+                color: Color::DarkGray,
+            });
+            ctx.layer();
+            for (band, (_, gain)) in bands.iter().enumerate() {
+                let x = band as f64 + 0.5;
+                ctx.draw(&Line {
+                    x1: x,
+                    y1: 0.,
+                    x2: x,
+                    y2: f64::from(*gain),
+                    color: if band == selected {
+                        Color::Red
+                    } else {
+                        Color::Yellow
+                    },
+                });
+            }
+        });
+    f.render_widget(canvas, area);
+}
+
+fn format_frequency(freq: f32) -> String {
+    if freq >= 1000. {
+        format!("{} kHz", freq / 1000.)
+    } else {
+        format!("{} Hz", freq)
+    }
+}
+
+/// everything the status line shows
+pub struct Status<'a> {
+    pub tempo: Option<&'a Tempo>,
+    pub beat: bool,
+    pub onset: bool,
+    pub latency: f32, // of the FIR filter, in seconds
+    pub recording: Option<&'a RecordingStatus>,
+    pub ended: bool, // the piped input
+    pub network: Option<RtpStats>,
+    pub flow: FlowStats,
+    pub device: Option<&'a DeviceStatus>,
+}
+
+pub fn draw_status<B>(f: &mut Frame<B>, area: Rect, status: &Status)
+where
+    B: tui::backend::Backend,
+{
+    let Status {
+        tempo,
+        beat,
+        onset,
+        latency,
+        recording,
+        ended,
+        network,
+        flow,
+        device,
+    } = *status;
+    let bpm = match tempo.and_then(|t| t.bpm) {
+        Some(bpm) => format!("{:.1} BPM", bpm),
+        None => String::from("--- BPM"),
+    };
+    // beat phase as a bar filling up towards the next beat
+    let filled = tempo.map_or(0, |t| ((t.phase * 8.) as usize).min(8));
+    let bar = format!("[{}{}]", "=".repeat(filled), " ".repeat(8 - filled));
+
+    let mut status = vec![
+        Span::raw(format!(" {} ", bpm)),
+        Span::styled(
+            if beat { " \u{25cf} " } else { "   " },
+            Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+        ),
+        Span::raw(bar),
+        Span::styled(
+            if onset { " onset" } else { "      " },
+            Style::default().fg(Color::Yellow),
+        ),
+    ];
+    if latency > 0. {
+        status.push(Span::raw(format!(
+            " | FIR latency {:.1} ms",
+            latency * 1000.
+        )));
+    }
+    if let Some(file) = recording.and_then(|r| r.file.as_ref()) {
+        status.push(Span::styled(
+            " | \u{25cf} REC ",
+            Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+        ));
+        status.push(Span::raw(
+            file.file_name()
+                .map_or_else(String::new, |name| name.to_string_lossy().into_owned()),
+        ));
+    }
+    if let Some(dropped) = recording.map(|r| r.dropped).filter(|d| *d > 0) {
+        status.push(Span::raw(format!(" ({} samples dropped)", dropped)));
+    }
+    if let Some(network) = network {
+        status.push(Span::raw(format!(
+            " | RTP {} received, {} lost, {} late",
+            network.received, network.lost, network.late
+        )));
+    }
+    if flow.unfiltered_blocks > 0 {
+        status.push(Span::styled(
+            format!(" | {} blocks not equalized", flow.unfiltered_blocks),
+            Style::default().fg(Color::Yellow),
+        ));
+    }
+    if flow.dropped_samples > 0 || flow.dropped_frames > 0 {
+        status.push(Span::styled(
+            format!(
+                " | analysis lagging: {} samples, {} frames dropped",
+                flow.dropped_samples, flow.dropped_frames
+            ),
+            Style::default().fg(Color::Yellow),
+        ));
+    }
+    // the device only shows up when something is off with it
+    match device {
+        Some(lost @ DeviceStatus::Lost { .. }) => status.push(Span::styled(
+            format!(" | {}", lost),
+            Style::default().fg(Color::Red),
+        )),
+        Some(fallback @ DeviceStatus::Connected { fallback: true, .. }) => {
+            status.push(Span::raw(format!(" | {}", fallback)))
+        }
+        _ => {}
+    }
+    if ended {
+        status.push(Span::styled(
+            " | end of stream",
+            Style::default().fg(Color::Yellow),
+        ));
+    }
+    f.render_widget(Paragraph::new(Spans::from(status)), area);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steps_wrap_around() {
+        assert_eq!(next_step(&BIN_STEPS, None), Some(10));
+        assert_eq!(next_step(&BIN_STEPS, Some(30)), None);
+        assert_eq!(next_step(&SMOOTHING_STEPS, Some(0.3)), None);
+    }
 }
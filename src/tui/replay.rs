@@ -0,0 +1,131 @@
+use super::{draw, quits, restore, spectrum_bars, Events, IEvent};
+use crate::equalizer::session::Player;
+use crate::errors::Error;
+use crate::shutdown;
+
+use crossterm::{
+    event::{EnableMouseCapture, KeyCode},
+    execute,
+    terminal::{enable_raw_mode, EnterAlternateScreen},
+};
+use std::{
+    io::{stdout, Write},
+    time::Instant,
+};
+use tui::{
+    backend::CrosstermBackend,
+    layout::{Constraint, Direction, Layout, Rect},
+    text::{Span, Spans},
+    widgets::Paragraph,
+    Frame, Terminal,
+};
+
+const SEEK_STEP: f32 = 5.; // seconds
+
+/// plays a recorded spectrum session, no audio device involved
+pub struct ReplayApp {
+    terminal: Terminal<CrosstermBackend<std::io::Stdout>>,
+    player: Player,
+}
+
+impl ReplayApp {
+    pub fn new(player: Player) -> Result<ReplayApp, Error> {
+        enable_raw_mode()?;
+        let mut stdout = stdout();
+        execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
+
+        let backend = CrosstermBackend::new(stdout);
+        let terminal = Terminal::new(backend)?;
+        Ok(ReplayApp { terminal, player })
+    }
+
+    /// until q, Ctrl-C or SIGTERM; the terminal is restored either way
+    pub fn run(&mut self) -> Result<(), Error> {
+        let events = Events::start();
+        let result = self.show(&events);
+        drop(events);
+        result.and(restore(&mut self.terminal))
+    }
+
+    fn show(&mut self, events: &Events) -> Result<(), Error> {
+        let mut last_tick = Instant::now();
+
+        loop {
+            let owned = self
+                .player
+                .spectrum()
+                .as_ref()
+                .map(spectrum_bars)
+                .unwrap_or_default();
+            let bars: Vec<(&str, u64)> = owned
+                .iter()
+                .map(|(label, level)| (label.as_str(), *level))
+                .collect();
+            let player = &self.player;
+            self.terminal.draw(|f| {
+                let chunks = Layout::default()
+                    .direction(Direction::Vertical)
+                    .constraints([Constraint::Min(0), Constraint::Length(1)].as_ref())
+                    .split(f.size());
+                draw(f, chunks[0], &bars, "Rustalizer | replay");
+                draw_transport(f, chunks[1], player);
+            })?;
+
+            match events.next()? {
+                IEvent::Input(event) if quits(&event) => break,
+                IEvent::Input(event) => match event.code {
+                    KeyCode::Char(' ') => {
+                        let playing = self.player.playing();
+                        self.player.set_playing(!playing);
+                    }
+                    KeyCode::Left => {
+                        let position = self.player.position();
+                        self.player.seek(position - SEEK_STEP);
+                    }
+                    KeyCode::Right => {
+                        let position = self.player.position();
+                        self.player.seek(position + SEEK_STEP);
+                    }
+                    KeyCode::Up => {
+                        let speed = self.player.speed();
+                        self.player.set_speed(speed * 2.);
+                    }
+                    KeyCode::Down => {
+                        let speed = self.player.speed();
+                        self.player.set_speed(speed / 2.);
+                    }
+                    KeyCode::Home => self.player.seek(0.),
+                    _ => {}
+                },
+                IEvent::Failed(err) => return Err(err),
+                IEvent::Tick => {
+                    if shutdown::requested() {
+                        break;
+                    }
+                    self.player.advance(last_tick.elapsed());
+                    last_tick = Instant::now();
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn draw_transport<B>(f: &mut Frame<B>, area: Rect, player: &Player)
+where
+    B: tui::backend::Backend,
+{
+    let state = if player.playing() {
+        "\u{25b6}"
+    } else {
+        "\u{23f8}"
+    };
+    let text = format!(
+        " {} {:.1} / {:.1} s | x{} | space play/pause, \u{2190}/\u{2192} seek, \u{2191}/\u{2193} speed, Home restart",
+        state,
+        player.position(),
+        player.session().duration(),
+        player.speed()
+    );
+    f.render_widget(Paragraph::new(Spans::from(Span::raw(text))), area);
+}